@@ -9,8 +9,12 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use crate::SYSFS_DIR;
-use crate::{mmc, mock, nvme, partition::Partition, scsi, sysfs, virt};
+use crate::{DEVFS_DIR, SYSFS_DIR};
+use crate::{
+    mmc, mock, nvme,
+    partition::{Partition, udev_property},
+    scsi, sysfs, virt,
+};
 
 /// Represents the type of disk device.
 #[derive(Debug)]
@@ -42,6 +46,21 @@ impl Deref for Disk {
     }
 }
 
+impl Disk {
+    /// Mutable access to the BasicDisk of disks discovered through sysfs
+    ///
+    /// Mock disks are configured through their own builder methods instead.
+    pub(crate) fn sysfs_disk_mut(&mut self) -> Option<&mut BasicDisk> {
+        match self {
+            Disk::Mmc(disk) => Some(&mut disk.0),
+            Disk::Nvme(disk) => Some(&mut disk.0),
+            Disk::Scsi(disk) => Some(&mut disk.0),
+            Disk::Virtual(disk) => Some(&mut disk.0),
+            Disk::Mock(_) => None,
+        }
+    }
+}
+
 /// A basic disk representation containing common attributes shared by all disk types.
 /// This serves as the base structure that specific disk implementations build upon.
 #[derive(Debug, Default)]
@@ -58,6 +77,16 @@ pub struct BasicDisk {
     pub(crate) vendor: Option<String>,
     /// Partitions
     pub(crate) partitions: Vec<Partition>,
+    /// Logical block size in bytes, if known
+    pub(crate) logical_block_size: Option<u64>,
+    /// Whether the running system's root filesystem lives on this disk
+    pub(crate) is_system: bool,
+    /// World Wide Name of the disk, if known
+    pub(crate) wwn: Option<String>,
+    /// Whether the disk is spinning media
+    pub(crate) rotational: bool,
+    /// Whether the disk is removable media, e.g. a USB stick or card reader
+    pub(crate) removable: bool,
 }
 
 impl fmt::Display for Disk {
@@ -126,6 +155,118 @@ impl BasicDisk {
     pub fn vendor(&self) -> Option<&str> {
         self.vendor.as_deref()
     }
+
+    /// Returns the logical block size of the disk in bytes, if known.
+    pub fn logical_block_size(&self) -> Option<u64> {
+        self.logical_block_size
+    }
+
+    /// Returns true if the running system's root filesystem lives on this disk.
+    pub fn is_system_disk(&self) -> bool {
+        self.is_system
+    }
+
+    /// Returns whether the disk is spinning media.
+    pub fn is_rotational(&self) -> bool {
+        self.rotational
+    }
+
+    /// Returns whether the disk is removable media.
+    pub fn is_removable(&self) -> bool {
+        self.removable
+    }
+
+    /// Returns the World Wide Name of the disk, if known.
+    pub fn wwn(&self) -> Option<&str> {
+        self.wwn.as_deref()
+    }
+
+    /// Returns the stable `/dev/disk/by-id` path of the disk, derived from its WWN.
+    ///
+    /// Unlike the `/dev` node, this path survives reordering of devices between boots.
+    pub fn by_id_path(&self) -> Option<PathBuf> {
+        self.wwn()
+            .map(|wwn| PathBuf::from("/dev/disk/by-id").join(format!("wwn-{wwn}")))
+    }
+}
+
+/// Find the names of the disks backing the root filesystem of the running system
+///
+/// The root mount's device number is read from mountinfo, then resolved through
+/// any stacked devices (LVM, dm-crypt, md) down to the underlying disks. Roots on
+/// anonymous devices (btrfs subvolumes, overlayfs) report a `0:N` device number
+/// that sysfs doesn't know, so the mount source is resolved instead.
+pub(crate) fn root_disks(sysroot: &Path) -> Vec<String> {
+    let Ok(mountinfo) = fs::read_to_string(sysroot.join("proc/self/mountinfo")) else {
+        return vec![];
+    };
+    let Some(fields) = mountinfo
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+        .find(|fields| fields.get(4) == Some(&"/"))
+    else {
+        return vec![];
+    };
+
+    let sysfs_dir = sysroot.join(SYSFS_DIR);
+    let by_number = fields.get(2).and_then(|dev| {
+        fs::read_dir(&sysfs_dir).ok()?.filter_map(Result::ok).find_map(|e| {
+            let name = e.file_name().to_string_lossy().to_string();
+            (sysfs::read::<String>(&sysfs_dir.join(&name), "dev").as_deref() == Some(*dev)).then_some(name)
+        })
+    });
+    // The mount source follows the filesystem type after the " - " separator
+    let Some(name) = by_number.or_else(|| {
+        let source = fields.iter().skip_while(|f| **f != "-").nth(2)?;
+        source_device(sysroot, source)
+    }) else {
+        log::warn!("Unable to resolve the device backing the root filesystem");
+        return vec![];
+    };
+
+    let mut disks = vec![];
+    let mut pending = vec![name];
+    while let Some(name) = pending.pop() {
+        let node = sysfs_dir.join(&name);
+        let slaves = fs::read_dir(node.join("slaves"))
+            .map(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    .map(|e| e.file_name().to_string_lossy().to_string())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        if !slaves.is_empty() {
+            pending.extend(slaves);
+        } else if node.join("partition").exists() {
+            // Partitions live beneath their parent disk in the canonical sysfs tree
+            if let Some(parent) = fs::canonicalize(&node)
+                .ok()
+                .and_then(|p| Some(p.parent()?.file_name()?.to_string_lossy().to_string()))
+            {
+                disks.push(parent);
+            }
+        } else {
+            disks.push(name);
+        }
+    }
+
+    disks
+}
+
+/// Resolve a mount source such as `/dev/mapper/root` to its block device name in sysfs
+fn source_device(sysroot: &Path, source: &str) -> Option<String> {
+    let path = Path::new(source).strip_prefix("/").ok()?;
+    if !path.starts_with(DEVFS_DIR) {
+        return None;
+    }
+
+    // Follow symlinks, e.g. from /dev/mapper to the dm-N node
+    let path = sysroot.join(path);
+    let resolved = fs::canonicalize(&path).unwrap_or(path);
+    let name = resolved.file_name()?.to_string_lossy().to_string();
+    sysroot.join(SYSFS_DIR).join(&name).exists().then_some(name)
 }
 
 /// Trait for initializing different types of disk devices from sysfs.
@@ -172,6 +313,17 @@ impl DiskInit for BasicDisk {
         let vendor = sysfs::read(&node, "device/vendor");
         log::debug!("Vendor: {vendor:?}");
 
+        let logical_block_size = sysfs::read(&node, "queue/logical_block_size");
+        log::debug!("Logical block size: {logical_block_size:?}");
+
+        let wwn =
+            udev_property(sysroot, &node, "ID_WWN_WITH_EXTENSION").or_else(|| udev_property(sysroot, &node, "ID_WWN"));
+        log::debug!("WWN: {wwn:?}");
+
+        let rotational = sysfs::read::<u8>(&node, "queue/rotational") == Some(1);
+        let removable = sysfs::read::<u8>(&node, "removable") == Some(1);
+        log::debug!("Rotational: {rotational}, removable: {removable}");
+
         Some(Self {
             name: name.to_owned(),
             sectors,
@@ -179,6 +331,12 @@ impl DiskInit for BasicDisk {
             model,
             vendor,
             partitions,
+            logical_block_size,
+            // Resolved once for all disks during enumeration
+            is_system: false,
+            wwn,
+            rotational,
+            removable,
         })
     }
 }
@@ -5,6 +5,8 @@
 mod disk;
 mod sizing;
 pub use sizing::*;
+mod table;
+pub use table::*;
 
 use std::{
     fs, io,
@@ -24,6 +26,7 @@ pub mod virt;
 
 const SYSFS_DIR: &str = "sys/class/block";
 const DEVFS_DIR: &str = "dev";
+const UDEV_DATA_DIR: &str = "run/udev/data";
 
 /// A block device on the system which can be either a physical disk or a partition.
 #[derive(Debug)]
@@ -56,6 +59,76 @@ impl BlockDevice {
         self.sectors() * 512
     }
 
+    /// Returns the logical block size of the block device in bytes, if known.
+    pub fn logical_block_size(&self) -> Option<u64> {
+        match self {
+            BlockDevice::Disk(disk) => disk.logical_block_size(),
+            BlockDevice::Loopback(device) => device.disk().and_then(|d| d.logical_block_size()),
+        }
+    }
+
+    /// Returns true if the running system's root filesystem lives on this block device.
+    pub fn is_system_disk(&self) -> bool {
+        match self {
+            BlockDevice::Disk(disk) => disk.is_system_disk(),
+            BlockDevice::Loopback(device) => device.disk().is_some_and(|d| d.is_system_disk()),
+        }
+    }
+
+    /// Returns the model name of the block device, if known.
+    pub fn model(&self) -> Option<&str> {
+        match self {
+            BlockDevice::Disk(disk) => disk.model(),
+            BlockDevice::Loopback(device) => device.disk().and_then(|d| d.model()),
+        }
+    }
+
+    /// Returns whether the block device is spinning media.
+    pub fn is_rotational(&self) -> bool {
+        match self {
+            BlockDevice::Disk(disk) => disk.is_rotational(),
+            BlockDevice::Loopback(device) => device.disk().is_some_and(|d| d.is_rotational()),
+        }
+    }
+
+    /// Returns whether the block device is removable media.
+    pub fn is_removable(&self) -> bool {
+        match self {
+            BlockDevice::Disk(disk) => disk.is_removable(),
+            BlockDevice::Loopback(device) => device.disk().is_some_and(|d| d.is_removable()),
+        }
+    }
+
+    /// Returns the World Wide Name of the block device, if known.
+    pub fn wwn(&self) -> Option<&str> {
+        match self {
+            BlockDevice::Disk(disk) => disk.wwn(),
+            BlockDevice::Loopback(device) => device.disk().and_then(|d| d.wwn()),
+        }
+    }
+
+    /// Returns the stable `/dev/disk/by-id` path of the block device, if known.
+    pub fn by_id_path(&self) -> Option<PathBuf> {
+        match self {
+            BlockDevice::Disk(disk) => disk.by_id_path(),
+            BlockDevice::Loopback(device) => device.disk().and_then(|d| d.by_id_path()),
+        }
+    }
+
+    /// Detects the kind of partition table already present on the block device.
+    ///
+    /// This reads the first sectors of the device, so returns `None` for devices
+    /// that can't be read as well as for blank ones.
+    pub fn detect_table(&self) -> Option<PartitionTableKind> {
+        match self {
+            BlockDevice::Disk(disk) => match &**disk {
+                Disk::Mock(mock) => mock.header().and_then(PartitionTableKind::detect),
+                _ => PartitionTableKind::detect_at(disk.device_path()),
+            },
+            BlockDevice::Loopback(device) => PartitionTableKind::detect_at(device.device_path()),
+        }
+    }
+
     /// Returns the partitions on the block device.
     pub fn partitions(&self) -> &[Partition] {
         match self {
@@ -64,6 +137,16 @@ impl BlockDevice {
         }
     }
 
+    /// Returns the number of existing partitions on the block device.
+    pub fn partition_count(&self) -> usize {
+        self.partitions().len()
+    }
+
+    /// Returns true if the block device has any existing partitions.
+    pub fn has_partitions(&self) -> bool {
+        !self.partitions().is_empty()
+    }
+
     /// Returns the path to the partition with the given index.
     /// No attempt is made to verify the existence of the partition.
     pub fn partition_path(&self, index: usize) -> PathBuf {
@@ -106,9 +189,14 @@ impl BlockDevice {
     ///
     /// The block device or an IO error if creation fails.
     pub fn from_sysfs_path(sysfs_root: impl AsRef<Path>, name: impl AsRef<str>) -> io::Result<BlockDevice> {
-        let name = name.as_ref();
         let sysfs_dir = sysfs_root.as_ref();
+        let mut device = Self::probe(sysfs_dir, name.as_ref())?;
+        device.mark_system_disk(&disk::root_disks(sysfs_dir));
+        Ok(device)
+    }
 
+    /// Creates a BlockDevice from sysfs without checking whether it backs the running system
+    fn probe(sysfs_dir: &Path, name: &str) -> io::Result<BlockDevice> {
         if let Some(disk) = scsi::Disk::from_sysfs_path(sysfs_dir, name) {
             return Ok(BlockDevice::Disk(Box::new(Disk::Scsi(disk))));
         } else if let Some(disk) = nvme::Disk::from_sysfs_path(sysfs_dir, name) {
@@ -124,6 +212,18 @@ impl BlockDevice {
         Err(io::Error::new(io::ErrorKind::NotFound, "Device not found"))
     }
 
+    /// Flags the underlying disk as the system disk if it is one of `root_disks`
+    fn mark_system_disk(&mut self, root_disks: &[String]) {
+        let disk = match self {
+            BlockDevice::Disk(disk) => disk.sysfs_disk_mut(),
+            BlockDevice::Loopback(device) => device.disk_mut(),
+        };
+        if let Some(disk) = disk {
+            disk.is_system = root_disks.iter().any(|name| name == disk.name());
+            log::debug!("System disk {}: {}", disk.name(), disk.is_system);
+        }
+    }
+
     /// Returns the name of the block device.
     pub fn name(&self) -> &str {
         match self {
@@ -163,8 +263,10 @@ impl BlockDevice {
 
         // For all the discovered block devices, try to create a Disk instance
         // At this point we completely ignore partitions. They come later.
+        let root_disks = disk::root_disks(Path::new(sysroot));
         for entry in entries {
-            if let Ok(device) = BlockDevice::from_sysfs_path(sysroot, &entry) {
+            if let Ok(mut device) = BlockDevice::probe(Path::new(sysroot), &entry) {
+                device.mark_system_disk(&root_disks);
                 devices.push(device);
             }
         }
@@ -207,6 +309,94 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_system_disk_on_anonymous_root() {
+        let sysroot = std::env::temp_dir().join(format!("disks-system-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&sysroot);
+        let write = |path: &str, contents: &str| {
+            let path = sysroot.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        };
+
+        // A btrfs root reports an anonymous device number, so only its source can be resolved
+        write(
+            "proc/self/mountinfo",
+            "22 1 0:33 /@ / rw,relatime shared:1 - btrfs /dev/nvme0n1p2 rw,subvol=/@\n",
+        );
+        write("sys/devices/nvme0n1/dev", "259:0");
+        write("sys/devices/nvme0n1/size", "1000000");
+        write("sys/devices/nvme0n1/nvme0n1p2/dev", "259:2");
+        write("sys/devices/nvme0n1/nvme0n1p2/partition", "2");
+        write("sys/devices/nvme0n1/nvme0n1p2/start", "2048");
+        write("sys/devices/nvme0n1/nvme0n1p2/size", "4096");
+        write("sys/devices/sda/dev", "8:0");
+        write("sys/devices/sda/size", "1000000");
+        fs::create_dir_all(sysroot.join(SYSFS_DIR)).unwrap();
+        for (name, target) in [
+            ("nvme0n1", "../../devices/nvme0n1"),
+            ("nvme0n1p2", "../../devices/nvme0n1/nvme0n1p2"),
+            ("sda", "../../devices/sda"),
+        ] {
+            std::os::unix::fs::symlink(target, sysroot.join(SYSFS_DIR).join(name)).unwrap();
+        }
+
+        let devices = BlockDevice::discover_in_sysroot(sysroot.to_string_lossy()).unwrap();
+        let system = devices
+            .iter()
+            .map(|d| (d.name().to_owned(), d.is_system_disk()))
+            .collect::<Vec<_>>();
+        assert_eq!(system, [("nvme0n1".to_owned(), true), ("sda".to_owned(), false)]);
+
+        fs::remove_dir_all(&sysroot).unwrap();
+    }
+
+    #[test]
+    fn test_partition_count() {
+        const MB: u64 = 1024 * 1024;
+        const GB: u64 = 1024 * MB;
+
+        let device = BlockDevice::mock_device(mock::MockDisk::new(500 * GB));
+        assert_eq!(device.partition_count(), 0);
+        assert!(!device.has_partitions());
+
+        let mut disk = mock::MockDisk::new(500 * GB);
+        disk.add_partition(0, 100 * MB);
+        disk.add_partition(100 * MB, 116 * MB);
+        disk.add_partition(116 * MB, 200 * GB + 116 * MB);
+        disk.add_partition(200 * GB + 116 * MB, 200 * GB + 616 * MB);
+        let device = BlockDevice::mock_device(disk);
+        assert_eq!(device.partition_count(), 4);
+        assert!(device.has_partitions());
+    }
+
+    #[test]
+    fn test_detect_table() {
+        let blank = BlockDevice::mock_device(mock::MockDisk::new(1024 * 1024));
+        assert_eq!(blank.detect_table(), None);
+
+        // Protective MBR followed by a GPT header at LBA 1
+        let mut header = vec![0u8; 1024];
+        header[446 + 4] = 0xEE;
+        header[510..512].copy_from_slice(&[0x55, 0xAA]);
+        header[512..520].copy_from_slice(b"EFI PART");
+        let gpt = BlockDevice::mock_device(mock::MockDisk::new(1024 * 1024).with_header(header.clone()));
+        assert_eq!(gpt.detect_table(), Some(PartitionTableKind::Gpt));
+
+        // A damaged GPT header is still evidenced by the protective MBR
+        header[512..520].fill(0);
+        assert_eq!(PartitionTableKind::detect(&header), Some(PartitionTableKind::Gpt));
+
+        // A plain DOS partition table
+        header[446 + 4] = 0x83;
+        assert_eq!(PartitionTableKind::detect(&header), Some(PartitionTableKind::Mbr));
+
+        // GPT on a 4Kn device has its header at byte 4096
+        let mut header = vec![0u8; PartitionTableKind::HEADER_SIZE];
+        header[4096..4104].copy_from_slice(b"EFI PART");
+        assert_eq!(PartitionTableKind::detect(&header), Some(PartitionTableKind::Gpt));
+    }
+
     #[test]
     fn test_partition_paths() {
         // Create a mock SCSI disk
@@ -78,6 +78,11 @@ impl Device {
         self.file.as_deref()
     }
 
+    /// Returns the disk device mutably, if the loop device is backed by a disk.
+    pub(crate) fn disk_mut(&mut self) -> Option<&mut BasicDisk> {
+        self.disk.as_mut()
+    }
+
     /// Returns the disk device if the loop device is backed by a disk.
     pub fn disk(&self) -> Option<&BasicDisk> {
         self.disk.as_ref()
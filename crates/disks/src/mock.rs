@@ -18,6 +18,8 @@ use crate::{BasicDisk, partition::Partition};
 pub struct MockDisk {
     basic_disk: BasicDisk,
     pub parts_prefix: bool,
+    /// Contents of the first sectors of the mock disk, if seeded
+    header: Option<Vec<u8>>,
 }
 
 impl Deref for MockDisk {
@@ -43,16 +45,71 @@ impl MockDisk {
             model: Some("Mock Device".to_string()),
             vendor: Some("Mock Vendor".to_string()),
             partitions: Vec::new(),
+            logical_block_size: None,
+            is_system: false,
+            wwn: None,
+            rotational: false,
+            removable: false,
         };
 
         Self {
             basic_disk: disk,
             parts_prefix,
+            header: None,
         }
     }
 
+    /// Flag the mock disk as backing the running system
+    pub fn with_system_disk(mut self, is_system: bool) -> Self {
+        self.basic_disk.is_system = is_system;
+        self
+    }
+
+    /// Set the World Wide Name reported by the mock disk
+    pub fn with_wwn(mut self, wwn: &str) -> Self {
+        self.basic_disk.wwn = Some(wwn.to_string());
+        self
+    }
+
+    /// Set the model name reported by the mock disk
+    pub fn with_model(mut self, model: &str) -> Self {
+        self.basic_disk.model = Some(model.to_string());
+        self
+    }
+
+    /// Flag the mock disk as spinning media
+    pub fn with_rotational(mut self, rotational: bool) -> Self {
+        self.basic_disk.rotational = rotational;
+        self
+    }
+
+    /// Flag the mock disk as removable media
+    pub fn with_removable(mut self, removable: bool) -> Self {
+        self.basic_disk.removable = removable;
+        self
+    }
+
+    /// Set the logical block size reported by the mock disk
+    pub fn with_logical_block_size(mut self, size: u64) -> Self {
+        self.basic_disk.logical_block_size = Some(size);
+        self
+    }
+
+    /// Seed the first sectors of the mock disk, e.g. with a partition table signature
+    pub fn with_header(mut self, header: Vec<u8>) -> Self {
+        self.header = Some(header);
+        self
+    }
+
+    /// The seeded contents of the first sectors of the mock disk
+    pub fn header(&self) -> Option<&[u8]> {
+        self.header.as_deref()
+    }
+
     /// Add a partition to the mock disk at the specified byte offsets
-    pub fn add_partition(&mut self, start_bytes: u64, end_bytes: u64) {
+    ///
+    /// Returns the new partition so that tests may further customise it.
+    pub fn add_partition(&mut self, start_bytes: u64, end_bytes: u64) -> &mut Partition {
         let partition_number = self.basic_disk.partitions().len() + 1;
         let start = start_bytes / 512;
         let end = end_bytes / 512;
@@ -65,8 +122,13 @@ impl MockDisk {
             name: format!("mock0p{partition_number}"),
             node: PathBuf::from("/sys/class/block/mock0/mock0p1"),
             device: PathBuf::from(format!("/dev/mock0p{partition_number}")),
+            type_guid: None,
+            uuid: None,
+            label: None,
         };
 
-        self.basic_disk.partitions_mut().push(partition);
+        let partitions = self.basic_disk.partitions_mut();
+        partitions.push(partition);
+        partitions.last_mut().expect("partition was just added")
     }
 }
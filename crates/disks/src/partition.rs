@@ -3,9 +3,10 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use std::fmt;
+use std::fs;
 use std::path::{Path, PathBuf};
 
-use crate::{DEVFS_DIR, SYSFS_DIR, sysfs};
+use crate::{DEVFS_DIR, SYSFS_DIR, UDEV_DATA_DIR, sysfs};
 
 /// Represents a partition on a disk device
 /// - Size in sectors
@@ -25,6 +26,12 @@ pub struct Partition {
     pub node: PathBuf,
     /// Path to the partition device in /dev
     pub device: PathBuf,
+    /// Partition type GUID, if known
+    pub type_guid: Option<String>,
+    /// Unique partition GUID (PARTUUID), if known
+    pub uuid: Option<String>,
+    /// GPT partition name (PARTLABEL), if known
+    pub label: Option<String>,
 }
 
 impl fmt::Display for Partition {
@@ -39,6 +46,19 @@ impl fmt::Display for Partition {
 }
 
 impl Partition {
+    /// Returns the byte offset of the start of the partition.
+    ///
+    /// The kernel reports partition geometry in 512-byte units whatever the
+    /// logical block size of the device, so this holds for 4Kn disks too.
+    pub fn start_bytes(&self) -> u64 {
+        self.start * 512
+    }
+
+    /// Returns the byte offset of the end of the partition, exclusive.
+    pub fn end_bytes(&self) -> u64 {
+        self.end * 512
+    }
+
     /// Creates a new Partition instance from a sysfs path and partition name.
     ///
     /// # Arguments
@@ -53,6 +73,9 @@ impl Partition {
         let partition_no: u32 = sysfs::read(&node, "partition")?;
         let start = sysfs::read(&node, "start")?;
         let size = sysfs::read(&node, "size")?;
+        let type_guid = udev_property(sysroot, &node, "ID_PART_ENTRY_TYPE");
+        let uuid = udev_property(sysroot, &node, "ID_PART_ENTRY_UUID");
+        let label = udev_property(sysroot, &node, "ID_PART_ENTRY_NAME").map(|name| udev_unescape(&name));
         Some(Self {
             name: name.to_owned(),
             number: partition_no,
@@ -61,6 +84,50 @@ impl Partition {
             end: start + size,
             node,
             device: sysroot.join(DEVFS_DIR).join(name),
+            type_guid,
+            uuid,
+            label,
         })
     }
 }
+
+/// Decode the `\xNN` escapes udev uses for unsafe characters in property values
+fn udev_unescape(value: &str) -> String {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .strip_prefix(b"x")
+            .and_then(|hex| std::str::from_utf8(hex.get(..2)?).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(decoded) if byte == b'\\' => {
+                bytes.push(decoded);
+                rest = &tail[3..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Reads a property for a block device node from the udev database
+///
+/// # Arguments
+/// * `sysroot` - Base path to sysfs
+/// * `node` - Path to the device node in sysfs
+/// * `key` - Name of the udev property (e.g. `ID_PART_ENTRY_TYPE`)
+///
+/// # Returns
+/// * `Some(String)` if udev has recorded the property
+/// * `None` if the database or the property is unavailable
+pub(crate) fn udev_property(sysroot: &Path, node: &Path, key: &str) -> Option<String> {
+    let dev: String = sysfs::read(node, "dev")?;
+    let data = fs::read_to_string(sysroot.join(UDEV_DATA_DIR).join(format!("b{dev}"))).ok()?;
+    data.lines()
+        .find_map(|line| line.strip_prefix("E:")?.strip_prefix(key)?.strip_prefix('='))
+        .map(str::to_owned)
+}
@@ -49,12 +49,51 @@ pub fn format_position(pos: u64, total: u64) -> String {
     format!("{}% ({})", (pos as f64 / total as f64 * 100.0) as u64, format_size(pos))
 }
 
+/// Format a disk position relative to the end of the disk
+///
+/// This is useful for describing partitions allocated from the tail of a disk.
+///
+/// # Examples
+///
+/// ```
+/// use disks::format_position_from_end;
+/// const GIB: u64 = 1024 * 1024 * 1024;
+/// assert_eq!(format_position_from_end(498 * GIB, 500 * GIB), "500.0GiB - 2.0GiB");
+/// assert_eq!(format_position_from_end(500 * GIB, 500 * GIB), "500.0GiB - 0B");
+/// ```
+pub fn format_position_from_end(pos: u64, total: u64) -> String {
+    format!("{} - {}", format_size(total), format_size(total.saturating_sub(pos)))
+}
+
+/// Scale a size by the fraction `numerator / denominator`, rounding down
+///
+/// The multiplication is carried out in `u128` so that large disks combined with
+/// percentages or weights cannot overflow before the division. Results that do not
+/// fit back into a `u64` saturate at `u64::MAX`.
+///
+/// # Examples
+///
+/// ```
+/// use disks::scale_size;
+/// assert_eq!(scale_size(1000, 80, 100), 800);
+/// assert_eq!(scale_size(u64::MAX, 3, 4), u64::MAX / 4 * 3 + 2);
+/// ```
+pub fn scale_size(size: u64, numerator: u64, denominator: u64) -> u64 {
+    let scaled = u128::from(size) * u128::from(numerator) / u128::from(denominator);
+    u64::try_from(scaled).unwrap_or(u64::MAX)
+}
+
 /// Check if a value is already aligned to the given boundary
+///
+/// The alignment may be any non-zero value, not just a power of two.
 pub fn is_aligned(value: u64, alignment: u64) -> bool {
     value % alignment == 0
 }
 
 /// Align up to the nearest multiple of alignment, unless already aligned
+///
+/// Uses division rather than bit masking, so non-power-of-two alignments
+/// such as RAID stripe widths (e.g. 3 * 64KiB) are handled correctly.
 pub fn align_up(value: u64, alignment: u64) -> u64 {
     match value % alignment {
         0 => value,
@@ -0,0 +1,82 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Detection of existing partition tables from the first sectors of a device.
+
+use std::{fmt, fs, io::Read, path::Path};
+
+/// Offset of the `0x55AA` boot signature within the MBR
+const MBR_SIGNATURE_OFFSET: usize = 510;
+
+/// Offset of the partition type of the first MBR partition entry
+const MBR_FIRST_TYPE_OFFSET: usize = 446 + 4;
+
+/// Partition type of the protective MBR entry covering a GPT disk
+const MBR_PROTECTIVE_TYPE: u8 = 0xEE;
+
+/// Signature at the start of the GPT header
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+
+/// Logical block sizes at which the GPT header is searched for
+const BLOCK_SIZES: [usize; 2] = [512, 4096];
+
+/// The kind of partition table found on a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionTableKind {
+    /// A GUID Partition Table, possibly only evidenced by its protective MBR
+    Gpt,
+    /// A legacy MBR (DOS) partition table
+    Mbr,
+}
+
+impl fmt::Display for PartitionTableKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Gpt => f.write_str("gpt"),
+            Self::Mbr => f.write_str("mbr"),
+        }
+    }
+}
+
+impl PartitionTableKind {
+    /// Number of bytes from the start of a device needed to detect any table
+    pub const HEADER_SIZE: usize = 4096 + 512;
+
+    /// Detect the partition table from the first bytes of a device
+    ///
+    /// A GPT header at LBA 1 is recognised for 512 and 4096 byte blocks, as is
+    /// a protective MBR whose GPT header is damaged. Any other MBR carrying the
+    /// boot signature is reported as [`PartitionTableKind::Mbr`].
+    pub fn detect(header: &[u8]) -> Option<Self> {
+        let has_gpt_header = BLOCK_SIZES
+            .iter()
+            .any(|&block| header.get(block..block + GPT_SIGNATURE.len()) == Some(GPT_SIGNATURE));
+        if has_gpt_header {
+            return Some(Self::Gpt);
+        }
+
+        if header.get(MBR_SIGNATURE_OFFSET..MBR_SIGNATURE_OFFSET + 2) != Some(&[0x55, 0xAA]) {
+            return None;
+        }
+        if header.get(MBR_FIRST_TYPE_OFFSET) == Some(&MBR_PROTECTIVE_TYPE) {
+            Some(Self::Gpt)
+        } else {
+            Some(Self::Mbr)
+        }
+    }
+
+    /// Detect the partition table of the device at the given path
+    ///
+    /// Devices that can't be read, e.g. without sufficient permissions, are
+    /// reported as having no table.
+    pub fn detect_at(path: &Path) -> Option<Self> {
+        let mut header = Vec::with_capacity(Self::HEADER_SIZE);
+        fs::File::open(path)
+            .ok()?
+            .take(Self::HEADER_SIZE as u64)
+            .read_to_end(&mut header)
+            .ok()?;
+        Self::detect(&header)
+    }
+}
@@ -8,13 +8,22 @@ use uuid::Uuid;
 
 /// Represents the table attributes of a GPT partition
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GptAttributes {
     /// The type GUID that identifies the partition type
+    #[cfg_attr(feature = "serde", serde(with = "type_guid"))]
     pub type_guid: partition_types::Type,
     /// Optional name for the partition
     pub name: Option<String>,
     /// Optional UUID for the partition
     pub uuid: Option<Uuid>,
+    /// Partition attribute flags
+    pub flags: u64,
+}
+
+impl GptAttributes {
+    /// Attribute flag marking the partition as bootable by legacy BIOS firmware
+    pub const LEGACY_BIOS_BOOTABLE: u64 = 1 << 2;
 }
 
 impl Default for GptAttributes {
@@ -23,12 +32,14 @@ impl Default for GptAttributes {
             type_guid: partition_types::BASIC,
             name: None,
             uuid: None,
+            flags: 0,
         }
     }
 }
 
 /// Represents attributes specific to different partition table types
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TableAttributes {
     /// GPT partition attributes
     Gpt(GptAttributes),
@@ -50,8 +61,42 @@ impl TableAttributes {
 
 /// Represents the attributes of a partition
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PartitionAttributes {
     pub table: TableAttributes,
     pub role: Option<PartitionRole>,
     pub filesystem: Option<Filesystem>,
 }
+
+impl PartitionAttributes {
+    /// Whether these attributes describe an EFI system partition, by role or type
+    pub fn is_esp(&self) -> bool {
+        self.role == Some(PartitionRole::Boot)
+            || self
+                .table
+                .as_gpt()
+                .is_some_and(|gpt| gpt.type_guid.guid == partition_types::EFI.guid)
+    }
+}
+
+/// Partition types are stored by their GUID alone, recovering well-known types on load
+#[cfg(feature = "serde")]
+mod type_guid {
+    use gpt::partition_types::Type;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use uuid::Uuid;
+
+    pub(super) fn serialize<S>(value: &Type, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        value.guid.serialize(serializer)
+    }
+
+    pub(super) fn deserialize<'de, D>(deserializer: D) -> Result<Type, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Uuid::deserialize(deserializer).map(Type::from)
+    }
+}
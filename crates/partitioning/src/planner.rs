@@ -14,16 +14,19 @@
 
 use disks::{BlockDevice, align_down, align_up, format_position, format_size, is_aligned};
 use log::{debug, warn};
-use std::collections::VecDeque;
+use std::collections::{BTreeSet, VecDeque};
 use thiserror::Error;
+use types::PartitionRole;
+use uuid::Uuid;
 
-use crate::PartitionAttributes;
+use crate::{GptAttributes, PartitionAttributes, TableAttributes};
 
 /// Errors that can occur while planning partition changes
 ///
 /// These errors help prevent invalid partition layouts by catching problems
 /// early in the planning phase.
 #[derive(Debug, Error)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PlanError {
     #[error("Region {start}..{end} overlaps with existing partition")]
     RegionOverlap { start: u64, end: u64 },
@@ -31,6 +34,65 @@ pub enum PlanError {
     RegionOutOfBounds { start: u64, end: u64 },
     #[error("No free regions available")]
     NoFreeRegions,
+    #[error("Region {start}..{end} shares a {block_size} byte block with an adjacent partition")]
+    SharedBlock { start: u64, end: u64, block_size: u64 },
+    #[error("Partition at index {index} is marked to be kept")]
+    PartitionKept { index: usize },
+    #[error("Cannot shrink partition at index {index} to {requested} bytes, at least {minimum} bytes are required")]
+    ShrinkBelowUsed { index: usize, requested: u64, minimum: u64 },
+    #[error("Region {start}..{end} intersects a bad region of the disk")]
+    BadRegion { start: u64, end: u64 },
+    #[error("Region {start}..{end} intersects space reserved for growth")]
+    RegionReserved { start: u64, end: u64 },
+    #[error("Layout leaves a gap of free space at {start}..{end}")]
+    Gap { start: u64, end: u64 },
+    #[error("Change refers to partition index {index}, which no longer exists")]
+    StaleChange { index: usize },
+    #[error("Partition number {number} is invalid or already in use")]
+    PartitionNumberTaken { number: u32 },
+    #[error("Offset {offset} is not aligned to the {sector_size} byte sector size")]
+    NotSectorAligned { offset: u64, sector_size: u64 },
+    #[error("Alignment of {alignment} bytes is not a multiple of the {sector_size} byte sector size")]
+    InvalidAlignment { alignment: u64, sector_size: u64 },
+    #[error("Percentage {percent}% is not between 1% and 100%")]
+    InvalidPercentage { percent: u32 },
+    #[error("EFI system partition would start at {start}, beyond the firmware limit of {limit}")]
+    EspTooFar { start: u64, limit: u64 },
+}
+
+/// Errors that can occur converting a region from bytes to sectors
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SectorError {
+    #[error("Block size must be non-zero")]
+    InvalidBlockSize,
+    #[error("Offset {offset} is not a multiple of the {block_size} byte block size")]
+    Misaligned { offset: u64, block_size: u64 },
+}
+
+impl PlanError {
+    /// A stable, machine readable code identifying the kind of error
+    ///
+    /// Unlike the display text, these codes never change and are suitable for
+    /// matching in frontends or looking up localized messages.
+    pub fn code(&self) -> &'static str {
+        match self {
+            PlanError::RegionOverlap { .. } => "region_overlap",
+            PlanError::RegionOutOfBounds { .. } => "out_of_bounds",
+            PlanError::NoFreeRegions => "no_free_regions",
+            PlanError::SharedBlock { .. } => "shared_block",
+            PlanError::PartitionKept { .. } => "partition_kept",
+            PlanError::ShrinkBelowUsed { .. } => "shrink_below_used",
+            PlanError::BadRegion { .. } => "bad_region",
+            PlanError::RegionReserved { .. } => "region_reserved",
+            PlanError::Gap { .. } => "gap",
+            PlanError::StaleChange { .. } => "stale_change",
+            PlanError::PartitionNumberTaken { .. } => "partition_number_taken",
+            PlanError::NotSectorAligned { .. } => "not_sector_aligned",
+            PlanError::InvalidAlignment { .. } => "invalid_alignment",
+            PlanError::InvalidPercentage { .. } => "invalid_percentage",
+            PlanError::EspTooFar { .. } => "esp_too_far",
+        }
+    }
 }
 
 /// A planned modification to the disk's partition layout
@@ -39,6 +101,7 @@ pub enum PlanError {
 /// Each change is validated when added to ensure it won't create an invalid
 /// disk layout.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Change {
     /// Add a new partition
     AddPartition {
@@ -49,8 +112,35 @@ pub enum Change {
     },
     /// Delete an existing partition
     DeletePartition { original_index: usize, partition_id: u32 },
+    /// Replace the attributes of an existing partition without recreating it
+    SetAttributes {
+        original_index: usize,
+        partition_id: u32,
+        attributes: PartitionAttributes,
+    },
+    /// Resize an existing partition in place
+    ResizePartition {
+        original_index: usize,
+        partition_id: u32,
+        start: u64,
+        end: u64,
+    },
+    /// Write a fresh partition table, discarding the original partitions
+    ///
+    /// The discarded layout is recorded so that the initialization can be undone.
+    InitializeDisk {
+        original_regions: Vec<Region>,
+        original_partition_ids: Vec<u32>,
+        next_partition_id: u32,
+    },
 }
 
+/// Free space that must remain on a filesystem beyond its used data when shrinking
+pub const SHRINK_HEADROOM: u64 = 1024 * 1024 * 1024;
+
+/// Smallest size a partition may be resized to
+pub const MIN_PARTITION_SIZE: u64 = 1024 * 1024;
+
 /// A disk partitioning planner.
 #[derive(Debug, Clone)]
 pub struct Planner {
@@ -58,18 +148,84 @@ pub struct Planner {
     usable_start: u64,
     /// Last usable LBA position on disk in bytes
     usable_end: u64,
+    /// Total size of the device in bytes
+    device_size: u64,
     /// Stack of changes that can be undone
     changes: VecDeque<Change>,
+    /// Stack of undone changes that can be reapplied
+    redo_stack: Vec<Change>,
     /// Original partition layout for reference
     original_regions: Vec<Region>,
     /// Track original partition IDs
     original_partition_ids: Vec<u32>,
+    /// Indices of original partitions that must be preserved
+    kept_indices: BTreeSet<usize>,
     /// Next available partition ID for new partitions
     next_partition_id: u32,
+    /// Partition IDs set aside for explicitly numbered partitions
+    reserved_partition_ids: BTreeSet<u32>,
+    /// Logical block size of the device in bytes, if known
+    block_size: Option<u64>,
+    /// Default alignment of partition boundaries in bytes
+    alignment: u64,
+    /// Known bad regions that must not be allocated
+    bad_regions: Vec<Region>,
+    /// Regions reserved for future growth of a partition
+    reserved_regions: Vec<Region>,
+    /// Offset before which no new partition may be placed
+    first_partition_offset: u64,
+    /// Whether free space between partitions is disallowed
+    no_gaps: bool,
+    /// Whether free space after the last partition is permitted when gaps are disallowed
+    allow_trailing_free: bool,
+    /// Offset beyond which an EFI system partition may not start
+    esp_max_start: Option<u64>,
+
+    wipe_disk: bool,
+}
 
+/// A checkpoint of the planned state of a [`Planner`]
+///
+/// Created by [`Planner::snapshot()`] and reinstated with [`Planner::restore()`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PlannerState {
+    usable_start: u64,
+    usable_end: u64,
+    changes: VecDeque<Change>,
+    original_regions: Vec<Region>,
+    original_partition_ids: Vec<u32>,
+    kept_indices: BTreeSet<usize>,
+    next_partition_id: u32,
     wipe_disk: bool,
 }
 
+/// A read-only view of a [`Planner`] with a hypothetical change applied
+///
+/// Created by [`Planner::with_overlay()`].
+#[derive(Debug)]
+pub struct PlannerView<'a> {
+    planner: &'a Planner,
+    overlay: Change,
+}
+
+impl PlannerView<'_> {
+    /// The layout of the planner after its pending changes and the overlaid change
+    pub fn current_layout(&self) -> Vec<Region> {
+        self.planner.layout_with(Some(&self.overlay))
+    }
+
+    /// The free space left in the overlaid layout, see [`Planner::free_regions()`]
+    pub fn free_regions(&self) -> Vec<Region> {
+        self.planner.free_regions_in(self.current_layout())
+    }
+
+    /// The hypothetical change applied by this view
+    pub fn overlay(&self) -> &Change {
+        &self.overlay
+    }
+}
+
 /// A contiguous region of disk space defined by absolute start and end positions
 ///
 /// Used to represent both existing partitions and planned partition changes.
@@ -83,6 +239,7 @@ pub struct Planner {
 /// assert_eq!(region.size(), 1024 * 1024);
 /// ```
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Region {
     /// The absolute start position of this region in bytes
     pub start: u64,
@@ -100,6 +257,58 @@ pub struct Region {
 /// performance and compatibility.
 pub const PARTITION_ALIGNMENT: u64 = 1024 * 1024;
 
+/// Size in bytes of a single GPT partition entry
+pub const GPT_ENTRY_SIZE: u64 = 128;
+
+/// Number of partition entries in a standard GPT
+pub const DEFAULT_GPT_ENTRIES: u32 = 128;
+
+/// Space taken at the end of the disk by the backup GPT, in bytes
+///
+/// This is the partition entry array, rounded up to whole blocks, followed by
+/// the one block backup header.
+pub fn gpt_backup_size(entries: u32, block_size: u64) -> u64 {
+    (u64::from(entries) * GPT_ENTRY_SIZE).div_ceil(block_size) * block_size + block_size
+}
+
+/// The end of the last whole logical block of a device
+///
+/// Partitions can only end on a whole block, even if the device doesn't.
+fn device_end(device: &BlockDevice) -> u64 {
+    let block_size = device.logical_block_size().unwrap_or(512);
+    device.size() / block_size * block_size
+}
+
+/// The kind of a segment within a [`Planner::layout_map()`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentKind {
+    /// A partition in the current layout
+    Partition,
+    /// Unallocated space
+    Free,
+    /// Unallocated space marked as bad, which will never be allocated
+    Bad,
+    /// Unallocated space reserved for a partition to grow into
+    Reserved,
+}
+
+/// A segment of the usable disk area within a [`Planner::layout_map()`]
+#[derive(Debug, Clone)]
+pub struct LayoutSegment {
+    pub region: Region,
+    pub kind: SegmentKind,
+    /// Whether a new partition could be placed in this segment. Free slivers
+    /// too small to hold an aligned partition, and all other kinds, are unusable.
+    pub usable: bool,
+}
+
+impl LayoutSegment {
+    fn new(region: Region, kind: SegmentKind, alignment: u64) -> Self {
+        let usable = kind == SegmentKind::Free && region.end / alignment > region.start.div_ceil(alignment);
+        Self { region, kind, usable }
+    }
+}
+
 /// Represents a contiguous region on disk between two absolute positions.
 /// Both start and end are absolute positions in bytes from the beginning of the disk.
 /// For example, a 1MB partition starting at the beginning of the disk would have
@@ -120,11 +329,124 @@ impl Region {
         self.end - self.start
     }
 
+    /// Get the unique partition GUID (PARTUUID) of this region, if known
+    pub fn partuuid(&self) -> Option<Uuid> {
+        self.attributes.as_ref()?.table.as_gpt()?.uuid
+    }
+
+    /// Check if this region covers exactly the same bytes as another
+    ///
+    /// Attributes and partition IDs are ignored, so planned and actual layouts
+    /// can be compared by their geometry alone.
+    pub fn same_extent(&self, other: &Region) -> bool {
+        self.start == other.start && self.end == other.end
+    }
+
     /// Check if this region overlaps with another
     pub fn overlaps_with(&self, other: &Region) -> bool {
         self.start < other.end && other.start < self.end
     }
 
+    /// Check if a position lies within this region
+    ///
+    /// Regions are half-open, so `start` is inside the region while `end` is the
+    /// first position past it.
+    pub fn contains_position(&self, pos: u64) -> bool {
+        self.start <= pos && pos < self.end
+    }
+
+    /// Check if another region lies entirely within this one
+    ///
+    /// As both regions are half-open, `other` may end exactly at `end`.
+    pub fn contains_region(&self, other: &Region) -> bool {
+        self.start <= other.start && other.end <= self.end
+    }
+
+    /// Split this region into `n` equal sub-regions aligned to `alignment`
+    ///
+    /// The boundaries are moved inward to the alignment, and any remainder that
+    /// doesn't divide evenly goes to the last sub-region. Returns nothing if the
+    /// region is too small to hold `n` aligned pieces.
+    pub fn divide(&self, n: usize, alignment: u64) -> Vec<Region> {
+        let start = self.start.div_ceil(alignment) * alignment;
+        let end = self.end / alignment * alignment;
+        if n == 0 || end <= start {
+            return vec![];
+        }
+        let piece = (end - start) / n as u64 / alignment * alignment;
+        if piece == 0 {
+            return vec![];
+        }
+        (0..n as u64)
+            .map(|i| {
+                let piece_start = start + i * piece;
+                let piece_end = if i + 1 == n as u64 { end } else { piece_start + piece };
+                Region::new(piece_start, piece_end)
+            })
+            .collect()
+    }
+
+    /// The span shared by this region and another, if they overlap
+    pub fn intersection(&self, other: &Region) -> Option<Region> {
+        self.overlaps_with(other)
+            .then(|| Region::new(self.start.max(other.start), self.end.min(other.end)))
+    }
+
+    /// Clamp this region into the usable range `start..end`
+    ///
+    /// The partition ID and attributes are kept. Returns `None` if the region lies
+    /// entirely outside the range.
+    pub fn clamp_to(&self, start: u64, end: u64) -> Option<Region> {
+        let start = self.start.max(start);
+        let end = self.end.min(end);
+        (start < end).then(|| Region {
+            start,
+            end,
+            ..self.clone()
+        })
+    }
+
+    /// Number of bytes between this region and a non-overlapping neighbour
+    ///
+    /// Touching regions have a gap of zero, while overlapping regions have no gap at all.
+    pub fn gap_to(&self, other: &Region) -> Option<u64> {
+        if self.overlaps_with(other) {
+            return None;
+        }
+        Some(self.start.max(other.start) - self.end.min(other.end))
+    }
+
+    /// Check if this region and a non-overlapping neighbour touch the same block
+    ///
+    /// Two regions may be byte-adjacent yet still land within the same logical block
+    /// when their shared boundary isn't block aligned, which the hardware can't represent.
+    pub fn shares_block_with(&self, other: &Region, block_size: u64) -> bool {
+        if self.overlaps_with(other) {
+            return false;
+        }
+        let (first, second) = if self.start <= other.start {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        (first.end - 1) / block_size == second.start / block_size
+    }
+
+    /// Convert this region to start and (exclusive) end LBAs for the given block size
+    ///
+    /// Fails if either boundary doesn't fall exactly on a block boundary.
+    pub fn to_sectors(&self, block_size: u64) -> Result<(u64, u64), SectorError> {
+        if block_size == 0 {
+            return Err(SectorError::InvalidBlockSize);
+        }
+        for offset in [self.start, self.end] {
+            if offset % block_size != 0 {
+                return Err(SectorError::Misaligned { offset, block_size });
+            }
+        }
+        Ok((self.start / block_size, self.end / block_size))
+    }
+
     /// Get a human readable description of this region
     pub fn describe(&self, disk_size: u64) -> String {
         format!(
@@ -144,12 +466,18 @@ impl Change {
                 start,
                 end,
                 partition_id,
-                ..
+                attributes,
             } => {
+                let filesystem = attributes
+                    .as_ref()
+                    .and_then(|a| a.filesystem.as_ref())
+                    .map(|fs| format!(" {fs}"))
+                    .unwrap_or_default();
                 format!(
-                    "Add new partition #{}: {} ({} at {})",
+                    "Add new partition #{}: {}{} ({} at {})",
                     partition_id,
                     format_size(end - start),
+                    filesystem,
                     Region::new(*start, *end).describe(disk_size),
                     format_position(*start, disk_size)
                 )
@@ -160,8 +488,45 @@ impl Change {
             } => {
                 format!("Delete partition #{} (index {})", partition_id, original_index + 1)
             }
+            Change::SetAttributes {
+                original_index,
+                partition_id,
+                ..
+            } => {
+                format!(
+                    "Update attributes of partition #{} (index {})",
+                    partition_id,
+                    original_index + 1
+                )
+            }
+            Change::ResizePartition {
+                start,
+                end,
+                partition_id,
+                ..
+            } => {
+                format!(
+                    "Resize partition #{} to {}",
+                    partition_id,
+                    Region::new(*start, *end).describe(disk_size)
+                )
+            }
+            Change::InitializeDisk { original_regions, .. } => {
+                format!(
+                    "Create new partition table, discarding {} partitions",
+                    original_regions.len()
+                )
+            }
         }
     }
+
+    /// Whether applying this change destroys existing data
+    ///
+    /// Resizes aren't judged here, as whether they shrink the partition depends on
+    /// its original extent. See [`Planner::has_destructive_changes`].
+    pub fn is_destructive(&self) -> bool {
+        matches!(self, Change::DeletePartition { .. } | Change::InitializeDisk { .. })
+    }
 }
 
 impl Planner {
@@ -175,20 +540,50 @@ impl Planner {
         let mut max_id = 0u32;
 
         for part in device.partitions() {
-            let mut region = Region::new(part.start, part.end);
+            let mut region = Region::new(part.start_bytes(), part.end_bytes());
             region.partition_id = Some(part.number);
+            let type_guid = part.type_guid.as_deref().and_then(|guid| Uuid::parse_str(guid).ok());
+            let uuid = part.uuid.as_deref().and_then(|uuid| Uuid::parse_str(uuid).ok());
+            if type_guid.is_some() || uuid.is_some() || part.label.is_some() {
+                region.attributes = Some(PartitionAttributes {
+                    table: TableAttributes::Gpt(GptAttributes {
+                        type_guid: type_guid.map_or(GptAttributes::default().type_guid, Into::into),
+                        name: part.label.clone(),
+                        uuid,
+                        ..Default::default()
+                    }),
+                    role: type_guid.as_ref().and_then(PartitionRole::from_type_guid),
+                    filesystem: None,
+                });
+            }
             original_regions.push(region);
             original_partition_ids.push(part.number);
             max_id = max_id.max(part.number);
         }
 
+        // Partitions can only end on a whole block, even if the device doesn't
+        let block_size = device.logical_block_size();
+        let usable_end = device_end(device);
+
         Self {
             usable_start: 0,
-            usable_end: device.size(),
+            usable_end,
+            device_size: device.size(),
             changes: VecDeque::new(),
+            redo_stack: Vec::new(),
             original_regions,
             original_partition_ids,
+            kept_indices: BTreeSet::new(),
             next_partition_id: max_id + 1,
+            reserved_partition_ids: BTreeSet::new(),
+            block_size,
+            alignment: PARTITION_ALIGNMENT,
+            bad_regions: Vec::new(),
+            reserved_regions: Vec::new(),
+            first_partition_offset: 0,
+            no_gaps: false,
+            allow_trailing_free: true,
+            esp_max_start: None,
             wipe_disk: false,
         }
     }
@@ -209,6 +604,165 @@ impl Planner {
         }
     }
 
+    /// Align partition boundaries to `alignment` bytes instead of [`PARTITION_ALIGNMENT`]
+    ///
+    /// The alignment must be a non-zero multiple of the logical sector size of the
+    /// device, so that partitions always start and end on sector boundaries.
+    pub fn with_alignment(self, alignment: u64) -> Result<Self, PlanError> {
+        let sector_size = self.sector_size();
+        if alignment == 0 || alignment % sector_size != 0 {
+            warn!("Alignment of {alignment} bytes doesn't fit the {sector_size} byte sectors");
+            return Err(PlanError::InvalidAlignment { alignment, sector_size });
+        }
+        Ok(Self { alignment, ..self })
+    }
+
+    /// The default alignment of partition boundaries in bytes
+    pub fn alignment(&self) -> u64 {
+        self.alignment
+    }
+
+    /// The logical sector size of the device, assuming 512 bytes if unknown
+    pub fn sector_size(&self) -> u64 {
+        self.block_size.unwrap_or(512)
+    }
+
+    /// Reserve space at the end of the disk for a backup GPT with `entries` partition entries
+    ///
+    /// The reservation is derived from the entry count and the logical block size,
+    /// so fewer entries leave more of the disk usable. This only ever shrinks the
+    /// usable region, so combines with [`Planner::with_end_offset`].
+    pub fn with_gpt_entries(self, entries: u32) -> Self {
+        let reserved = gpt_backup_size(entries, self.sector_size());
+        Self {
+            usable_end: self.usable_end.min(self.device_size.saturating_sub(reserved)),
+            ..self
+        }
+    }
+
+    /// Never place new partitions before the given offset
+    ///
+    /// Unlike the usable start, this leaves the space before the offset available
+    /// to the platform, e.g. for a raw bootloader on some ARM boards.
+    pub fn with_first_partition_offset(self, offset: u64) -> Self {
+        Self {
+            first_partition_offset: offset,
+            ..self
+        }
+    }
+
+    /// Disallow free space between partitions, as required by some fully-packed images
+    ///
+    /// This is enforced by [`Planner::validate`] once all changes have been planned.
+    pub fn with_no_gaps(self, no_gaps: bool) -> Self {
+        Self { no_gaps, ..self }
+    }
+
+    /// Whether free space may remain after the last partition when gaps are disallowed
+    pub fn with_trailing_free(self, allow_trailing_free: bool) -> Self {
+        Self {
+            allow_trailing_free,
+            ..self
+        }
+    }
+
+    /// Never start an EFI system partition beyond the given offset
+    ///
+    /// Some firmware fails to boot from an ESP that starts past the first 2TiB of
+    /// a large disk. Partitions are recognised as an ESP by their role or type.
+    pub fn with_esp_max_start(self, limit: u64) -> Self {
+        Self {
+            esp_max_start: Some(limit),
+            ..self
+        }
+    }
+
+    /// The offset beyond which an EFI system partition may not start, if limited
+    pub fn esp_max_start(&self) -> Option<u64> {
+        self.esp_max_start
+    }
+
+    /// Validate the planned layout as a whole
+    ///
+    /// Individual changes are checked as they are planned, but later changes to
+    /// the planner such as its usable offsets can invalidate them. This rechecks
+    /// the full layout for stale changes, bounds, overlaps and, if disallowed,
+    /// gaps, returning every problem found rather than just the first.
+    pub fn validate(&self) -> Result<(), Vec<PlanError>> {
+        let mut errors = vec![];
+
+        // Changes to original partitions must still refer to one
+        for change in &self.changes {
+            let index = match change {
+                Change::DeletePartition { original_index, .. }
+                | Change::ResizePartition { original_index, .. }
+                | Change::SetAttributes { original_index, .. } => *original_index,
+                Change::AddPartition { .. } | Change::InitializeDisk { .. } => continue,
+            };
+            if index >= self.original_regions.len() {
+                warn!("Change refers to stale partition index {index}");
+                errors.push(PlanError::StaleChange { index });
+            }
+        }
+
+        let layout = self.current_layout();
+        let mut previous_end = None;
+        for region in &layout {
+            if region.start < self.usable_start || region.end > self.usable_end {
+                warn!(
+                    "Partition at {}..{} is outside the usable disk region",
+                    region.start, region.end
+                );
+                errors.push(PlanError::RegionOutOfBounds {
+                    start: region.start,
+                    end: region.end,
+                });
+            }
+            if previous_end.is_some_and(|end| region.start < end) {
+                warn!("Partition at {}..{} overlaps its predecessor", region.start, region.end);
+                errors.push(PlanError::RegionOverlap {
+                    start: region.start,
+                    end: region.end,
+                });
+            }
+            previous_end = Some(previous_end.map_or(region.end, |end: u64| end.max(region.end)));
+        }
+
+        if self.no_gaps {
+            // Bad regions can never be allocated, so they don't count as gaps
+            let mut layout = layout;
+            layout.extend(self.bad_regions.iter().cloned());
+            layout.sort_by_key(|r| r.start);
+
+            let mut current = None;
+            for region in &layout {
+                if let Some(end) = current.filter(|end| region.start > *end) {
+                    warn!("Gap in layout at {end}..{}", region.start);
+                    errors.push(PlanError::Gap {
+                        start: end,
+                        end: region.start,
+                    });
+                }
+                current = Some(current.map_or(region.end, |end: u64| end.max(region.end)));
+            }
+
+            if let Some(end) = current.filter(|end| !self.allow_trailing_free && *end < self.usable_end) {
+                warn!("Trailing free space at {end}..{}", self.usable_end);
+                errors.push(PlanError::Gap {
+                    start: end,
+                    end: self.usable_end,
+                });
+            }
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    /// Get the first position at which new partitions may be allocated
+    pub fn allocation_start(&self) -> u64 {
+        self.usable_start.max(self.first_partition_offset)
+    }
+
     /// Get a human readable description of pending changes
     pub fn describe_changes(&self) -> String {
         if self.changes.is_empty() {
@@ -224,23 +778,52 @@ impl Planner {
         description
     }
 
-    /// Returns the current effective layout after all pending changes
+    /// Returns the current effective layout after all pending changes, ordered by start position
     pub fn current_layout(&self) -> Vec<Region> {
+        self.layout_with(None)
+    }
+
+    /// Compute the layout after all pending changes, plus an optional hypothetical one
+    fn layout_with(&self, overlay: Option<&Change>) -> Vec<Region> {
+        // Initializing the disk discards every partition, original or planned
+        if let Some(Change::InitializeDisk { .. }) = overlay {
+            return vec![];
+        }
+
         let mut layout = self.original_regions.clone();
         let mut deleted_indices = Vec::new();
 
-        // First pass: collect indices to delete
-        for change in &self.changes {
-            if let Change::DeletePartition {
-                original_index,
-                partition_id: _,
-            } = change
-            {
-                deleted_indices.push(*original_index);
+        // First pass: apply resizes and collect indices to delete. Stale changes
+        // referring to partitions that no longer exist are skipped here and
+        // reported by `validate()`.
+        for change in self.changes.iter().chain(overlay) {
+            match change {
+                Change::DeletePartition { original_index, .. } if *original_index < layout.len() => {
+                    deleted_indices.push(*original_index)
+                }
+                Change::ResizePartition {
+                    original_index,
+                    start,
+                    end,
+                    ..
+                } if *original_index < layout.len() => {
+                    layout[*original_index].start = *start;
+                    layout[*original_index].end = *end;
+                }
+                Change::SetAttributes {
+                    original_index,
+                    attributes,
+                    ..
+                } if *original_index < layout.len() => {
+                    layout[*original_index].attributes = Some(attributes.clone());
+                }
+                Change::AddPartition { .. } | Change::InitializeDisk { .. } => {}
+                _ => warn!("Skipping stale change: {change:?}"),
             }
         }
-        // Sort in reverse order to remove from highest index first
+        // Sort in reverse order to remove from highest index first, removing each only once
         deleted_indices.sort_unstable_by(|a, b| b.cmp(a));
+        deleted_indices.dedup();
 
         // Remove deleted partitions
         for index in deleted_indices {
@@ -248,7 +831,7 @@ impl Planner {
         }
 
         // Second pass: add new partitions
-        for change in &self.changes {
+        for change in self.changes.iter().chain(overlay) {
             if let Change::AddPartition {
                 start,
                 end,
@@ -266,10 +849,117 @@ impl Planner {
             }
         }
 
+        layout.sort_by_key(|r| r.start);
+        debug_assert!(
+            layout.windows(2).all(|pair| pair[0].start != pair[1].start),
+            "Multiple partitions start at the same position: {layout:?}"
+        );
+
         debug!("Current layout has {} partitions", layout.len());
         layout
     }
 
+    /// Normalize the pending changes into the minimal list of operations
+    ///
+    /// Only the last resize and attribute update of each original partition is
+    /// kept, resizes that leave a partition at its original bounds are dropped,
+    /// and any update to a partition that is later deleted is dropped in favour
+    /// of the deletion. Partitions that are not changed overall are untouched.
+    ///
+    /// Operations are ordered with any disk initialization first, then deletions,
+    /// resizes, attribute updates and finally additions, so freed space is
+    /// available before it is reused.
+    pub fn minimal_operations(&self) -> Vec<Change> {
+        let mut initialization = None;
+        let mut deletions: Vec<Change> = vec![];
+        let mut resizes: Vec<Change> = vec![];
+        let mut updates: Vec<Change> = vec![];
+        let mut additions = vec![];
+
+        let index_of = |change: &Change| match change {
+            Change::DeletePartition { original_index, .. }
+            | Change::ResizePartition { original_index, .. }
+            | Change::SetAttributes { original_index, .. } => Some(*original_index),
+            Change::AddPartition { .. } | Change::InitializeDisk { .. } => None,
+        };
+
+        for change in &self.changes {
+            let index = index_of(change);
+            match change {
+                Change::DeletePartition { .. } => {
+                    resizes.retain(|c| index_of(c) != index);
+                    updates.retain(|c| index_of(c) != index);
+                    if !deletions.iter().any(|c| index_of(c) == index) {
+                        deletions.push(change.clone());
+                    }
+                }
+                Change::ResizePartition { .. } => {
+                    resizes.retain(|c| index_of(c) != index);
+                    resizes.push(change.clone());
+                }
+                Change::SetAttributes { .. } => {
+                    updates.retain(|c| index_of(c) != index);
+                    updates.push(change.clone());
+                }
+                Change::AddPartition { .. } => additions.push(change.clone()),
+                Change::InitializeDisk { .. } => initialization = Some(change.clone()),
+            }
+        }
+
+        // Drop resizes that end up back at the original bounds
+        resizes.retain(|change| match change {
+            Change::ResizePartition {
+                original_index,
+                start,
+                end,
+                ..
+            } => self
+                .original_regions
+                .get(*original_index)
+                .is_none_or(|original| original.start != *start || original.end != *end),
+            _ => true,
+        });
+
+        let operations = initialization
+            .into_iter()
+            .chain(deletions)
+            .chain(resizes)
+            .chain(updates)
+            .chain(additions)
+            .collect::<Vec<_>>();
+        debug!(
+            "Reduced {} changes to {} operations",
+            self.changes.len(),
+            operations.len()
+        );
+        operations
+    }
+
+    /// Compute a stable checksum of the current effective layout
+    ///
+    /// The layout is sorted by position and hashed over each region's start, end
+    /// and partition ID, making it suitable for golden comparisons in tests.
+    pub fn layout_checksum(&self) -> String {
+        const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+        const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+        let mut layout = self.current_layout();
+        layout.sort_by_key(|r| (r.start, r.end, r.partition_id));
+
+        let mut hash = FNV_OFFSET;
+        for region in &layout {
+            let id = region.partition_id.map_or(0, |id| u64::from(id) + 1);
+            for value in [region.start, region.end, id] {
+                for byte in value.to_le_bytes() {
+                    hash ^= u64::from(byte);
+                    hash = hash.wrapping_mul(FNV_PRIME);
+                }
+            }
+        }
+
+        format!("{hash:016x}")
+    }
+
     pub fn plan_add_partition(&mut self, start: u64, end: u64) -> Result<(), PlanError> {
         self.plan_add_partition_with_attributes(start, end, None)
     }
@@ -289,38 +979,207 @@ impl Planner {
         end: u64,
         attributes: Option<PartitionAttributes>,
     ) -> Result<(), PlanError> {
-        debug!("Planning to add partition {start}..{end}");
-        debug!("Original size requested: {}", end - start);
+        self.plan_add_partition_aligned(start, end, attributes, self.alignment)
+    }
 
-        // Align start and end positions, capping to usable bounds
-        let aligned_start = std::cmp::max(align_up(start, PARTITION_ALIGNMENT), self.usable_start);
-        let aligned_end = std::cmp::min(align_down(end, PARTITION_ALIGNMENT), self.usable_end);
+    /// Plan to add a new partition, aligning it to the given boundary rather than
+    /// the planner's default alignment
+    ///
+    /// This allows e.g. swap to be placed on 2MiB hugepage boundaries.
+    pub fn plan_add_partition_aligned(
+        &mut self,
+        start: u64,
+        end: u64,
+        attributes: Option<PartitionAttributes>,
+        alignment: u64,
+    ) -> Result<(), PlanError> {
+        let region = self.aligned_region(start, end, alignment)?;
+        self.check_esp_start(&region, attributes.as_ref())?;
 
-        debug!("Aligned positions: {aligned_start}..{aligned_end}");
-        debug!("Size after alignment: {}", aligned_end - aligned_start);
+        let partition_id = self.allocate_partition_id();
+        debug!("Adding new partition with ID {partition_id} to change queue");
+        self.push_change(Change::AddPartition {
+            start: region.start,
+            end: region.end,
+            partition_id,
+            attributes,
+        });
+        Ok(())
+    }
 
-        // Validate input alignments
-        if is_aligned(start, PARTITION_ALIGNMENT) && aligned_start != start {
-            warn!("Start position was already aligned but was re-aligned differently");
-            return Err(PlanError::RegionOutOfBounds {
-                start: aligned_start,
-                end: aligned_end,
-            });
-        }
-        if is_aligned(end, PARTITION_ALIGNMENT) && aligned_end != end {
-            warn!("End position was already aligned but was re-aligned differently");
-            return Err(PlanError::RegionOutOfBounds {
-                start: aligned_start,
-                end: aligned_end,
-            });
+    /// Plan to add several partitions at once, all or nothing
+    ///
+    /// Each `(start, end, attributes)` spec is aligned and validated against the
+    /// current layout and the specs before it. If any spec fails, nothing is
+    /// queued and the error for the first failing spec is returned.
+    pub fn plan_add_partitions(&mut self, specs: &[(u64, u64, Option<PartitionAttributes>)]) -> Result<(), PlanError> {
+        let saved = self.clone();
+        for (start, end, attributes) in specs {
+            if let Err(e) = self.plan_add_partition_with_attributes(*start, *end, attributes.clone()) {
+                warn!("Discarding {} partitions, {start}..{end} failed: {e}", specs.len());
+                *self = saved;
+                return Err(e);
+            }
         }
-        // Validate bounds against usable disk region
-        if aligned_start < self.usable_start || aligned_end > self.usable_end {
-            warn!("Partition would be outside usable disk region");
-            return Err(PlanError::RegionOutOfBounds {
-                start: aligned_start,
-                end: aligned_end,
-            });
+        Ok(())
+    }
+
+    /// Plan to add a partition of at least `size` bytes at the start of the largest free region
+    ///
+    /// The size is rounded up to the planner's alignment, and the partition ID
+    /// allocated for the new partition is returned. This is a convenience for
+    /// interactive tools that don't want to compute positions themselves.
+    pub fn plan_add_partition_in_largest_free(&mut self, size: u64) -> Result<u32, PlanError> {
+        let size = size.div_ceil(self.alignment) * self.alignment;
+        let Some(region) = self.free_regions().into_iter().max_by_key(|r| r.size()) else {
+            warn!("No free region for a partition of {}", format_size(size));
+            return Err(PlanError::NoFreeRegions);
+        };
+
+        let start = region.start.max(self.first_partition_offset).div_ceil(self.alignment) * self.alignment;
+        if size == 0 || start.saturating_add(size) > region.end {
+            warn!(
+                "Largest free region {}..{} can't hold {}",
+                region.start,
+                region.end,
+                format_size(size)
+            );
+            return Err(PlanError::NoFreeRegions);
+        }
+
+        let region = self.aligned_region(start, start + size, self.alignment)?;
+        let partition_id = self.allocate_partition_id();
+        debug!("Adding new partition with ID {partition_id} to change queue");
+        self.push_change(Change::AddPartition {
+            start: region.start,
+            end: region.end,
+            partition_id,
+            attributes: None,
+        });
+        Ok(partition_id)
+    }
+
+    /// Plan to add a new partition with an explicit partition number
+    ///
+    /// This is for firmware that refers to partitions by number, and fails if the
+    /// number is zero or taken by a partition in the current layout.
+    pub fn plan_add_partition_numbered(
+        &mut self,
+        start: u64,
+        end: u64,
+        attributes: Option<PartitionAttributes>,
+        alignment: u64,
+        number: u32,
+    ) -> Result<(), PlanError> {
+        if number == 0 || self.partition_id_in_use(number) {
+            warn!("Partition number {number} is not available");
+            return Err(PlanError::PartitionNumberTaken { number });
+        }
+        let region = self.aligned_region(start, end, alignment)?;
+        self.check_esp_start(&region, attributes.as_ref())?;
+
+        // Automatically allocated IDs continue after the highest one in use
+        self.reserved_partition_ids.remove(&number);
+        self.next_partition_id = self.next_partition_id.max(number + 1);
+        debug!("Adding new partition with explicit ID {number} to change queue");
+        self.push_change(Change::AddPartition {
+            start: region.start,
+            end: region.end,
+            partition_id: number,
+            attributes,
+        });
+        Ok(())
+    }
+
+    /// Set aside a partition number so it is never allocated automatically
+    ///
+    /// Used to keep a number free for a partition that is planned later with
+    /// [`Planner::plan_add_partition_numbered`].
+    pub fn reserve_partition_id(&mut self, number: u32) -> Result<(), PlanError> {
+        if number == 0 || self.partition_id_in_use(number) || !self.reserved_partition_ids.insert(number) {
+            warn!("Cannot reserve partition number {number}");
+            return Err(PlanError::PartitionNumberTaken { number });
+        }
+        Ok(())
+    }
+
+    /// Release all reserved partition numbers
+    pub fn clear_reserved_partition_ids(&mut self) {
+        self.reserved_partition_ids.clear();
+    }
+
+    /// Whether a partition in the current layout already uses this ID
+    fn partition_id_in_use(&self, id: u32) -> bool {
+        self.current_layout().iter().any(|r| r.partition_id == Some(id))
+    }
+
+    /// Preview the region a partition between `start` and `end` would occupy
+    ///
+    /// This applies the same alignment and validation as [`Planner::plan_add_partition`]
+    /// without queueing a change, so the final size can be shown before committing to it.
+    pub fn preview_add(&self, start: u64, end: u64) -> Result<Region, PlanError> {
+        self.aligned_region(start, end, self.alignment)
+    }
+
+    /// Reject an EFI system partition starting beyond the firmware limit, if one is set
+    fn check_esp_start(&self, region: &Region, attributes: Option<&PartitionAttributes>) -> Result<(), PlanError> {
+        match self.esp_max_start {
+            Some(limit) if region.start > limit && attributes.is_some_and(PartitionAttributes::is_esp) => {
+                warn!(
+                    "EFI system partition at {}..{} starts too far into the disk",
+                    region.start, region.end
+                );
+                Err(PlanError::EspTooFar {
+                    start: region.start,
+                    limit,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Align a requested partition and validate it against the current layout
+    fn aligned_region(&self, start: u64, end: u64, alignment: u64) -> Result<Region, PlanError> {
+        debug!("Planning to add partition {start}..{end}");
+        debug!("Original size requested: {}", end - start);
+
+        // Align start and end positions, capping to usable bounds
+        let aligned_start = std::cmp::max(align_up(start, alignment), self.usable_start);
+        let aligned_end = std::cmp::min(align_down(end, alignment), self.usable_end);
+
+        debug!("Aligned positions: {aligned_start}..{aligned_end}");
+        debug!("Size after alignment: {}", aligned_end - aligned_start);
+
+        // Validate input alignments
+        if is_aligned(start, alignment) && aligned_start != start {
+            warn!("Start position was already aligned but was re-aligned differently");
+            return Err(PlanError::RegionOutOfBounds {
+                start: aligned_start,
+                end: aligned_end,
+            });
+        }
+        if is_aligned(end, alignment) && aligned_end != end {
+            warn!("End position was already aligned but was re-aligned differently");
+            return Err(PlanError::RegionOutOfBounds {
+                start: aligned_start,
+                end: aligned_end,
+            });
+        }
+        // Validate bounds against usable disk region
+        if aligned_start < self.usable_start || aligned_end > self.usable_end {
+            warn!("Partition would be outside usable disk region");
+            return Err(PlanError::RegionOutOfBounds {
+                start: aligned_start,
+                end: aligned_end,
+            });
+        }
+
+        if aligned_start < self.first_partition_offset {
+            warn!("Partition would start before the first partition offset");
+            return Err(PlanError::RegionOutOfBounds {
+                start: aligned_start,
+                end: aligned_end,
+            });
         }
 
         // Ensure we haven't created a zero-sized partition through alignment
@@ -334,6 +1193,26 @@ impl Planner {
 
         // Check for overlaps with current layout
         let new_region = Region::new(aligned_start, aligned_end);
+        if let Some(bad) = self.bad_regions.iter().find(|r| new_region.overlaps_with(r)) {
+            warn!(
+                "Partition would intersect bad region at {}..{} - attempted region {}..{}",
+                bad.start, bad.end, new_region.start, new_region.end
+            );
+            return Err(PlanError::BadRegion {
+                start: aligned_start,
+                end: aligned_end,
+            });
+        }
+        if let Some(reserved) = self.reserved_regions.iter().find(|r| new_region.overlaps_with(r)) {
+            warn!(
+                "Partition would intersect reserved region at {}..{} - attempted region {}..{}",
+                reserved.start, reserved.end, new_region.start, new_region.end
+            );
+            return Err(PlanError::RegionReserved {
+                start: aligned_start,
+                end: aligned_end,
+            });
+        }
         let current = self.current_layout();
         for region in &current {
             if new_region.overlaps_with(region) {
@@ -346,17 +1225,29 @@ impl Planner {
                     end: aligned_end,
                 });
             }
+            if let Some(block_size) = self.block_size {
+                if new_region.shares_block_with(region, block_size) {
+                    warn!(
+                        "Partition would share a {block_size} byte block with partition at {}..{}",
+                        region.start, region.end
+                    );
+                    return Err(PlanError::SharedBlock {
+                        start: aligned_start,
+                        end: aligned_end,
+                        block_size,
+                    });
+                }
+            }
         }
 
-        let partition_id = self.allocate_partition_id();
-        debug!("Adding new partition with ID {partition_id} to change queue");
-        self.changes.push_back(Change::AddPartition {
-            start: aligned_start,
-            end: aligned_end,
-            partition_id,
-            attributes,
-        });
-        Ok(())
+        // The partition table can only describe whole sectors
+        let sector_size = self.sector_size();
+        if let Some(offset) = [aligned_start, aligned_end].into_iter().find(|o| o % sector_size != 0) {
+            warn!("Partition boundary {offset} is not aligned to the {sector_size} byte sector size");
+            return Err(PlanError::NotSectorAligned { offset, sector_size });
+        }
+
+        Ok(new_region)
     }
 
     /// Plan to delete an existing partition
@@ -371,6 +1262,11 @@ impl Planner {
             });
         }
 
+        if self.kept_indices.contains(&index) {
+            warn!("Refusing to delete kept partition at index {index}");
+            return Err(PlanError::PartitionKept { index });
+        }
+
         let partition_id = self
             .get_original_partition_id(index)
             .ok_or(PlanError::RegionOutOfBounds {
@@ -379,104 +1275,1592 @@ impl Planner {
             })?;
 
         debug!("Adding deletion of partition ID {partition_id} to change queue");
-        self.changes.push_back(Change::DeletePartition {
+        self.push_change(Change::DeletePartition {
             original_index: index,
             partition_id,
         });
         Ok(())
     }
 
-    /// Undo the most recent change
-    pub fn undo(&mut self) -> bool {
-        if let Some(change) = self.changes.pop_back() {
-            debug!("Undoing last change: {change:?}");
-            true
-        } else {
-            debug!("No changes to undo");
-            false
+    /// Plan to move the end of an existing partition, growing or shrinking it
+    ///
+    /// The new end must be aligned and leave at least [`MIN_PARTITION_SIZE`] bytes.
+    /// Unlike [`Planner::plan_shrink_partition`] the data on the partition isn't
+    /// considered, so this is intended for growing into adjacent free space.
+    pub fn plan_resize_partition(&mut self, index: usize, new_end: u64) -> Result<(), PlanError> {
+        let Some(region) = self.current_layout_region(index) else {
+            warn!("Invalid partition index {index}");
+            return Err(PlanError::RegionOutOfBounds {
+                start: self.usable_start,
+                end: self.usable_size(),
+            });
+        };
+
+        if self.kept_indices.contains(&index) {
+            warn!("Refusing to resize kept partition at index {index}");
+            return Err(PlanError::PartitionKept { index });
+        }
+
+        if !is_aligned(new_end, self.alignment) || new_end > self.usable_end {
+            warn!("New end {new_end} of partition {index} is misaligned or outside the usable disk region");
+            return Err(PlanError::RegionOutOfBounds {
+                start: region.start,
+                end: new_end,
+            });
+        }
+
+        if new_end < region.start.saturating_add(MIN_PARTITION_SIZE) {
+            warn!(
+                "Refusing to resize partition {index} below {}",
+                format_size(MIN_PARTITION_SIZE)
+            );
+            return Err(PlanError::ShrinkBelowUsed {
+                index,
+                requested: new_end.saturating_sub(region.start),
+                minimum: MIN_PARTITION_SIZE,
+            });
+        }
+
+        let resized = Region::new(region.start, new_end);
+        if self.bad_regions.iter().any(|r| resized.overlaps_with(r)) {
+            warn!("Resized partition {index} would intersect a bad region");
+            return Err(PlanError::BadRegion {
+                start: region.start,
+                end: new_end,
+            });
+        }
+        if self.reserved_regions.iter().any(|r| resized.overlaps_with(r)) {
+            warn!("Resized partition {index} would intersect space reserved for growth");
+            return Err(PlanError::RegionReserved {
+                start: region.start,
+                end: new_end,
+            });
+        }
+        if let Some(other) = self
+            .current_layout()
+            .iter()
+            .filter(|r| r.start != region.start)
+            .find(|r| resized.overlaps_with(r))
+        {
+            warn!(
+                "Resized partition {index} would overlap the partition at {}..{}",
+                other.start, other.end
+            );
+            return Err(PlanError::RegionOverlap {
+                start: region.start,
+                end: new_end,
+            });
+        }
+
+        let partition_id = self
+            .get_original_partition_id(index)
+            .ok_or(PlanError::RegionOutOfBounds {
+                start: self.usable_start,
+                end: self.usable_size(),
+            })?;
+
+        debug!("Adding resize of partition ID {partition_id} to end at {new_end} to change queue");
+        self.push_change(Change::ResizePartition {
+            original_index: index,
+            partition_id,
+            start: region.start,
+            end: new_end,
+        });
+        Ok(())
+    }
+
+    /// Plan to shrink an existing partition to `new_size` bytes
+    ///
+    /// The filesystem on the partition must keep its data, so the caller supplies
+    /// the `used_bytes` reported by the filesystem tooling (`df`, `ntfsresize --info`).
+    /// Shrinking below the used data plus [`SHRINK_HEADROOM`] is refused.
+    pub fn plan_shrink_partition(&mut self, index: usize, new_size: u64, used_bytes: u64) -> Result<(), PlanError> {
+        let Some(region) = self.current_layout_region(index) else {
+            warn!("Invalid partition index {index}");
+            return Err(PlanError::RegionOutOfBounds {
+                start: self.usable_start,
+                end: self.usable_size(),
+            });
+        };
+
+        if self.kept_indices.contains(&index) {
+            warn!("Refusing to shrink kept partition at index {index}");
+            return Err(PlanError::PartitionKept { index });
+        }
+
+        if new_size == 0 || new_size > region.size() {
+            warn!("Shrinking partition {index} to {new_size} bytes would not reduce it");
+            return Err(PlanError::RegionOutOfBounds {
+                start: region.start,
+                end: region.start + new_size,
+            });
         }
+
+        let minimum = used_bytes.saturating_add(SHRINK_HEADROOM);
+        if new_size < minimum {
+            warn!(
+                "Refusing to shrink partition {index} to {} with {} in use",
+                format_size(new_size),
+                format_size(used_bytes)
+            );
+            return Err(PlanError::ShrinkBelowUsed {
+                index,
+                requested: new_size,
+                minimum,
+            });
+        }
+
+        let partition_id = self
+            .get_original_partition_id(index)
+            .ok_or(PlanError::RegionOutOfBounds {
+                start: self.usable_start,
+                end: self.usable_size(),
+            })?;
+
+        debug!("Adding shrink of partition ID {partition_id} to {new_size} bytes to change queue");
+        self.push_change(Change::ResizePartition {
+            original_index: index,
+            partition_id,
+            start: region.start,
+            end: region.start + new_size,
+        });
+        Ok(())
     }
 
-    /// Clear all planned changes
-    pub fn reset(&mut self) {
-        eprintln!("Resetting all planned changes");
-        self.changes.clear();
+    /// Plan to replace the attributes of an existing partition
+    ///
+    /// This only changes partition metadata such as the type or flags, leaving
+    /// the partition contents untouched.
+    pub fn plan_set_attributes(&mut self, index: usize, attributes: PartitionAttributes) -> Result<(), PlanError> {
+        if self.current_layout_region(index).is_none() {
+            warn!("Invalid partition index {index}");
+            return Err(PlanError::RegionOutOfBounds {
+                start: self.usable_start,
+                end: self.usable_size(),
+            });
+        }
+
+        let partition_id = self
+            .get_original_partition_id(index)
+            .ok_or(PlanError::RegionOutOfBounds {
+                start: self.usable_start,
+                end: self.usable_size(),
+            })?;
+
+        debug!("Adding attribute update of partition ID {partition_id} to change queue");
+        self.push_change(Change::SetAttributes {
+            original_index: index,
+            partition_id,
+            attributes,
+        });
+        Ok(())
     }
 
-    /// Check if there are any pending changes
-    pub fn has_changes(&self) -> bool {
-        !self.changes.is_empty()
+    /// Find the current bounds of an original partition, accounting for pending
+    /// resizes. Returns None if the partition doesn't exist or is pending deletion.
+    fn current_layout_region(&self, index: usize) -> Option<Region> {
+        let mut region = self.original_regions.get(index)?.clone();
+        for change in &self.changes {
+            match change {
+                Change::DeletePartition { original_index, .. } if *original_index == index => return None,
+                Change::ResizePartition {
+                    original_index,
+                    start,
+                    end,
+                    ..
+                } if *original_index == index => {
+                    region.start = *start;
+                    region.end = *end;
+                }
+                _ => {}
+            }
+        }
+        Some(region)
     }
-    /// Get the list of pending changes
-    pub fn changes(&self) -> &VecDeque<Change> {
-        &self.changes
+
+    /// Mark a region of the disk as bad, e.g. from SMART or badblocks data
+    ///
+    /// New partitions will never be placed over a bad region, and allocation
+    /// strategies route around them.
+    pub fn mark_bad_region(&mut self, start: u64, end: u64) -> Result<(), PlanError> {
+        if end <= start || end > self.usable_end {
+            warn!("Bad region {start}..{end} is outside the disk");
+            return Err(PlanError::RegionOutOfBounds { start, end });
+        }
+
+        debug!("Marking {start}..{end} as bad");
+        self.bad_regions.push(Region::new(start, end));
+        self.bad_regions.sort_by_key(|r| r.start);
+        Ok(())
     }
 
-    /// Get the size of the usable disk region in bytes
-    pub fn usable_size(&self) -> u64 {
-        self.usable_end - self.usable_start
+    /// Get the regions of the disk marked as bad, sorted by position
+    pub fn bad_regions(&self) -> &[Region] {
+        &self.bad_regions
+    }
+
+    /// Reserve a region for a partition to grow into later
+    ///
+    /// Reserved regions are left unallocated and new partitions will not be
+    /// placed over them.
+    pub fn reserve_region(&mut self, start: u64, end: u64) -> Result<(), PlanError> {
+        self.push_reserve(Region::new(start, end))
+    }
+
+    /// Reserve a region for a planned partition to grow into later
+    ///
+    /// Unlike [`Planner::reserve_region`] the reservation belongs to the partition
+    /// with `partition_id`, and is released along with the change adding it when
+    /// that is undone or cleared. Redoing the change doesn't reinstate it.
+    pub fn reserve_growth(&mut self, partition_id: u32, start: u64, end: u64) -> Result<(), PlanError> {
+        let mut region = Region::new(start, end);
+        region.partition_id = Some(partition_id);
+        self.push_reserve(region)
+    }
+
+    fn push_reserve(&mut self, region: Region) -> Result<(), PlanError> {
+        let (start, end) = (region.start, region.end);
+        if end <= start || start < self.usable_start || end > self.usable_end {
+            warn!("Reserved region {start}..{end} is outside the usable disk region");
+            return Err(PlanError::RegionOutOfBounds { start, end });
+        }
+
+        if self.current_layout().iter().any(|r| r.overlaps_with(&region)) {
+            warn!("Reserved region {start}..{end} overlaps with an existing partition");
+            return Err(PlanError::RegionOverlap { start, end });
+        }
+
+        debug!("Reserving {start}..{end} for growth");
+        self.reserved_regions.push(region);
+        self.reserved_regions.sort_by_key(|r| r.start);
+        Ok(())
+    }
+
+    /// Get the regions reserved for growth, sorted by position
+    pub fn reserved_regions(&self) -> &[Region] {
+        &self.reserved_regions
+    }
+
+    /// Release growth reserves whose partition is no longer planned
+    fn release_orphaned_reserves(&mut self) {
+        let planned = self
+            .changes
+            .iter()
+            .filter_map(|change| match change {
+                Change::AddPartition { partition_id, .. } => Some(*partition_id),
+                _ => None,
+            })
+            .collect::<BTreeSet<_>>();
+        self.reserved_regions
+            .retain(|r| r.partition_id.is_none_or(|id| planned.contains(&id)));
+    }
+
+    /// Find the free space between partitions in the current layout
+    ///
+    /// Returns the gaps within the usable disk area, including any before the
+    /// first and after the last partition. Gaps smaller than [`PARTITION_ALIGNMENT`]
+    /// can't hold a partition and are left out. Bad and reserved regions are not
+    /// subtracted, see [`layout_map()`](Self::layout_map) for those.
+    pub fn free_regions(&self) -> Vec<Region> {
+        self.free_regions_in(self.current_layout())
+    }
+
+    /// Find the free space around the partitions of a sorted layout
+    fn free_regions_in(&self, layout: Vec<Region>) -> Vec<Region> {
+        let mut regions = vec![];
+        let mut current = self.usable_start;
+        let mut push_gap = |start: u64, end: u64| {
+            if end.saturating_sub(start) >= PARTITION_ALIGNMENT {
+                regions.push(Region::new(start, end));
+            }
+        };
+
+        for region in layout {
+            if region.start > current {
+                push_gap(current, region.start);
+            }
+            current = current.max(region.end);
+        }
+        if self.usable_end > current {
+            push_gap(current, self.usable_end);
+        }
+
+        regions
+    }
+
+    /// Capture the planned state, to return to later with [`Planner::restore()`]
+    ///
+    /// This covers the usable region, pending changes, the original layout and the
+    /// partition numbering, but not configuration such as bad or reserved regions.
+    /// Restoring does release growth reserves of partitions that are no longer planned.
+    pub fn snapshot(&self) -> PlannerState {
+        PlannerState {
+            usable_start: self.usable_start,
+            usable_end: self.usable_end,
+            changes: self.changes.clone(),
+            original_regions: self.original_regions.clone(),
+            original_partition_ids: self.original_partition_ids.clone(),
+            kept_indices: self.kept_indices.clone(),
+            next_partition_id: self.next_partition_id,
+            wipe_disk: self.wipe_disk,
+        }
+    }
+
+    /// Return to a state captured by [`Planner::snapshot()`], dropping any undone changes
+    pub fn restore(&mut self, state: PlannerState) {
+        debug!("Restoring planner snapshot with {} changes", state.changes.len());
+        self.usable_start = state.usable_start;
+        self.usable_end = state.usable_end;
+        self.changes = state.changes;
+        self.redo_stack.clear();
+        self.original_regions = state.original_regions;
+        self.original_partition_ids = state.original_partition_ids;
+        self.kept_indices = state.kept_indices;
+        self.next_partition_id = state.next_partition_id;
+        self.wipe_disk = state.wipe_disk;
+        self.release_orphaned_reserves();
+    }
+
+    /// Preview the planner with a hypothetical change applied
+    ///
+    /// The returned view reflects the change in its layout without queueing it,
+    /// e.g. to preview a hovered operation in a UI. The change is not validated.
+    pub fn with_overlay(&self, change: Change) -> PlannerView<'_> {
+        PlannerView {
+            planner: self,
+            overlay: change,
+        }
+    }
+
+    /// Map the usable disk area into contiguous, non-overlapping segments
+    ///
+    /// Partitions are reported as-is, while gaps between them are split into
+    /// free, bad and reserved segments. Bad regions within existing partitions
+    /// are not shown.
+    pub fn layout_map(&self) -> Vec<LayoutSegment> {
+        let mut layout = self.current_layout();
+        layout.sort_by_key(|r| r.start);
+
+        let mut marked = self
+            .bad_regions
+            .iter()
+            .map(|r| (r, SegmentKind::Bad))
+            .chain(self.reserved_regions.iter().map(|r| (r, SegmentKind::Reserved)))
+            .collect::<Vec<_>>();
+        marked.sort_by_key(|(r, _)| r.start);
+
+        let mut segments = vec![];
+        let mut current = self.usable_start;
+        let push_gap = |segments: &mut Vec<LayoutSegment>, start: u64, end: u64| {
+            let mut position = start;
+            for (span, kind) in marked.iter().filter(|(r, _)| r.start < end && r.end > start) {
+                let span_start = span.start.max(position);
+                let span_end = span.end.min(end);
+                if span_end <= span_start {
+                    continue;
+                }
+                if span_start > position {
+                    segments.push(LayoutSegment::new(
+                        Region::new(position, span_start),
+                        SegmentKind::Free,
+                        self.alignment,
+                    ));
+                }
+                segments.push(LayoutSegment::new(
+                    Region::new(span_start, span_end),
+                    *kind,
+                    self.alignment,
+                ));
+                position = span_end;
+            }
+            if end > position {
+                segments.push(LayoutSegment::new(
+                    Region::new(position, end),
+                    SegmentKind::Free,
+                    self.alignment,
+                ));
+            }
+        };
+
+        for region in layout {
+            if region.start > current {
+                push_gap(&mut segments, current, region.start);
+            }
+            current = current.max(region.end);
+            segments.push(LayoutSegment::new(region, SegmentKind::Partition, self.alignment));
+        }
+        if self.usable_end > current {
+            push_gap(&mut segments, current, self.usable_end);
+        }
+
+        segments
+    }
+
+    /// Render the layout as a single-line proportional bar, `width` columns wide
+    ///
+    /// Each segment of the [`layout_map()`](Self::layout_map) is drawn in proportion
+    /// to its size and labelled where space allows, e.g. `[boot|##root##....free....]`.
+    pub fn render_bar(&self, width: usize) -> String {
+        let segments = self.layout_map();
+        let inner = width.saturating_sub(2);
+        let count = segments.len();
+
+        // Every segment gets at least one column, with the rest shared proportionally
+        let available = inner.saturating_sub(count.saturating_sub(1));
+        let spare = available.saturating_sub(count) as u128;
+        let total = segments
+            .iter()
+            .map(|s| u128::from(s.region.size()))
+            .sum::<u128>()
+            .max(1);
+        let mut columns = segments
+            .iter()
+            .map(|s| 1 + (spare * u128::from(s.region.size()) / total) as usize)
+            .collect::<Vec<_>>();
+
+        // Hand out columns lost to rounding, largest segments first
+        let mut order = (0..count).collect::<Vec<_>>();
+        order.sort_by_key(|i| std::cmp::Reverse(segments[*i].region.size()));
+        let leftover = available.saturating_sub(columns.iter().sum());
+        for i in order.iter().cycle().take(if count > 0 { leftover } else { 0 }) {
+            columns[*i] += 1;
+        }
+
+        let cells = segments.iter().zip(columns).map(|(segment, columns)| {
+            let (label, fill) = match segment.kind {
+                SegmentKind::Partition => (
+                    segment
+                        .region
+                        .attributes
+                        .as_ref()
+                        .and_then(|a| a.role.as_ref())
+                        .map(|role| role.to_string())
+                        .or_else(|| segment.region.partition_id.map(|id| format!("p{id}")))
+                        .unwrap_or_default(),
+                    '#',
+                ),
+                SegmentKind::Free => ("free".to_string(), '.'),
+                SegmentKind::Bad => ("bad".to_string(), 'X'),
+                SegmentKind::Reserved => ("reserved".to_string(), '~'),
+            };
+
+            if label.len() > columns {
+                return fill.to_string().repeat(columns);
+            }
+            let padding = columns - label.len();
+            let left = padding / 2;
+            format!(
+                "{}{label}{}",
+                fill.to_string().repeat(left),
+                fill.to_string().repeat(padding - left)
+            )
+        });
+
+        let mut bar = format!("[{}]", cells.collect::<Vec<_>>().join("|"));
+        let length = bar.chars().count();
+        if length > width {
+            bar = bar.chars().take(width).collect();
+        } else {
+            bar.push_str(&" ".repeat(width - length));
+        }
+        bar
     }
 
-    /// Get the usable disk region offsets
-    pub fn offsets(&self) -> (u64, u64) {
-        (self.usable_start, self.usable_end)
+    /// Mark an existing partition to be preserved
+    ///
+    /// Kept partitions can't be deleted, and initializing the disk will reclaim
+    /// every other partition instead of wiping the partition table.
+    pub fn keep_partition(&mut self, index: usize) -> Result<(), PlanError> {
+        if index >= self.original_regions.len() {
+            warn!("Invalid partition index {index}");
+            return Err(PlanError::RegionOutOfBounds {
+                start: self.usable_start,
+                end: self.usable_size(),
+            });
+        }
+
+        debug!("Keeping partition at index {index}");
+        self.redo_stack.clear();
+        self.kept_indices.insert(index);
+        Ok(())
+    }
+
+    /// Get the original partition regions, in device order
+    pub fn original_regions(&self) -> &[Region] {
+        &self.original_regions
+    }
+
+    /// Get the original partition region at `index`, if any
+    pub fn original_region(&self, index: usize) -> Option<&Region> {
+        self.original_regions.get(index)
+    }
+
+    /// Find the index into the original regions for a partition number
+    ///
+    /// Partition numbers as displayed to users needn't be contiguous, so this
+    /// should be used to map them to the indices taken by the `plan_*` methods.
+    pub fn index_of_partition_id(&self, id: u32) -> Option<usize> {
+        self.original_partition_ids.iter().position(|&p| p == id)
+    }
+
+    /// Record a new change, invalidating anything that could be redone
+    fn push_change(&mut self, change: Change) {
+        self.redo_stack.clear();
+        self.changes.push_back(change);
+    }
+
+    /// Undo the most recent change, returning it
+    pub fn undo(&mut self) -> Option<Change> {
+        if let Some(change) = self.changes.pop_back() {
+            debug!("Undoing last change: {change:?}");
+            if let Change::InitializeDisk {
+                original_regions,
+                original_partition_ids,
+                next_partition_id,
+            } = &change
+            {
+                self.original_regions = original_regions.clone();
+                self.original_partition_ids = original_partition_ids.clone();
+                self.next_partition_id = *next_partition_id;
+                self.wipe_disk = false;
+            }
+            // Give back the ID of the most recently added partition for reuse
+            if let Change::AddPartition { partition_id, .. } = &change {
+                if *partition_id + 1 == self.next_partition_id {
+                    self.next_partition_id = *partition_id;
+                }
+            }
+            self.redo_stack.push(change.clone());
+            self.release_orphaned_reserves();
+            Some(change)
+        } else {
+            debug!("No changes to undo");
+            None
+        }
+    }
+
+    /// Reapply the most recently undone change, returning it
+    pub fn redo(&mut self) -> Option<Change> {
+        if let Some(change) = self.redo_stack.pop() {
+            debug!("Redoing change: {change:?}");
+            self.reapply(change.clone());
+            Some(change)
+        } else {
+            debug!("No changes to redo");
+            None
+        }
+    }
+
+    /// Apply previously recorded changes on top of the pending ones
+    ///
+    /// This allows a saved session to be resumed, e.g. from changes serialized with
+    /// the `serde` feature, on a planner freshly created for the same device. The
+    /// result is validated, and if any change doesn't fit the planner is left untouched.
+    pub fn replay_changes(&mut self, changes: impl IntoIterator<Item = Change>) -> Result<(), Vec<PlanError>> {
+        let saved = self.clone();
+        self.redo_stack.clear();
+        for change in changes {
+            self.reapply(change);
+        }
+        self.validate().inspect_err(|_| *self = saved)
+    }
+
+    /// Record a change that was already planned once, repeating its side effects
+    fn reapply(&mut self, change: Change) {
+        match change {
+            Change::InitializeDisk { .. } => self.discard_original_layout(),
+            Change::AddPartition { partition_id, .. } => {
+                self.next_partition_id = self.next_partition_id.max(partition_id + 1);
+            }
+            _ => {}
+        }
+        self.changes.push_back(change);
+    }
+
+    /// Check if there are any undone changes that can be redone
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    /// Drop all pending and undone changes, keeping the original layout the planner was built with
+    ///
+    /// Growth reserves of the dropped partitions are released too.
+    ///
+    /// Note that a planned disk initialization has already discarded the original
+    /// partitions; use [`Planner::reset_to_device`] to recover them.
+    ///
+    /// Returns the number of pending changes that were discarded.
+    pub fn clear_changes(&mut self) -> usize {
+        let count = self.changes.len();
+        debug!("Clearing {count} planned changes");
+        self.changes.clear();
+        self.redo_stack.clear();
+        self.release_orphaned_reserves();
+        count
+    }
+
+    /// Clear all planned changes, an alias for [`Planner::clear_changes`]
+    ///
+    /// Returns the number of pending changes that were discarded.
+    pub fn reset(&mut self) -> usize {
+        self.clear_changes()
+    }
+
+    /// Rebuild the planner from scratch for the given device
+    ///
+    /// Unlike [`Planner::clear_changes`] this rereads the original partitions and
+    /// discards all configuration, as if the planner was created with [`Planner::new`].
+    pub fn reset_to_device(&mut self, device: &BlockDevice) {
+        debug!("Resetting planner to device {}", device.name());
+        *self = Self::new(device);
+    }
+
+    /// Check if there are any pending changes
+    pub fn has_changes(&self) -> bool {
+        !self.changes.is_empty()
+    }
+
+    /// List the partitions that would be renumbered if partition numbers were compacted
+    ///
+    /// The planner keeps the original partition numbers, but tools applying the plan
+    /// may close the gaps left by deleted partitions. Each pair maps the current
+    /// number of a remaining partition to the number it would have after compacting,
+    /// sorted by the current number.
+    pub fn renumbering_impact(&self) -> Vec<(u32, u32)> {
+        let deleted = self
+            .changes
+            .iter()
+            .filter_map(|change| match change {
+                Change::DeletePartition { partition_id, .. } => Some(*partition_id),
+                _ => None,
+            })
+            .collect::<BTreeSet<_>>();
+
+        let mut impact = self
+            .current_layout()
+            .iter()
+            .filter_map(|region| region.partition_id)
+            .filter_map(|id| {
+                let shift = deleted.range(..id).count() as u32;
+                (shift > 0).then_some((id, id - shift))
+            })
+            .collect::<Vec<_>>();
+        impact.sort_unstable();
+        impact
+    }
+
+    /// Check if any pending change, or wiping the disk, would destroy existing data
+    ///
+    /// This includes resizes that shrink a partition below its original extent.
+    pub fn has_destructive_changes(&self) -> bool {
+        self.wipe_disk
+            || self.changes.iter().any(|change| match change {
+                Change::ResizePartition {
+                    original_index,
+                    start,
+                    end,
+                    ..
+                } => self
+                    .original_regions
+                    .get(*original_index)
+                    .is_some_and(|original| *start > original.start || *end < original.end),
+                change => change.is_destructive(),
+            })
+    }
+
+    /// Get the list of pending changes
+    pub fn changes(&self) -> &VecDeque<Change> {
+        &self.changes
+    }
+
+    /// Get the size of the usable disk region in bytes
+    pub fn usable_size(&self) -> u64 {
+        self.usable_end - self.usable_start
+    }
+
+    /// Get the usable disk region offsets
+    pub fn offsets(&self) -> (u64, u64) {
+        (self.usable_start, self.usable_end)
+    }
+
+    /// Clamp the planned layout onto a (possibly smaller) device
+    ///
+    /// Planned partitions that no longer fit within the device are dropped and
+    /// returned. Space the planner reserves at the end of the disk, such as for
+    /// the backup GPT, is kept clear on the new device too. If any partition that
+    /// would be dropped has a role assigned, it is considered essential and the
+    /// layout is left untouched, as it is if a resized partition no longer fits.
+    pub fn trim_to_device(&mut self, device: &BlockDevice) -> Result<Vec<Region>, PlanError> {
+        let block_size = self.sector_size();
+        let reserved_tail = (self.device_size / block_size * block_size).saturating_sub(self.usable_end);
+        let device_end = self.usable_end.min(device_end(device).saturating_sub(reserved_tail));
+
+        // Existing partitions can't be dropped from the plan like new ones
+        if let Some(Change::ResizePartition {
+            partition_id,
+            start,
+            end,
+            ..
+        }) = self
+            .changes
+            .iter()
+            .find(|c| matches!(c, Change::ResizePartition { end, .. } if *end > device_end))
+        {
+            warn!("Resized partition {partition_id} ({start}..{end}) does not fit within {device_end}");
+            return Err(PlanError::RegionOutOfBounds {
+                start: *start,
+                end: *end,
+            });
+        }
+
+        let overflowing = |change: &Change| matches!(change, Change::AddPartition { end, .. } if *end > device_end);
+
+        if let Some(Change::AddPartition { start, end, .. }) = self.changes.iter().find(|c| {
+            overflowing(c) && matches!(c, Change::AddPartition { attributes: Some(attrs), .. } if attrs.role.is_some())
+        }) {
+            warn!("Essential partition {start}..{end} does not fit within {device_end}");
+            return Err(PlanError::RegionOutOfBounds {
+                start: *start,
+                end: *end,
+            });
+        }
+
+        let mut dropped = vec![];
+        self.changes.retain(|change| {
+            if !overflowing(change) {
+                return true;
+            }
+            if let Change::AddPartition {
+                start,
+                end,
+                partition_id,
+                attributes,
+            } = change
+            {
+                debug!("Dropping partition {partition_id} ({start}..{end}) beyond device end {device_end}");
+                dropped.push(Region {
+                    start: *start,
+                    end: *end,
+                    partition_id: Some(*partition_id),
+                    attributes: attributes.clone(),
+                });
+            }
+            false
+        });
+
+        self.usable_end = device_end;
+        self.device_size = device.size();
+        self.block_size = device.logical_block_size();
+
+        Ok(dropped)
+    }
+
+    /// Plan to initialize a clean partition layout
+    ///
+    /// If any partitions are marked to be kept, the partition table is preserved
+    /// and all other partitions are deleted instead.
+    pub fn plan_initialize_disk(&mut self) -> Result<(), PlanError> {
+        if !self.kept_indices.is_empty() {
+            debug!("Planning to reclaim all partitions except {:?}", self.kept_indices);
+            self.clear_changes();
+            for index in 0..self.original_regions.len() {
+                if !self.kept_indices.contains(&index) {
+                    self.plan_delete_partition(index)?;
+                }
+            }
+            return Ok(());
+        }
+
+        debug!("Planning to create new GPT partition table");
+        // Initializing twice must still be able to restore the layout from before the first time
+        let change = match self.changes.front() {
+            Some(change @ Change::InitializeDisk { .. }) => change.clone(),
+            _ => Change::InitializeDisk {
+                original_regions: self.original_regions.clone(),
+                original_partition_ids: self.original_partition_ids.clone(),
+                next_partition_id: self.next_partition_id,
+            },
+        };
+        self.clear_changes(); // Clear any existing changes
+        self.discard_original_layout();
+        self.push_change(change);
+        Ok(())
+    }
+
+    /// Forget the original partitions, starting over with an empty partition table
+    fn discard_original_layout(&mut self) {
+        self.original_regions.clear();
+        self.original_partition_ids.clear();
+        self.next_partition_id = 1;
+        self.wipe_disk = true;
+    }
+
+    pub fn wipe_disk(&self) -> bool {
+        self.wipe_disk
+    }
+    /// Get the next available partition ID and increment the counter
+    pub fn allocate_partition_id(&mut self) -> u32 {
+        while self.reserved_partition_ids.contains(&self.next_partition_id) {
+            self.next_partition_id += 1;
+        }
+        let id = self.next_partition_id;
+        self.next_partition_id += 1;
+        id
+    }
+
+    /// Get the original partition ID for a given index
+    pub fn get_original_partition_id(&self, index: usize) -> Option<u32> {
+        self.original_partition_ids.get(index).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strategy::{AllocationStrategy, PartitionRequest, SizeRequirement, Strategy, StrategyError};
+    use disks::mock::MockDisk;
+    use test_log::test;
+
+    const MB: u64 = 1024 * 1024;
+    const GB: u64 = 1024 * MB;
+
+    /// Creates a mock disk with a typical size of 500GB
+    fn create_mock_disk() -> MockDisk {
+        MockDisk::new(500 * GB)
+    }
+
+    /// Creates a mock disk with an existing Windows installation
+    /// Layout:
+    /// - EFI System Partition (ESP): 100MB
+    /// - Microsoft Reserved: 16MB
+    /// - Windows C: Drive: 200GB
+    /// - Recovery: 500MB
+    fn create_windows_disk() -> MockDisk {
+        let mut disk = MockDisk::new(500 * GB);
+        // All positions are absolute start/end, not sizes
+        disk.add_partition(0, 100 * MB); // ESP: 0 -> 100MB
+        disk.add_partition(100 * MB, 116 * MB); // MSR: 100MB -> 116MB
+        disk.add_partition(116 * MB, 200 * GB + 116 * MB); // Windows: 116MB -> 200.116GB
+        disk.add_partition(200 * GB + 116 * MB, 200 * GB + 616 * MB); // Recovery: 200.116GB -> 200.616GB
+        disk
+    }
+
+    #[test]
+    fn test_preview_add() {
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_mock_disk()));
+
+        // Unaligned requests are rounded to the alignment boundaries
+        let preview = planner.preview_add(MB + 100, 10 * GB - 100).unwrap();
+        assert_eq!((preview.start, preview.end), (MB, 10 * GB));
+        assert!(!planner.has_changes());
+
+        planner.plan_add_partition(MB + 100, 10 * GB - 100).unwrap();
+        let queued = planner.current_layout();
+        assert_eq!((queued[0].start, queued[0].end), (preview.start, preview.end));
+
+        // Previews fail exactly as queueing would
+        assert!(matches!(
+            planner.preview_add(5 * GB, 15 * GB),
+            Err(PlanError::RegionOverlap { .. })
+        ));
+        assert_eq!(planner.changes().len(), 1);
+    }
+
+    #[test]
+    fn test_minimal_operations() {
+        let mut disk = create_windows_disk();
+        disk.add_partition(300 * GB, 320 * GB);
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+        let windows = planner.original_region(2).unwrap().clone();
+
+        // Shrinking twice collapses into the final shrink
+        planner.plan_shrink_partition(2, 150 * GB, 50 * GB).unwrap();
+        planner.plan_shrink_partition(2, 100 * GB, 50 * GB).unwrap();
+
+        // Updating attributes of a partition that is then deleted only deletes it
+        planner
+            .plan_set_attributes(
+                1,
+                PartitionAttributes {
+                    table: TableAttributes::Gpt(GptAttributes::default()),
+                    role: None,
+                    filesystem: None,
+                },
+            )
+            .unwrap();
+        planner.plan_delete_partition(1).unwrap();
+
+        // A shrink back to the current size is a no-op
+        planner.plan_shrink_partition(4, 20 * GB, 0).unwrap();
+
+        planner.plan_add_partition(400 * GB, 410 * GB).unwrap();
+        assert_eq!(planner.changes().len(), 6);
+
+        let operations = planner.minimal_operations();
+        assert_eq!(operations.len(), 3);
+        assert!(matches!(
+            operations[0],
+            Change::DeletePartition { original_index: 1, .. }
+        ));
+        assert!(matches!(
+            operations[1],
+            Change::ResizePartition { original_index: 2, start, end, .. }
+                if start == windows.start && end == windows.start + 100 * GB
+        ));
+        assert!(matches!(operations[2], Change::AddPartition { start, .. } if start == 400 * GB));
+
+        // Without any changes there is nothing to do
+        let planner = Planner::new(&BlockDevice::mock_device(create_windows_disk()));
+        assert!(planner.minimal_operations().is_empty());
+    }
+
+    #[test]
+    fn test_stale_changes() {
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_windows_disk()));
+
+        // Initializing the disk discards deletions queued against the old table
+        planner.plan_delete_partition(3).unwrap();
+        planner.plan_initialize_disk().unwrap();
+        assert_eq!(planner.changes().len(), 1);
+        assert!(matches!(planner.changes()[0], Change::InitializeDisk { .. }));
+        assert!(planner.current_layout().is_empty());
+        assert!(planner.validate().is_ok());
+
+        // Any change that still refers to a vanished partition is reported
+        planner.changes.push_back(Change::DeletePartition {
+            original_index: 3,
+            partition_id: 4,
+        });
+        assert!(matches!(
+            planner.validate().unwrap_err()[..],
+            [PlanError::StaleChange { index: 3 }]
+        ));
+    }
+
+    #[test]
+    fn test_current_layout_sorted() {
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_windows_disk()));
+
+        planner.plan_add_partition(300 * GB, 400 * GB).unwrap();
+        planner.plan_delete_partition(2).unwrap();
+        planner.plan_add_partition(116 * MB, 100 * GB).unwrap();
+        planner.plan_delete_partition(1).unwrap();
+        planner.plan_add_partition(100 * MB, 116 * MB).unwrap();
+
+        let layout = planner.current_layout();
+        assert_eq!(layout.len(), 5);
+        assert!(layout.windows(2).all(|pair| pair[0].start < pair[1].start));
+        assert_eq!(layout[1].start, 100 * MB);
+        assert_eq!(layout.last().unwrap().start, 300 * GB);
+    }
+
+    #[test]
+    fn test_current_layout_invalid_index() {
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_windows_disk()));
+        planner.changes.push_back(Change::DeletePartition {
+            original_index: 10,
+            partition_id: 11,
+        });
+        planner.changes.push_back(Change::ResizePartition {
+            original_index: 12,
+            partition_id: 13,
+            start: 0,
+            end: MB,
+        });
+        planner.plan_delete_partition(3).unwrap();
+        planner.plan_delete_partition(3).unwrap();
+
+        let layout = planner.current_layout();
+        assert_eq!(layout.len(), 3);
+        assert!(matches!(
+            planner.validate().unwrap_err()[..],
+            [
+                PlanError::StaleChange { index: 10 },
+                PlanError::StaleChange { index: 12 }
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_validate_after_offset_change() {
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_mock_disk()));
+        planner.plan_add_partition(0, 100 * GB).unwrap();
+        planner.plan_add_partition(100 * GB, 200 * GB).unwrap();
+        planner.plan_add_partition(200 * GB, 300 * GB).unwrap();
+        assert!(planner.validate().is_ok());
+
+        // Tightening the end retroactively pushes the later partitions out of bounds
+        let planner = planner.with_end_offset(150 * GB);
+        let errors = planner.validate().unwrap_err();
+        let out_of_bounds = errors
+            .iter()
+            .map(|e| match e {
+                PlanError::RegionOutOfBounds { start, .. } => *start,
+                _ => panic!("unexpected error {e:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(out_of_bounds, vec![100 * GB, 200 * GB]);
+    }
+
+    #[test]
+    fn test_no_gaps() {
+        let device = BlockDevice::mock_device(create_mock_disk());
+
+        let mut planner = Planner::new(&device).with_no_gaps(true);
+        planner.plan_add_partition(MB, 10 * GB).unwrap();
+        planner.plan_add_partition(10 * GB, 20 * GB).unwrap();
+        assert!(planner.validate().is_ok());
+
+        // Interior gaps are rejected
+        planner.plan_add_partition(30 * GB, 40 * GB).unwrap();
+        let errors = planner.validate().unwrap_err();
+        assert!(matches!(errors[..], [PlanError::Gap { start, end }] if start == 20 * GB && end == 30 * GB));
+        assert_eq!(errors[0].code(), "gap");
+
+        // Gaps are fine unless disallowed
+        let mut planner = Planner::new(&device);
+        planner.plan_add_partition(MB, 10 * GB).unwrap();
+        planner.plan_add_partition(30 * GB, 40 * GB).unwrap();
+        assert!(planner.validate().is_ok());
+
+        // Trailing free space is only rejected on request
+        let mut planner = Planner::new(&device).with_no_gaps(true).with_trailing_free(false);
+        planner.plan_add_partition(MB, 10 * GB).unwrap();
+        assert!(matches!(planner.validate().unwrap_err()[..], [PlanError::Gap { start, .. }] if start == 10 * GB));
+        planner.plan_add_partition(10 * GB, 500 * GB).unwrap();
+        assert!(planner.validate().is_ok());
+    }
+
+    #[test]
+    fn test_partition_id_mapping() {
+        let mut disk = create_windows_disk();
+        disk.add_partition(300 * GB, 310 * GB).number = 7;
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+
+        assert_eq!(planner.index_of_partition_id(1), Some(0));
+        assert_eq!(planner.index_of_partition_id(4), Some(3));
+        assert_eq!(planner.index_of_partition_id(7), Some(4));
+        assert_eq!(planner.index_of_partition_id(5), None);
+        assert_eq!(planner.index_of_partition_id(0), None);
+
+        let region = planner.original_region(4).unwrap();
+        assert_eq!(region.partition_id, Some(7));
+        assert_eq!(region.start, 300 * GB);
+        assert!(planner.original_region(5).is_none());
+
+        let index = planner.index_of_partition_id(7).unwrap();
+        planner.plan_delete_partition(index).unwrap();
+        assert!(matches!(
+            planner.changes().back(),
+            Some(Change::DeletePartition { partition_id: 7, .. })
+        ));
+    }
+
+    #[test]
+    fn test_render_bar() {
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_mock_disk()));
+        assert_eq!(planner.render_bar(40), format!("[{:.^38}]", "free"));
+
+        let role = |role| {
+            Some(PartitionAttributes {
+                table: TableAttributes::Gpt(GptAttributes::default()),
+                role: Some(role),
+                filesystem: None,
+            })
+        };
+        planner
+            .plan_add_partition_with_attributes(0, GB, role(PartitionRole::Boot))
+            .unwrap();
+        planner
+            .plan_add_partition_with_attributes(GB, 300 * GB, role(PartitionRole::Root))
+            .unwrap();
+
+        for width in [10, 40, 80] {
+            let bar = planner.render_bar(width);
+            assert_eq!(bar.chars().count(), width);
+            assert!(bar.starts_with('['));
+            assert!(bar.ends_with(']'));
+            assert_eq!(bar.matches('|').count(), 2);
+        }
+
+        let bar = planner.render_bar(80);
+        assert!(bar.contains("root"));
+        assert!(bar.contains("free"));
+    }
+
+    #[test]
+    fn test_esp_max_start() {
+        const TB: u64 = 1024 * GB;
+        let esp = || {
+            Some(PartitionAttributes {
+                table: TableAttributes::Gpt(GptAttributes {
+                    type_guid: gpt::partition_types::EFI,
+                    ..Default::default()
+                }),
+                role: None,
+                filesystem: None,
+            })
+        };
+
+        // Only ESPs are held to the limit
+        let mut planner = Planner::new(&BlockDevice::mock_device(MockDisk::new(4 * TB))).with_esp_max_start(2 * TB);
+        assert_eq!(planner.esp_max_start(), Some(2 * TB));
+        assert!(matches!(
+            planner.plan_add_partition_with_attributes(3 * TB, 3 * TB + GB, esp()),
+            Err(PlanError::EspTooFar { start, limit }) if start == 3 * TB && limit == 2 * TB
+        ));
+        assert!(planner.plan_add_partition(3 * TB, 3 * TB + GB).is_ok());
+        assert!(planner.plan_add_partition_with_attributes(MB, GB, esp()).is_ok());
+
+        // A large disk with a small gap at the front and plenty of room past 2TiB
+        let mut disk = MockDisk::new(4 * TB);
+        disk.add_partition(GB, 2 * TB + GB);
+        let device = BlockDevice::mock_device(disk);
+
+        let strategy = |esp_size| {
+            let mut strategy = Strategy::new(AllocationStrategy::LargestFree);
+            strategy.add_request(PartitionRequest {
+                attributes: esp(),
+                ..PartitionRequest::new(SizeRequirement::Exact(esp_size))
+            });
+            strategy.add_request(PartitionRequest::new(SizeRequirement::Remaining));
+            strategy
+        };
+
+        // The ESP is kept within the first 2TiB, while the rest goes to the larger region
+        let mut planner = Planner::new(&device).with_start_offset(MB).with_esp_max_start(2 * TB);
+        assert!(strategy(512 * MB).apply(&mut planner).is_ok());
+        let layout = planner.current_layout();
+        let esp_region = layout.iter().find(|r| r.partition_id == Some(2)).unwrap();
+        assert_eq!(esp_region.start, MB);
+        assert!(esp_region.start <= 2 * TB);
+        let root_region = layout.iter().find(|r| r.partition_id == Some(3)).unwrap();
+        assert_eq!(root_region.start, 2 * TB + GB);
+
+        // Without room at the front, the ESP would land beyond the limit and is rejected
+        let mut planner = Planner::new(&device).with_start_offset(MB).with_esp_max_start(2 * TB);
+        assert!(matches!(
+            strategy(2 * GB).apply(&mut planner),
+            Err(StrategyError::Rejected {
+                index: 0,
+                source: PlanError::EspTooFar { .. }
+            })
+        ));
+        assert!(planner.changes().is_empty());
+
+        // Firmware without the limit takes it anywhere
+        let mut planner = Planner::new(&device).with_start_offset(MB);
+        assert!(strategy(2 * GB).apply(&mut planner).is_ok());
+    }
+
+    #[test]
+    fn test_first_partition_offset() {
+        let disk = create_mock_disk();
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk)).with_first_partition_offset(16 * MB);
+        assert_eq!(planner.offsets().0, 0);
+        assert_eq!(planner.allocation_start(), 16 * MB);
+
+        assert!(matches!(
+            planner.plan_add_partition(0, GB),
+            Err(PlanError::RegionOutOfBounds { .. })
+        ));
+
+        let mut strategy = Strategy::new(AllocationStrategy::LargestFree);
+        strategy.add_request(PartitionRequest::new(SizeRequirement::Remaining));
+        assert!(strategy.apply(&mut planner).is_ok());
+
+        let layout = planner.current_layout();
+        assert_eq!(layout.len(), 1);
+        assert_eq!(layout[0].start, 16 * MB);
+        assert_eq!(layout[0].end, 500 * GB);
+    }
+
+    #[test]
+    fn test_set_attributes() {
+        let disk = create_windows_disk();
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+
+        let bootable = |flags| PartitionAttributes {
+            table: TableAttributes::Gpt(GptAttributes {
+                flags,
+                ..Default::default()
+            }),
+            role: None,
+            filesystem: None,
+        };
+        let flags = |planner: &Planner| {
+            planner.current_layout()[0]
+                .attributes
+                .as_ref()
+                .and_then(|a| a.table.as_gpt())
+                .map(|gpt| gpt.flags)
+        };
+
+        assert_eq!(flags(&planner), None);
+
+        assert!(
+            planner
+                .plan_set_attributes(0, bootable(GptAttributes::LEGACY_BIOS_BOOTABLE))
+                .is_ok()
+        );
+        assert_eq!(flags(&planner), Some(GptAttributes::LEGACY_BIOS_BOOTABLE));
+        assert_eq!(planner.current_layout().len(), 4);
+
+        assert!(planner.plan_set_attributes(0, bootable(0)).is_ok());
+        assert_eq!(flags(&planner), Some(0));
+
+        assert!(planner.plan_set_attributes(4, bootable(0)).is_err());
+        planner.plan_delete_partition(1).unwrap();
+        assert!(planner.plan_set_attributes(1, bootable(0)).is_err());
+    }
+
+    #[test]
+    fn test_describe_filesystem() {
+        let disk = create_mock_disk();
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+
+        let attributes = PartitionAttributes {
+            table: TableAttributes::Gpt(GptAttributes::default()),
+            role: None,
+            filesystem: Some(types::Filesystem::Standard {
+                filesystem_type: types::StandardFilesystemType::Xfs,
+                label: None,
+                uuid: None,
+            }),
+        };
+        planner
+            .plan_add_partition_with_attributes(0, 100 * GB, Some(attributes))
+            .unwrap();
+        planner.plan_add_partition(100 * GB, 200 * GB).unwrap();
+
+        let changes = planner.changes();
+        assert!(changes[0].describe(planner.usable_size()).contains(" xfs ("));
+        assert!(!changes[1].describe(planner.usable_size()).contains("xfs"));
+        assert!(planner.describe_changes().contains("100.0GiB xfs"));
+    }
+
+    #[test]
+    fn test_reset_no_stderr() {
+        // The harness captures stderr, so reset in a child process that doesn't
+        if std::env::var_os("PLANNER_RESET_CHILD").is_some() {
+            let mut planner = Planner::new(&BlockDevice::mock_device(create_windows_disk()));
+            planner.plan_delete_partition(2).unwrap();
+            assert_eq!(planner.reset(), 1);
+            return;
+        }
+
+        // With logging off, anything on stderr was printed directly
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "planner::tests::test_reset_no_stderr", "--nocapture"])
+            .env("PLANNER_RESET_CHILD", "1")
+            .env("RUST_LOG", "off")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert!(output.stderr.is_empty(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+
+    #[test]
+    fn test_reset() {
+        let device = BlockDevice::mock_device(create_windows_disk());
+
+        // Clearing changes keeps the original layout
+        let mut planner = Planner::new(&device);
+        planner.plan_delete_partition(2).unwrap();
+        planner.plan_add_partition(116 * MB, 100 * GB).unwrap();
+        planner.clear_changes();
+        assert!(!planner.has_changes());
+        assert_eq!(planner.current_layout().len(), 4);
+
+        // Reset is the same operation
+        planner.plan_delete_partition(3).unwrap();
+        planner.plan_delete_partition(2).unwrap();
+        assert_eq!(planner.reset(), 2);
+        assert!(!planner.has_changes());
+        assert_eq!(planner.reset(), 0);
+        assert_eq!(planner.current_layout().len(), 4);
+
+        // Clearing changes after initializing does not restore the original partitions
+        planner.plan_initialize_disk().unwrap();
+        planner.clear_changes();
+        assert!(planner.current_layout().is_empty());
+        assert!(planner.wipe_disk());
+
+        // Resetting to the device does
+        planner.reset_to_device(&device);
+        assert!(!planner.has_changes());
+        assert!(!planner.wipe_disk());
+        assert_eq!(planner.original_regions().len(), 4);
+        assert_eq!(planner.current_layout().len(), 4);
+        assert_eq!(planner.allocate_partition_id(), 5);
+    }
+
+    #[test]
+    fn test_bad_regions() {
+        let disk = create_windows_disk();
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+
+        assert!(planner.mark_bad_region(300 * GB, 300 * GB + 4096).is_ok());
+        assert!(planner.mark_bad_region(400 * GB, 600 * GB).is_err());
+
+        assert!(matches!(
+            planner.plan_add_partition(250 * GB, 350 * GB),
+            Err(PlanError::BadRegion { .. })
+        ));
+        assert!(planner.plan_add_partition(250 * GB, 300 * GB).is_ok());
+
+        let map = planner.layout_map();
+        let kinds = map.iter().map(|s| s.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                SegmentKind::Partition,
+                SegmentKind::Partition,
+                SegmentKind::Partition,
+                SegmentKind::Partition,
+                SegmentKind::Free,
+                SegmentKind::Partition,
+                SegmentKind::Bad,
+                SegmentKind::Free,
+            ]
+        );
+        assert_eq!(map[6].region.start, 300 * GB);
+        assert_eq!(map[6].region.end, 300 * GB + 4096);
+        assert_eq!(map.last().unwrap().region.end, 500 * GB);
     }
 
-    /// Plan to initialize a clean partition layout
-    pub fn plan_initialize_disk(&mut self) -> Result<(), PlanError> {
-        debug!("Planning to create new GPT partition table");
-        self.changes.clear(); // Clear any existing changes
-        self.original_regions.clear(); // Clear original partitions
-        self.original_partition_ids.clear();
-        self.next_partition_id = 1;
-        self.wipe_disk = true;
-        Ok(())
+    #[test]
+    fn test_unusable_slivers() {
+        let mut disk = create_mock_disk();
+        disk.add_partition(0, 10 * GB);
+        disk.add_partition(10 * GB + 512 * 1024, 20 * GB);
+        let planner = Planner::new(&BlockDevice::mock_device(disk));
+
+        let map = planner.layout_map();
+        let segments = map.iter().map(|s| (s.kind, s.usable)).collect::<Vec<_>>();
+        assert_eq!(
+            segments,
+            vec![
+                (SegmentKind::Partition, false),
+                (SegmentKind::Free, false),
+                (SegmentKind::Partition, false),
+                (SegmentKind::Free, true),
+            ]
+        );
+        assert_eq!(map[1].region.size(), 512 * 1024);
+
+        // A finer alignment makes the sliver usable
+        let map = planner.with_alignment(4096).unwrap().layout_map();
+        assert!(map[1].usable);
     }
 
-    pub fn wipe_disk(&self) -> bool {
-        self.wipe_disk
-    }
-    /// Get the next available partition ID and increment the counter
-    pub fn allocate_partition_id(&mut self) -> u32 {
-        let id = self.next_partition_id;
-        self.next_partition_id += 1;
-        id
+    #[test]
+    fn test_error_codes() {
+        let cases = [
+            (PlanError::RegionOverlap { start: 0, end: 1 }, "region_overlap"),
+            (PlanError::RegionOutOfBounds { start: 0, end: 1 }, "out_of_bounds"),
+            (PlanError::NoFreeRegions, "no_free_regions"),
+            (
+                PlanError::SharedBlock {
+                    start: 0,
+                    end: 1,
+                    block_size: 4096,
+                },
+                "shared_block",
+            ),
+            (PlanError::PartitionKept { index: 0 }, "partition_kept"),
+            (PlanError::BadRegion { start: 0, end: 1 }, "bad_region"),
+            (PlanError::RegionReserved { start: 0, end: 1 }, "region_reserved"),
+            (
+                PlanError::ShrinkBelowUsed {
+                    index: 0,
+                    requested: 0,
+                    minimum: 1,
+                },
+                "shrink_below_used",
+            ),
+            (PlanError::PartitionNumberTaken { number: 1 }, "partition_number_taken"),
+            (
+                PlanError::NotSectorAligned {
+                    offset: 512,
+                    sector_size: 4096,
+                },
+                "not_sector_aligned",
+            ),
+            (
+                PlanError::InvalidAlignment {
+                    alignment: 512,
+                    sector_size: 4096,
+                },
+                "invalid_alignment",
+            ),
+            (PlanError::InvalidPercentage { percent: 0 }, "invalid_percentage"),
+        ];
+
+        for (error, code) in cases {
+            assert_eq!(error.code(), code);
+        }
     }
 
-    /// Get the original partition ID for a given index
-    pub fn get_original_partition_id(&self, index: usize) -> Option<u32> {
-        self.original_partition_ids.get(index).copied()
+    #[test]
+    fn test_resize_partition() {
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_windows_disk()));
+        let recovery = planner.original_regions()[3].clone();
+
+        // Grow the recovery partition into the free space after it
+        assert!(planner.plan_resize_partition(3, recovery.end + 10 * GB).is_ok());
+        assert_eq!(planner.current_layout()[3].end, recovery.end + 10 * GB);
+        assert_eq!(planner.current_layout()[3].start, recovery.start);
+
+        // Growing the MSR would run into the Windows partition
+        let msr = planner.original_regions()[1].clone();
+        assert!(matches!(
+            planner.plan_resize_partition(1, msr.end + GB),
+            Err(PlanError::RegionOverlap { .. })
+        ));
+
+        // Tiny or misaligned partitions are refused
+        assert!(matches!(
+            planner.plan_resize_partition(1, msr.start),
+            Err(PlanError::ShrinkBelowUsed { index: 1, .. })
+        ));
+        assert!(matches!(
+            planner.plan_resize_partition(3, recovery.end + 1),
+            Err(PlanError::RegionOutOfBounds { .. })
+        ));
+
+        // The resize can be undone
+        assert!(matches!(planner.undo(), Some(Change::ResizePartition { .. })));
+        assert_eq!(planner.current_layout()[3].end, recovery.end);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use disks::mock::MockDisk;
-    use test_log::test;
+    #[test]
+    fn test_shrink_partition() {
+        let disk = create_windows_disk();
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
 
-    const MB: u64 = 1024 * 1024;
-    const GB: u64 = 1024 * MB;
+        // Windows partition is 200GB with 150GB in use
+        assert!(matches!(
+            planner.plan_shrink_partition(2, 150 * GB, 150 * GB),
+            Err(PlanError::ShrinkBelowUsed { index: 2, minimum, .. }) if minimum == 150 * GB + SHRINK_HEADROOM
+        ));
+        assert!(!planner.has_changes());
 
-    /// Creates a mock disk with a typical size of 500GB
-    fn create_mock_disk() -> MockDisk {
-        MockDisk::new(500 * GB)
+        // Growing isn't shrinking
+        assert!(planner.plan_shrink_partition(2, 300 * GB, 150 * GB).is_err());
+
+        assert!(planner.plan_shrink_partition(2, 160 * GB, 150 * GB).is_ok());
+        let layout = planner.current_layout();
+        assert_eq!(layout.len(), 4);
+        assert_eq!(layout[2].end, 116 * MB + 160 * GB);
+
+        // The freed space can now be used
+        assert!(
+            planner
+                .plan_add_partition(116 * MB + 160 * GB, 116 * MB + 200 * GB)
+                .is_ok()
+        );
     }
 
-    /// Creates a mock disk with an existing Windows installation
-    /// Layout:
-    /// - EFI System Partition (ESP): 100MB
-    /// - Microsoft Reserved: 16MB
-    /// - Windows C: Drive: 200GB
-    /// - Recovery: 500MB
-    fn create_windows_disk() -> MockDisk {
-        let mut disk = MockDisk::new(500 * GB);
-        // All positions are absolute start/end, not sizes
-        disk.add_partition(0, 100 * MB); // ESP: 0 -> 100MB
-        disk.add_partition(100 * MB, 116 * MB); // MSR: 100MB -> 116MB
-        disk.add_partition(116 * MB, 200 * GB + 116 * MB); // Windows: 116MB -> 200.116GB
-        disk.add_partition(200 * GB + 116 * MB, 200 * GB + 616 * MB); // Recovery: 200.116GB -> 200.616GB
-        disk
+    #[test]
+    fn test_region_to_sectors() {
+        let region = Region::new(MB, 2 * MB);
+        assert_eq!(region.to_sectors(512), Ok((2048, 4096)));
+        assert_eq!(region.to_sectors(4096), Ok((256, 512)));
+
+        let region = Region::new(MB + 512, 2 * MB);
+        assert_eq!(region.to_sectors(512), Ok((2049, 4096)));
+        assert_eq!(
+            region.to_sectors(4096),
+            Err(SectorError::Misaligned {
+                offset: MB + 512,
+                block_size: 4096
+            })
+        );
+
+        let region = Region::new(0, MB + 100);
+        assert_eq!(
+            region.to_sectors(512),
+            Err(SectorError::Misaligned {
+                offset: MB + 100,
+                block_size: 512
+            })
+        );
+        assert_eq!(region.to_sectors(0), Err(SectorError::InvalidBlockSize));
+    }
+
+    #[test]
+    fn test_trim_to_device() {
+        let large = BlockDevice::mock_device(MockDisk::new(1024 * GB));
+        let small = BlockDevice::mock_device(create_mock_disk());
+
+        let mut planner = Planner::new(&large);
+        planner.plan_initialize_disk().unwrap();
+        planner
+            .plan_add_partition_with_attributes(
+                0,
+                100 * GB,
+                Some(PartitionAttributes {
+                    table: TableAttributes::Gpt(GptAttributes::default()),
+                    role: Some(PartitionRole::Root),
+                    filesystem: None,
+                }),
+            )
+            .unwrap();
+        planner.plan_add_partition(100 * GB, 400 * GB).unwrap();
+        planner.plan_add_partition(400 * GB, 1000 * GB).unwrap();
+
+        let dropped = planner.trim_to_device(&small).unwrap();
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].start, 400 * GB);
+        assert_eq!(dropped[0].partition_id, Some(3));
+
+        let layout = planner.current_layout();
+        assert_eq!(layout.len(), 2);
+        assert_eq!(layout[1].end, 400 * GB);
+        assert_eq!(planner.offsets().1, 500 * GB);
+
+        // Essential partitions that do not fit are an error
+        let mut planner = Planner::new(&large);
+        planner.plan_initialize_disk().unwrap();
+        planner.plan_add_partition(0, 100 * GB).unwrap();
+        planner
+            .plan_add_partition_with_attributes(
+                100 * GB,
+                800 * GB,
+                Some(PartitionAttributes {
+                    table: TableAttributes::Gpt(GptAttributes::default()),
+                    role: Some(PartitionRole::Home),
+                    filesystem: None,
+                }),
+            )
+            .unwrap();
+        assert!(matches!(
+            planner.trim_to_device(&small),
+            Err(PlanError::RegionOutOfBounds { .. })
+        ));
+        assert_eq!(planner.current_layout().len(), 2);
+
+        // The backup GPT stays reserved, so a partition ending at the raw device end no longer fits
+        let mut planner = Planner::new(&large).with_gpt_entries(128);
+        planner.plan_initialize_disk().unwrap();
+        planner.plan_add_partition(0, 100 * GB).unwrap();
+        planner.plan_add_partition(100 * GB, 500 * GB).unwrap();
+        let dropped = planner.trim_to_device(&small).unwrap();
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].end, 500 * GB);
+        assert_eq!(planner.offsets().1, 500 * GB - gpt_backup_size(128, 512));
+
+        // Resized partitions that no longer fit are an error
+        let mut disk = MockDisk::new(1024 * GB);
+        disk.add_partition(MB, 100 * GB);
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+        planner.plan_resize_partition(0, 600 * GB).unwrap();
+        assert!(matches!(
+            planner.trim_to_device(&small),
+            Err(PlanError::RegionOutOfBounds { .. })
+        ));
+        assert_eq!(planner.offsets().1, 1024 * GB);
     }
 
     #[test]
@@ -551,6 +2935,327 @@ mod tests {
         assert_eq!(layout[1].size(), 8 * GB);
     }
 
+    #[test]
+    fn test_add_partition_in_largest_free() {
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_windows_disk()));
+
+        // The size is rounded up and placed at the start of the trailing free space
+        assert_eq!(planner.plan_add_partition_in_largest_free(50 * GB - 1).unwrap(), 5);
+        let added = planner.current_layout()[4].clone();
+        assert_eq!(added.start, 200 * GB + 616 * MB);
+        assert_eq!(added.size(), 50 * GB);
+
+        // Just past a boundary still rounds up, never down
+        assert_eq!(planner.plan_add_partition_in_largest_free(50 * GB + 1).unwrap(), 6);
+        let above = planner.current_layout()[5].clone();
+        assert_eq!(above.start, added.end);
+        assert_eq!(above.size(), 50 * GB + MB);
+
+        assert_eq!(planner.plan_add_partition_in_largest_free(100).unwrap(), 7);
+        assert_eq!(planner.current_layout()[6].size(), MB);
+
+        // Nothing can hold more than the remaining space
+        assert!(matches!(
+            planner.plan_add_partition_in_largest_free(400 * GB),
+            Err(PlanError::NoFreeRegions)
+        ));
+        assert_eq!(planner.changes().len(), 3);
+
+        // An unaligned free region starts at the next boundary, not the nearest
+        let mut disk = MockDisk::new(100 * GB);
+        disk.add_partition(MB, 10 * MB + 100 * 1024);
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+        planner.plan_add_partition_in_largest_free(GB).unwrap();
+        assert_eq!(planner.current_layout()[1].start, 11 * MB);
+    }
+
+    #[test]
+    fn test_gpt_entries() {
+        let device = BlockDevice::mock_device(create_mock_disk());
+
+        let standard = Planner::new(&device).with_gpt_entries(DEFAULT_GPT_ENTRIES);
+        assert_eq!(standard.offsets().1, 500 * GB - 16 * 1024 - 512);
+
+        // Fewer entries shrink the backup table, leaving more space at the tail
+        let small = Planner::new(&device).with_gpt_entries(32);
+        assert_eq!(small.offsets().1, 500 * GB - 4 * 1024 - 512);
+        assert!(small.usable_size() > standard.usable_size());
+
+        // An explicit end offset below the backup table is kept
+        let offset = Planner::new(&device).with_end_offset(400 * GB).with_gpt_entries(32);
+        assert_eq!(offset.offsets().1, 400 * GB);
+
+        assert_eq!(gpt_backup_size(128, 4096), 16 * 1024 + 4096);
+        assert_eq!(gpt_backup_size(1, 4096), 2 * 4096);
+    }
+
+    #[test]
+    fn test_overlay() {
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_windows_disk()));
+        planner.plan_delete_partition(3).unwrap();
+
+        let view = planner.with_overlay(Change::AddPartition {
+            start: 300 * GB,
+            end: 400 * GB,
+            partition_id: 5,
+            attributes: None,
+        });
+        let layout = view.current_layout();
+        assert_eq!(layout.len(), 4);
+        assert_eq!(layout[3].start, 300 * GB);
+        assert_eq!(layout[3].partition_id, Some(5));
+        assert_eq!(view.free_regions().len(), 2);
+
+        // The planner itself is untouched
+        assert_eq!(planner.current_layout().len(), 3);
+        assert_eq!(planner.changes().len(), 1);
+        assert_eq!(planner.free_regions().len(), 1);
+
+        // Previewing an initialization shows an empty disk
+        let view = planner.with_overlay(Change::InitializeDisk {
+            original_regions: vec![],
+            original_partition_ids: vec![],
+            next_partition_id: 1,
+        });
+        assert!(view.current_layout().is_empty());
+        assert_eq!(view.free_regions().len(), 1);
+    }
+
+    #[test]
+    fn test_partial_block_device() {
+        // Loop devices may be backed by files that aren't a whole number of blocks
+        let disk = MockDisk::new(500 * GB + 1024).with_logical_block_size(4096);
+        let device = BlockDevice::mock_device(disk);
+        assert_eq!(device.size() % 4096, 1024);
+
+        let planner = Planner::new(&device);
+        assert_eq!(planner.offsets(), (0, 500 * GB));
+        let region = planner.free_regions().pop().unwrap();
+        assert_eq!(region.end % 4096, 0);
+
+        // Trimming to a smaller device floors its end too
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_mock_disk()));
+        planner
+            .trim_to_device(&BlockDevice::mock_device(
+                MockDisk::new(10 * GB + 3072).with_logical_block_size(4096),
+            ))
+            .unwrap();
+        assert_eq!(planner.offsets(), (0, 10 * GB));
+    }
+
+    #[test]
+    fn test_plan_add_partitions() {
+        let device = BlockDevice::mock_device(create_mock_disk());
+        let mut planner = Planner::new(&device);
+
+        // The second spec overlaps the first, so none are queued
+        let result = planner.plan_add_partitions(&[
+            (MB, 100 * MB, None),
+            (50 * MB, 200 * MB, None),
+            (200 * MB, 300 * MB, None),
+        ]);
+        assert!(matches!(result, Err(PlanError::RegionOverlap { .. })));
+        assert!(!planner.has_changes());
+        assert_eq!(planner.allocate_partition_id(), 1);
+
+        let mut planner = Planner::new(&device);
+        planner
+            .plan_add_partitions(&[
+                (MB, 100 * MB, None),
+                (100 * MB, 200 * MB, None),
+                (200 * MB, 300 * MB, None),
+            ])
+            .unwrap();
+        let ids = planner
+            .current_layout()
+            .iter()
+            .map(|r| r.partition_id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![Some(1), Some(2), Some(3)]);
+    }
+
+    #[test]
+    fn test_sector_alignment() {
+        let device = BlockDevice::mock_device(MockDisk::new(500 * GB).with_logical_block_size(4096));
+        let planner = Planner::new(&device);
+        assert_eq!(planner.sector_size(), 4096);
+        assert_eq!(planner.alignment(), PARTITION_ALIGNMENT);
+
+        // Overrides must be whole sectors
+        assert!(matches!(
+            Planner::new(&device).with_alignment(512),
+            Err(PlanError::InvalidAlignment {
+                alignment: 512,
+                sector_size: 4096
+            })
+        ));
+        assert!(Planner::new(&device).with_alignment(0).is_err());
+        let mut planner = Planner::new(&device).with_alignment(64 * 1024).unwrap();
+        assert_eq!(planner.alignment(), 64 * 1024);
+
+        // The default alignment comes from the planner
+        planner.plan_add_partition(128 * 1024 - 4096, 10 * GB).unwrap();
+        assert_eq!(planner.current_layout()[0].start, 128 * 1024);
+
+        // A finer explicit alignment can't split sectors
+        assert!(matches!(
+            planner.plan_add_partition_aligned(20 * GB + 512, 30 * GB, None, 512),
+            Err(PlanError::NotSectorAligned {
+                offset,
+                sector_size: 4096
+            }) if offset == 20 * GB + 512
+        ));
+        assert!(
+            planner
+                .plan_add_partition_aligned(20 * GB + 4096, 30 * GB, None, 4096)
+                .is_ok()
+        );
+
+        // 512 byte sector devices accept any multiple of 512
+        let device = BlockDevice::mock_device(MockDisk::new(500 * GB));
+        assert!(Planner::new(&device).with_alignment(1536).is_ok());
+        assert!(Planner::new(&device).with_alignment(1000).is_err());
+    }
+
+    #[test]
+    fn test_free_regions() {
+        // An empty disk is one free region spanning the usable area
+        let planner = Planner::new(&BlockDevice::mock_device(create_mock_disk())).with_start_offset(MB);
+        let free = planner.free_regions();
+        assert_eq!(free.len(), 1);
+        assert_eq!((free[0].start, free[0].end), (MB, 500 * GB));
+        assert_eq!(free[0].partition_id, None);
+
+        // The Windows layout only has space after the recovery partition
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_windows_disk()));
+        let free = planner.free_regions();
+        assert_eq!(free.len(), 1);
+        assert_eq!((free[0].start, free[0].end), (200 * GB + 616 * MB, 500 * GB));
+
+        // Deleting the ESP and MSR frees the space before the first partition as one region
+        planner.plan_delete_partition(0).unwrap();
+        planner.plan_delete_partition(1).unwrap();
+        planner.plan_add_partition(300 * GB, 400 * GB).unwrap();
+        let free = planner
+            .free_regions()
+            .iter()
+            .map(|r| (r.start, r.end))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            free,
+            vec![(0, 116 * MB), (200 * GB + 616 * MB, 300 * GB), (400 * GB, 500 * GB)]
+        );
+
+        // Slivers too small for a partition are not reported
+        let mut disk = create_mock_disk();
+        disk.add_partition(0, 100 * MB);
+        disk.add_partition(100 * MB + 4096, 500 * GB);
+        let planner = Planner::new(&BlockDevice::mock_device(disk));
+        assert!(planner.free_regions().is_empty());
+    }
+
+    #[test]
+    fn test_divide() {
+        let region = Region::new(MB + 5, 400 * GB + 3 * MB);
+        let pieces = region.divide(4, MB);
+        assert_eq!(pieces.len(), 4);
+        assert_eq!(pieces[0].start, 2 * MB);
+        for pair in pieces.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+        }
+        for piece in &pieces[..3] {
+            assert_eq!(piece.size(), 100 * GB);
+        }
+        assert_eq!(pieces[3].size(), 100 * GB + MB);
+        assert!(pieces.iter().all(|p| p.start % MB == 0 && p.end % MB == 0));
+        assert_eq!(pieces[3].end, 400 * GB + 3 * MB);
+
+        assert!(region.divide(0, MB).is_empty());
+        assert!(Region::new(0, 3 * MB).divide(4, MB).is_empty());
+    }
+
+    #[test]
+    fn test_containment() {
+        let region = Region::new(MB, 100 * MB);
+
+        assert!(!region.contains_position(MB - 1));
+        assert!(region.contains_position(MB));
+        assert!(region.contains_position(100 * MB - 1));
+        assert!(!region.contains_position(100 * MB));
+
+        assert!(region.contains_region(&region));
+        assert!(region.contains_region(&Region::new(MB, 2 * MB)));
+        assert!(region.contains_region(&Region::new(99 * MB, 100 * MB)));
+        assert!(!region.contains_region(&Region::new(MB - 1, 2 * MB)));
+        assert!(!region.contains_region(&Region::new(99 * MB, 100 * MB + 1)));
+        assert!(!Region::new(2 * MB, 3 * MB).contains_region(&region));
+    }
+
+    #[test]
+    fn test_intersection_and_gap() {
+        let first = Region::new(MB, 100 * MB);
+
+        // Disjoint, in either order
+        let disjoint = Region::new(150 * MB, 200 * MB);
+        assert!(first.intersection(&disjoint).is_none());
+        assert_eq!(first.gap_to(&disjoint), Some(50 * MB));
+        assert_eq!(disjoint.gap_to(&first), Some(50 * MB));
+
+        // Touching
+        let touching = Region::new(100 * MB, 200 * MB);
+        assert!(first.intersection(&touching).is_none());
+        assert_eq!(first.gap_to(&touching), Some(0));
+        assert_eq!(touching.gap_to(&first), Some(0));
+
+        // Nested
+        let nested = Region::new(10 * MB, 20 * MB);
+        assert!(first.intersection(&nested).unwrap().same_extent(&nested));
+        assert!(nested.intersection(&first).unwrap().same_extent(&nested));
+        assert_eq!(first.gap_to(&nested), None);
+
+        // Partially overlapping
+        let partial = Region::new(50 * MB, 150 * MB);
+        let shared = first.intersection(&partial).unwrap();
+        assert!(shared.same_extent(&Region::new(50 * MB, 100 * MB)));
+        assert!(partial.intersection(&first).unwrap().same_extent(&shared));
+        assert_eq!(partial.gap_to(&first), None);
+    }
+
+    #[test]
+    fn test_clamp_to() {
+        let mut region = Region::new(400 * MB, 600 * MB);
+        region.partition_id = Some(2);
+
+        // Overhanging the usable end
+        let clamped = region.clamp_to(MB, 500 * MB).unwrap();
+        assert!(clamped.same_extent(&Region::new(400 * MB, 500 * MB)));
+        assert_eq!(clamped.partition_id, Some(2));
+
+        // Already within bounds
+        assert!(region.clamp_to(MB, GB).unwrap().same_extent(&region));
+
+        // Entirely outside, or merely touching
+        assert!(region.clamp_to(700 * MB, GB).is_none());
+        assert!(region.clamp_to(MB, 400 * MB).is_none());
+    }
+
+    #[test]
+    fn test_same_extent() {
+        let plain = Region::new(MB, 100 * MB);
+        let mut described = Region::new(MB, 100 * MB);
+        described.partition_id = Some(3);
+        described.attributes = Some(PartitionAttributes {
+            table: TableAttributes::Gpt(GptAttributes::default()),
+            role: Some(PartitionRole::Root),
+            filesystem: None,
+        });
+
+        assert!(plain.same_extent(&described));
+        assert!(described.same_extent(&plain));
+        assert!(!plain.same_extent(&Region::new(MB, 101 * MB)));
+        assert!(!plain.same_extent(&Region::new(2 * MB, 100 * MB)));
+    }
+
     #[test]
     fn test_region_validation() {
         let disk = create_mock_disk();
@@ -570,6 +3275,145 @@ mod tests {
         ));
     }
 
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let device = BlockDevice::mock_device(create_windows_disk());
+        let mut planner = Planner::new(&device);
+        planner.plan_delete_partition(2).unwrap();
+        let attributes = PartitionAttributes {
+            table: TableAttributes::Gpt(GptAttributes {
+                type_guid: gpt::partition_types::LINUX_FS,
+                ..Default::default()
+            }),
+            role: Some(PartitionRole::Root),
+            filesystem: None,
+        };
+        planner
+            .plan_add_partition_with_attributes(116 * MB, 100 * GB, Some(attributes))
+            .unwrap();
+
+        let json = serde_json::to_string(planner.changes()).unwrap();
+        let changes: Vec<Change> = serde_json::from_str(&json).unwrap();
+
+        let mut resumed = Planner::new(&device);
+        resumed.replay_changes(changes).unwrap();
+        let expected = planner.current_layout();
+        let layout = resumed.current_layout();
+        assert_eq!(layout.len(), expected.len());
+        for (region, expected) in layout.iter().zip(&expected) {
+            assert!(region.same_extent(expected));
+            assert_eq!(region.partition_id, expected.partition_id);
+            assert_eq!(
+                region.attributes.as_ref().and_then(|a| a.role.clone()),
+                expected.attributes.as_ref().and_then(|a| a.role.clone())
+            );
+        }
+        assert_eq!(resumed.allocate_partition_id(), planner.allocate_partition_id());
+
+        // Regions and attributes round-trip by themselves, keeping well-known types
+        let region: Region = serde_json::from_str(&serde_json::to_string(&expected[2]).unwrap()).unwrap();
+        let type_guid = region
+            .attributes
+            .and_then(|a| a.table.as_gpt().map(|g| g.type_guid.clone()));
+        assert_eq!(
+            type_guid,
+            expected[2]
+                .attributes
+                .as_ref()
+                .and_then(|a| a.table.as_gpt().map(|g| g.type_guid.clone()))
+        );
+
+        // Changes that no longer fit are rejected without touching the planner
+        let mut small = Planner::new(&BlockDevice::mock_device(MockDisk::new(50 * GB)));
+        assert!(
+            small
+                .replay_changes(serde_json::from_str::<Vec<Change>>(&json).unwrap())
+                .is_err()
+        );
+        assert!(!small.has_changes());
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let device = BlockDevice::mock_device(create_windows_disk());
+        let mut planner = Planner::new(&device);
+        planner.plan_delete_partition(3).unwrap();
+        let state = planner.snapshot();
+        let before = planner.current_layout();
+
+        // Risky operations, including wiping the disk
+        planner.plan_add_partition(200 * GB + 116 * MB, 300 * GB).unwrap();
+        planner.plan_initialize_disk().unwrap();
+        planner.plan_add_partition(0, 100 * GB).unwrap();
+        assert!(planner.wipe_disk());
+
+        planner.restore(state.clone());
+        let after = planner.current_layout();
+        assert_eq!(after.len(), before.len());
+        for (region, expected) in after.iter().zip(&before) {
+            assert!(region.same_extent(expected));
+            assert_eq!(region.partition_id, expected.partition_id);
+        }
+        assert!(!planner.wipe_disk());
+        assert_eq!(planner.changes().len(), 1);
+        assert!(!planner.can_redo());
+        assert_eq!(planner.allocate_partition_id(), 5);
+
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_string(&state).unwrap();
+            let mut resumed = Planner::new(&device);
+            resumed.restore(serde_json::from_str(&json).unwrap());
+            assert_eq!(resumed.current_layout().len(), before.len());
+        }
+    }
+
+    #[test]
+    fn test_renumbering_impact() {
+        let device = BlockDevice::mock_device(create_windows_disk());
+        let mut planner = Planner::new(&device);
+        assert!(planner.renumbering_impact().is_empty());
+
+        planner.plan_delete_partition(1).unwrap();
+        assert_eq!(planner.renumbering_impact(), vec![(3, 2), (4, 3)]);
+
+        // Deleting the last partition moves nothing further
+        planner.plan_delete_partition(3).unwrap();
+        assert_eq!(planner.renumbering_impact(), vec![(3, 2)]);
+    }
+
+    #[test]
+    fn test_destructive_changes() {
+        let mut disk = create_mock_disk();
+        disk.add_partition(0, 100 * MB);
+        disk.add_partition(100 * MB, 50 * GB);
+        let device = BlockDevice::mock_device(disk);
+
+        // Adding partitions leaves existing data alone
+        let mut planner = Planner::new(&device);
+        planner.plan_add_partition(50 * GB, 100 * GB).unwrap();
+        assert!(!planner.changes().iter().any(Change::is_destructive));
+        assert!(!planner.has_destructive_changes());
+
+        // Growing a partition is safe, shrinking it isn't
+        planner.undo();
+        planner.plan_resize_partition(1, 60 * GB).unwrap();
+        assert!(!planner.has_destructive_changes());
+        planner.undo();
+        planner.plan_resize_partition(1, 40 * GB).unwrap();
+        assert!(planner.has_destructive_changes());
+        planner.undo();
+
+        planner.plan_delete_partition(0).unwrap();
+        assert!(planner.changes().back().unwrap().is_destructive());
+        assert!(planner.has_destructive_changes());
+
+        let mut planner = Planner::new(&device);
+        planner.plan_initialize_disk().unwrap();
+        assert!(planner.has_destructive_changes());
+    }
+
     #[test]
     fn test_undo_operations() {
         let disk = create_mock_disk();
@@ -581,15 +3425,114 @@ mod tests {
         assert_eq!(planner.current_layout().len(), 2);
 
         // Undo last addition
-        assert!(planner.undo());
+        assert!(planner.undo().is_some());
         assert_eq!(planner.current_layout().len(), 1);
 
         // Undo first addition
-        assert!(planner.undo());
+        assert!(planner.undo().is_some());
         assert_eq!(planner.current_layout().len(), 0);
 
         // Verify no more changes to undo
-        assert!(!planner.undo());
+        assert!(planner.undo().is_none());
+    }
+
+    #[test]
+    fn test_undo_initialize_disk() {
+        let disk = create_windows_disk();
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+        let original = planner.current_layout();
+
+        assert!(planner.plan_initialize_disk().is_ok());
+        assert!(planner.wipe_disk());
+        assert!(planner.current_layout().is_empty());
+        assert!(planner.plan_add_partition(0, 100 * GB).is_ok());
+        assert_eq!(planner.current_layout()[0].partition_id, Some(1));
+
+        assert!(matches!(planner.undo(), Some(Change::AddPartition { .. })));
+        assert!(matches!(planner.undo(), Some(Change::InitializeDisk { .. })));
+        assert!(!planner.wipe_disk());
+        assert!(!planner.has_changes());
+
+        // The original Windows layout reappears, including its partition numbers
+        let layout = planner.current_layout();
+        assert_eq!(layout.len(), 4);
+        for (region, original) in layout.iter().zip(&original) {
+            assert_eq!((region.start, region.end), (original.start, original.end));
+            assert_eq!(region.partition_id, original.partition_id);
+        }
+        assert_eq!(planner.allocate_partition_id(), 5);
+
+        // Redoing the initialization discards the layout again
+        assert!(planner.redo().is_some());
+        assert!(planner.wipe_disk());
+        assert!(planner.current_layout().is_empty());
+    }
+
+    #[test]
+    fn test_undo_reuses_partition_id() {
+        let disk = create_mock_disk();
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+
+        assert!(planner.plan_add_partition(0, 100 * GB).is_ok());
+        assert!(planner.undo().is_some());
+        assert!(planner.plan_add_partition(0, 100 * GB).is_ok());
+        assert!(planner.plan_add_partition(100 * GB, 200 * GB).is_ok());
+
+        let ids = planner
+            .current_layout()
+            .iter()
+            .map(|r| r.partition_id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![Some(1), Some(2)]);
+
+        // Redoing the addition claims its ID again
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_mock_disk()));
+        assert!(planner.plan_add_partition(0, 100 * GB).is_ok());
+        assert!(planner.undo().is_some());
+        assert!(planner.redo().is_some());
+        assert_eq!(planner.allocate_partition_id(), 2);
+    }
+
+    #[test]
+    fn test_redo_operations() {
+        let disk = create_mock_disk();
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+
+        assert!(planner.plan_add_partition(0, 100 * GB).is_ok());
+        assert!(planner.plan_add_partition(100 * GB, 200 * GB).is_ok());
+        assert!(planner.redo().is_none());
+
+        // Undo hands back what was reverted
+        let Some(Change::AddPartition { start, .. }) = planner.undo() else {
+            panic!("expected to undo the second addition");
+        };
+        assert_eq!(start, 100 * GB);
+        assert!(planner.undo().is_some());
+        assert!(planner.current_layout().is_empty());
+
+        // Redo in reverse order of undoing
+        let Some(Change::AddPartition { start, .. }) = planner.redo() else {
+            panic!("expected to redo the first addition");
+        };
+        assert_eq!(start, 0);
+        assert_eq!(planner.current_layout().len(), 1);
+        assert!(planner.can_redo());
+
+        // Interleave another undo, then redo both
+        assert!(planner.undo().is_some());
+        assert!(planner.redo().is_some());
+        assert!(planner.redo().is_some());
+        assert!(planner.redo().is_none());
+        assert_eq!(planner.current_layout().len(), 2);
+        assert_eq!(planner.current_layout()[1].start, 100 * GB);
+
+        // A new change discards anything left to redo
+        assert!(planner.undo().is_some());
+        assert!(planner.can_redo());
+        assert!(planner.plan_add_partition(200 * GB, 300 * GB).is_ok());
+        assert!(!planner.can_redo());
+        assert!(planner.redo().is_none());
+        assert_eq!(planner.current_layout()[1].start, 200 * GB);
     }
 
     #[test]
@@ -663,6 +3606,27 @@ mod tests {
         assert_eq!(align_down(4 * mb + (600 * kb), mb), 5 * mb);
     }
 
+    #[test]
+    fn test_alignment_functions_non_power_of_two() {
+        // RAID stripe of 3 * 64KiB
+        let stripe = 192 * 1024;
+
+        assert!(is_aligned(5 * stripe, stripe));
+        assert!(!is_aligned(4 * MB, stripe));
+
+        // Test align_up
+        assert_eq!(align_up(5 * stripe, stripe), 5 * stripe); // Already aligned
+        assert_eq!(align_up(5 * stripe + 1000, stripe), 5 * stripe);
+        assert_eq!(align_up(5 * stripe + 150 * 1024, stripe), 6 * stripe);
+        assert_eq!(align_up(MB, stripe), 5 * stripe);
+
+        // Test align_down
+        assert_eq!(align_down(3 * stripe, stripe), 3 * stripe); // Already aligned
+        assert_eq!(align_down(3 * stripe + 1000, stripe), 3 * stripe);
+        assert_eq!(align_down(3 * stripe - 1000, stripe), 3 * stripe);
+        assert_eq!(align_down(3 * stripe - 150 * 1024, stripe), 2 * stripe);
+    }
+
     #[test]
     fn test_initialize_disk_partition_numbers() {
         let mut disk = create_mock_disk();
@@ -684,4 +3648,106 @@ mod tests {
         assert_eq!(layout[0].partition_id, Some(1));
         assert_eq!(layout[1].partition_id, Some(2));
     }
+
+    #[test]
+    fn test_layout_checksum() {
+        // Fresh disk with two partitions
+        let mut fresh = Planner::new(&BlockDevice::mock_device(create_mock_disk()));
+        assert!(fresh.plan_add_partition(0, 100 * GB).is_ok());
+        assert!(fresh.plan_add_partition(100 * GB, 200 * GB).is_ok());
+
+        // Wiped Windows disk reaching the same layout
+        let mut wiped = Planner::new(&BlockDevice::mock_device(create_windows_disk()));
+        assert!(wiped.plan_initialize_disk().is_ok());
+        assert!(wiped.plan_add_partition(0, 100 * GB).is_ok());
+        assert!(wiped.plan_add_partition(100 * GB, 200 * GB).is_ok());
+
+        assert_eq!(fresh.layout_checksum(), wiped.layout_checksum());
+
+        // Any geometry change must alter the checksum
+        assert!(wiped.undo().is_some());
+        assert!(wiped.plan_add_partition(100 * GB, 300 * GB).is_ok());
+        assert_ne!(fresh.layout_checksum(), wiped.layout_checksum());
+    }
+
+    #[test]
+    fn test_keep_partition() {
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_windows_disk()));
+
+        // Keep the ESP
+        assert!(planner.keep_partition(0).is_ok());
+        assert!(matches!(
+            planner.plan_delete_partition(0),
+            Err(PlanError::PartitionKept { index: 0 })
+        ));
+
+        // Initializing reclaims everything else without wiping
+        assert!(planner.plan_initialize_disk().is_ok());
+        assert!(!planner.wipe_disk());
+
+        let layout = planner.current_layout();
+        assert_eq!(layout.len(), 1);
+        assert_eq!(layout[0].partition_id, Some(1));
+        assert_eq!(layout[0].end, 100 * MB);
+
+        // New partitions are numbered after the originals
+        assert!(planner.plan_add_partition(100 * MB, 200 * GB).is_ok());
+        assert_eq!(planner.current_layout()[1].partition_id, Some(5));
+    }
+
+    #[test]
+    fn test_kept_partition_retains_partuuid() {
+        let partuuid = Uuid::parse_str("c12a7328-f81f-11d2-ba4b-00a0c93ec93b").unwrap();
+
+        let mut disk = create_mock_disk();
+        disk.add_partition(0, 100 * MB).uuid = Some(partuuid.to_string());
+        disk.add_partition(100 * MB, 116 * MB);
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+        assert_eq!(planner.original_regions()[0].partuuid(), Some(partuuid));
+        assert_eq!(planner.original_regions()[1].partuuid(), None);
+
+        assert!(planner.keep_partition(0).is_ok());
+        assert!(planner.plan_initialize_disk().is_ok());
+        assert!(planner.plan_add_partition(100 * MB, 200 * GB).is_ok());
+
+        let layout = planner.current_layout();
+        assert_eq!(layout.len(), 2);
+        assert_eq!(layout[0].partuuid(), Some(partuuid));
+        assert_eq!(layout[1].partuuid(), None);
+    }
+
+    #[test]
+    fn test_original_regions_4kn() {
+        // Partition offsets don't scale with the logical block size
+        let mut disk = MockDisk::new(500 * GB).with_logical_block_size(4096);
+        disk.add_partition(MB, 100 * MB);
+        disk.add_partition(100 * MB, 10 * GB);
+        let planner = Planner::new(&BlockDevice::mock_device(disk));
+
+        let regions = planner.original_regions();
+        assert_eq!((regions[0].start, regions[0].end), (MB, 100 * MB));
+        assert_eq!((regions[1].start, regions[1].end), (100 * MB, 10 * GB));
+    }
+
+    #[test]
+    fn test_shared_block_adjacency() {
+        // 4Kn disk with an existing partition ending mid-block
+        let mut disk = MockDisk::new(500 * GB).with_logical_block_size(4096);
+        disk.add_partition(0, MB + 512);
+
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk)).with_start_offset(MB + 512);
+
+        // Byte-adjacent, so the overlap test passes, but both touch the block at 1MiB
+        assert!(matches!(
+            planner.plan_add_partition(MB + 512, 100 * MB),
+            Err(PlanError::SharedBlock { block_size: 4096, .. })
+        ));
+
+        // Starting on the next block boundary is fine
+        let mut planner = Planner::new(&BlockDevice::mock_device(
+            MockDisk::new(500 * GB).with_logical_block_size(4096),
+        ))
+        .with_start_offset(MB + 4096);
+        assert!(planner.plan_add_partition(MB + 4096, 100 * MB).is_ok());
+    }
 }
@@ -18,31 +18,110 @@
 //! let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
 //!
 //! // Request needed partitions
+//! strategy.add_request(PartitionRequest::new(SizeRequirement::Exact(512 * 1024 * 1024))); // 512MB EFI partition
 //! strategy.add_request(PartitionRequest {
-//!     size: SizeRequirement::Exact(512 * 1024 * 1024), // 512MB EFI partition
-//!     attributes: None,
-//! });
-//! strategy.add_request(PartitionRequest {
-//!     size: SizeRequirement::Remaining, // Rest for root
-//!     attributes: None,
+//!     group: Some("system".into()),
+//!     ..PartitionRequest::new(SizeRequirement::Remaining) // Rest for root
 //! });
 //! ```
+//!
+//! Within the chosen region, requests are placed in the order they were added,
+//! with grouped requests kept together at the position of the group's first
+//! member. The same requests on the same disk therefore always produce the
+//! same layout.
+//!
+//! Space is handed out in order of priority instead: boot partitions, then swap,
+//! then everything else, unless a request sets its own priority. Exact sizes are
+//! satisfied before space is shared out between flexible requests, and
+//! [`Strategy::apply_by_priority`] leaves out low priority requests that don't fit.
+//!
+//! Whatever the allocation strategy, EFI system partitions are placed before any
+//! other request, in the lowest free region that fits them. Firmware is most
+//! reliable when the ESP is the first partition at the front of the disk, so this
+//! overrides e.g. [`AllocationStrategy::LargestFree`] picking a later region.
+
+use std::cmp::Reverse;
 
-use crate::planner::{PlanError, Planner};
+use disks::scale_size;
+use log::warn;
+use thiserror::Error;
+use types::PartitionRole;
+
+use crate::planner::{Change, PlanError, Planner};
 
 use crate::PartitionAttributes;
 use crate::planner::Region;
 
+/// Errors that can occur applying a strategy to a planner
+///
+/// Request indices refer to the order requests were added to the [`Strategy`].
+#[derive(Debug, Error)]
+pub enum StrategyError {
+    #[error("Request {index} needs {needed} bytes, but only {available} bytes are available")]
+    InsufficientSpace { index: usize, needed: u64, available: u64 },
+    #[error("Fixed size requests {indices:?} need {total} bytes, but only {available} bytes are available")]
+    ConflictingFixedSizes {
+        indices: Vec<usize>,
+        total: u64,
+        available: u64,
+    },
+    #[error("Request {index} cannot be placed at its {alignment} byte alignment")]
+    Alignment { index: usize, alignment: u64 },
+    #[error("Request {index} was rejected by the planner: {source}")]
+    Rejected { index: usize, source: PlanError },
+    #[error(transparent)]
+    Plan(#[from] PlanError),
+}
+
+impl StrategyError {
+    /// The index of the request that couldn't be satisfied, if any single one
+    pub fn request_index(&self) -> Option<usize> {
+        match self {
+            StrategyError::InsufficientSpace { index, .. }
+            | StrategyError::Alignment { index, .. }
+            | StrategyError::Rejected { index, .. } => Some(*index),
+            StrategyError::ConflictingFixedSizes { .. } | StrategyError::Plan(_) => None,
+        }
+    }
+
+    /// Translate request indices through `original`, after requests were removed
+    fn reindex(mut self, original: &[usize]) -> Self {
+        match &mut self {
+            StrategyError::InsufficientSpace { index, .. }
+            | StrategyError::Alignment { index, .. }
+            | StrategyError::Rejected { index, .. } => *index = original[*index],
+            StrategyError::ConflictingFixedSizes { indices, .. } => {
+                indices.iter_mut().for_each(|index| *index = original[*index])
+            }
+            StrategyError::Plan(_) => {}
+        }
+        self
+    }
+}
+
 /// Strategy for allocating partitions
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub enum AllocationStrategy {
     /// Initialize a clean partition layout using the entire disk.
-    /// This will remove all existing partitions and create a new layout.
+    /// This will remove all existing partitions (except those kept by the
+    /// planner) and create a new layout.
     InitializeWholeDisk,
     /// Use largest available free region on existing table
+    #[default]
     LargestFree,
-    /// Use first free region that fits on existing table
+    /// Use the smallest free region that still fits the minimum size of every request
+    SmallestFree,
+    /// Use the free region that leaves the least space over once every request has
+    /// its preferred size, i.e. its exact, maximum or recommended size. Falls back to
+    /// the largest free region when a request is unbounded or nothing fits.
+    BestFit,
+    /// Use the lowest-addressed free region that fits the minimum size of every
+    /// request on existing table
     FirstFit,
+    /// Prefer interior gaps between existing partitions over the trailing free
+    /// region, keeping the disk tail contiguous. Only applies when every request
+    /// has an upper bound on its size; open-ended requests use the trailing region.
+    FillGapsFirst,
     /// Use specific region on existing table
     SpecificRegion(Region),
 }
@@ -56,8 +135,26 @@ pub enum SizeRequirement {
     AtLeast(u64),
     /// Between min and max bytes
     Range { min: u64, max: u64 },
+    /// Prefer the recommended size, shrinking toward min when space is tight.
+    /// The recommended size is clamped to lie within min and max.
+    Bounded { min: u64, recommended: u64, max: u64 },
+    /// A percentage of the usable size of the device, from 1 to 100, resolved when
+    /// the strategy is applied and aligned down to the planner's alignment. Sized
+    /// like an exact request, so percentages and exact sizes must fit together.
+    Percent(u8),
     /// Use all remaining space
     Remaining,
+    /// Share the remaining space with other flexible requests in proportion to
+    /// the weight, where requests without a weight count as one. A root with a
+    /// weight of 3 beside a plain remaining home thus takes three quarters.
+    RemainingWeighted(u32),
+    /// Use all remaining space, leaving the given number of bytes free at the end
+    RemainingMinus(u64),
+    /// A fraction of the space still free when this request is sized, after exact
+    /// requests and any flexible requests declared before it have taken theirs.
+    /// Declaration order therefore matters: two requests for half of the remaining
+    /// space receive a half and then a quarter. Fractions above one are capped.
+    FractionOfRemaining { numerator: u64, denominator: u64 },
 }
 
 /// A partition request for the strategy to plan
@@ -65,6 +162,94 @@ pub enum SizeRequirement {
 pub struct PartitionRequest {
     pub size: SizeRequirement,
     pub attributes: Option<PartitionAttributes>,
+    /// Requests sharing a group are placed next to each other
+    pub group: Option<String>,
+    /// Percentage of the partition size to leave free after it for later growth
+    pub grow_reserve_percent: u8,
+    /// Alignment for the partition boundaries, overriding the planner's default
+    /// (usually [`crate::planner::PARTITION_ALIGNMENT`]), e.g. 2MiB hugepage alignment for swap
+    pub alignment: Option<u64>,
+    /// Explicit partition number, for firmware that refers to partitions by number
+    pub number: Option<u32>,
+    /// Priority when competing for space, higher first, overriding the priority of the role
+    pub priority: Option<u8>,
+}
+
+impl PartitionRequest {
+    /// A request for `size` bytes, without attributes, grouping or other preferences
+    pub fn new(size: SizeRequirement) -> Self {
+        Self {
+            size,
+            attributes: None,
+            group: None,
+            grow_reserve_percent: 0,
+            alignment: None,
+            number: None,
+            priority: None,
+        }
+    }
+
+    /// The space taken up by a partition of `size` bytes, including its growth reserve
+    fn footprint(&self, size: u64) -> u64 {
+        size.saturating_add(scale_size(size, u64::from(self.grow_reserve_percent), 100))
+    }
+
+    /// The partition size within a footprint, excluding its growth reserve
+    fn size_within(&self, footprint: u64) -> u64 {
+        scale_size(footprint, 100, 100 + u64::from(self.grow_reserve_percent))
+    }
+
+    /// The most a custom alignment can push the start past a boundary of the planner's alignment
+    fn alignment_slack(&self, planner: &Planner) -> u64 {
+        self.alignment.map_or(0, |alignment| {
+            alignment.saturating_sub(gcd(alignment, planner.alignment()))
+        })
+    }
+
+    /// The space taken up by a fixed size request, including its growth reserve and
+    /// any room needed to align its start
+    fn fixed_footprint(&self, planner: &Planner) -> Option<u64> {
+        self.fixed_size(planner)
+            .map(|size| self.footprint(size).saturating_add(self.alignment_slack(planner)))
+    }
+
+    /// The size of this request if it doesn't depend on the space available
+    fn fixed_size(&self, planner: &Planner) -> Option<u64> {
+        match self.size {
+            SizeRequirement::Exact(size) => Some(size),
+            SizeRequirement::Percent(percent) => {
+                let size = scale_size(planner.usable_size(), u64::from(percent), 100);
+                Some(size / planner.alignment() * planner.alignment())
+            }
+            _ => None,
+        }
+    }
+
+    /// The priority of this request when competing for space
+    ///
+    /// Without an explicit priority, boot partitions come first, then swap, then
+    /// everything else.
+    pub fn effective_priority(&self) -> u8 {
+        self.priority
+            .unwrap_or(match self.attributes.as_ref().and_then(|a| a.role.as_ref()) {
+                Some(PartitionRole::Boot | PartitionRole::ExtendedBoot) => 2,
+                Some(PartitionRole::Swap) => 1,
+                _ => 0,
+            })
+    }
+
+    /// The weight of this request when sharing out remaining space
+    fn weight(&self) -> u64 {
+        match self.size {
+            SizeRequirement::RemainingWeighted(weight) => u64::from(weight.max(1)),
+            _ => 1,
+        }
+    }
+
+    /// Whether this request is for an EFI system partition, by role or type
+    fn is_esp(&self) -> bool {
+        self.attributes.as_ref().is_some_and(PartitionAttributes::is_esp)
+    }
 }
 
 /// Handles planning partition layouts according to specific strategies
@@ -72,6 +257,8 @@ pub struct PartitionRequest {
 pub struct Strategy {
     allocation: AllocationStrategy,
     requests: Vec<PartitionRequest>,
+    /// Unpartitioned gaps, each left before the request at the given index
+    gaps: Vec<(usize, u64)>,
 }
 
 impl Strategy {
@@ -80,6 +267,7 @@ impl Strategy {
         Self {
             allocation,
             requests: Vec::new(),
+            gaps: Vec::new(),
         }
     }
 
@@ -88,21 +276,180 @@ impl Strategy {
         self.requests.push(request);
     }
 
+    /// Remove the request at `index`, keeping any gaps with the requests that follow
+    fn remove_request(&mut self, index: usize) -> PartitionRequest {
+        for (before, _) in self.gaps.iter_mut().filter(|(before, _)| *before > index) {
+            *before -= 1;
+        }
+        self.requests.remove(index)
+    }
+
+    /// Leave `size` bytes unpartitioned before the next request added
+    ///
+    /// The gap stays free in the layout, but none of this strategy's requests
+    /// are placed in it, e.g. as a safety buffer before a data partition. Exact
+    /// requests are placed before flexible ones, so the gap moves with the request
+    /// following it rather than its position in the declaration order.
+    pub fn add_gap(&mut self, size: u64) {
+        self.gaps.push((self.requests.len(), size));
+    }
+
+    /// Total size of the gaps left before the given request
+    fn gap_before(&self, idx: usize) -> u64 {
+        self.gaps
+            .iter()
+            .filter(|(before, _)| *before == idx)
+            .map(|(_, size)| size)
+            .sum()
+    }
+
+    /// Reorder request indices so members of the same group are placed
+    /// consecutively, at the position of the group's first member
+    fn placement_order(&self, order: &[usize]) -> Vec<usize> {
+        let mut placed = Vec::with_capacity(order.len());
+        for &idx in order {
+            if placed.contains(&idx) {
+                continue;
+            }
+            match &self.requests[idx].group {
+                Some(group) => placed.extend(
+                    order
+                        .iter()
+                        .copied()
+                        .filter(|i| self.requests[*i].group.as_ref() == Some(group)),
+                ),
+                None => placed.push(idx),
+            }
+        }
+        placed
+    }
+
+    /// Place EFI system partitions ahead of every other request, each in the lowest
+    /// free region that fits it, returning the space taken including growth reserves
+    ///
+    /// ESP requests take their exact, maximum or recommended size, shrinking toward
+    /// their minimum when the lowest region that fits them is tight.
+    fn place_esp_requests(
+        &self,
+        planner: &mut Planner,
+        reserves: &mut Vec<(u32, u64, u64)>,
+    ) -> Result<Vec<Region>, StrategyError> {
+        let mut placed: Vec<Region> = Vec::new();
+        for (index, request) in self.requests.iter().enumerate().filter(|(_, r)| r.is_esp()) {
+            let (min, preferred) = match request.size {
+                SizeRequirement::Exact(size) | SizeRequirement::AtLeast(size) => (size, size),
+                SizeRequirement::Percent(_) => {
+                    let size = request.fixed_size(planner).unwrap_or_default();
+                    (size, size)
+                }
+                SizeRequirement::Range { min, max } => (min, max.max(min)),
+                SizeRequirement::Bounded { min, recommended, max } => (min, recommended.clamp(min, max.max(min))),
+                SizeRequirement::Remaining
+                | SizeRequirement::RemainingWeighted(_)
+                | SizeRequirement::RemainingMinus(_)
+                | SizeRequirement::FractionOfRemaining { .. } => (0, 0),
+            };
+            let alignment = request.alignment.unwrap_or(planner.alignment());
+
+            // Growth reserves of earlier ESPs aren't reserved yet, so step over them
+            let (start, size) = self
+                .find_free_regions(planner)
+                .iter()
+                .find_map(|region| {
+                    let floor = placed
+                        .iter()
+                        .filter(|p| p.overlaps_with(region))
+                        .fold(region.start, |floor, p| floor.max(p.end));
+                    let start = floor.div_ceil(alignment) * alignment;
+                    let available = region.end.checked_sub(start)?;
+                    (request.footprint(min) <= available)
+                        .then(|| (start, request.size_within(available).clamp(min, preferred)))
+                })
+                .ok_or_else(|| StrategyError::InsufficientSpace {
+                    index,
+                    needed: request.footprint(min),
+                    available: self
+                        .find_free_regions(planner)
+                        .iter()
+                        .map(|r| r.size())
+                        .max()
+                        .unwrap_or(0),
+                })?;
+
+            let footprint = request.footprint(size);
+            let end = start + size;
+            match request.number {
+                Some(number) => {
+                    planner.plan_add_partition_numbered(start, end, request.attributes.clone(), alignment, number)
+                }
+                None => planner.plan_add_partition_aligned(start, end, request.attributes.clone(), alignment),
+            }
+            .map_err(|e| rejection(index, alignment, e))?;
+            if footprint > size {
+                if let Some(Change::AddPartition { partition_id, .. }) = planner.changes().back() {
+                    reserves.push((*partition_id, end, start + footprint));
+                }
+            }
+            placed.push(Region::new(start, start + footprint));
+        }
+        Ok(placed)
+    }
+
+    /// The least space needed to satisfy every request, including growth reserves
+    fn minimum_footprint(&self, planner: &Planner) -> u64 {
+        self.requests
+            .iter()
+            .map(|request| match request.size {
+                SizeRequirement::Percent(_) => request.footprint(request.fixed_size(planner).unwrap_or_default()),
+                SizeRequirement::Exact(min)
+                | SizeRequirement::AtLeast(min)
+                | SizeRequirement::Range { min, .. }
+                | SizeRequirement::Bounded { min, .. } => request.footprint(min),
+                SizeRequirement::Remaining
+                | SizeRequirement::RemainingWeighted(_)
+                | SizeRequirement::FractionOfRemaining { .. } => 0,
+                SizeRequirement::RemainingMinus(reserve) => reserve,
+            })
+            .chain(self.gaps.iter().map(|(_, size)| *size))
+            .sum()
+    }
+
+    /// The space needed for every request at its preferred size, if all are bounded
+    fn preferred_footprint(&self, planner: &Planner) -> Option<u64> {
+        self.requests
+            .iter()
+            .map(|request| match request.size {
+                SizeRequirement::Percent(_) => request.fixed_size(planner).map(|size| request.footprint(size)),
+                SizeRequirement::Exact(size) | SizeRequirement::Range { max: size, .. } => {
+                    Some(request.footprint(size))
+                }
+                SizeRequirement::Bounded { min, recommended, max } => {
+                    Some(request.footprint(recommended.clamp(min, max.max(min))))
+                }
+                _ => None,
+            })
+            .chain(self.gaps.iter().map(|(_, size)| Some(*size)))
+            .sum()
+    }
+
     /// Find available free regions on the disk
     fn find_free_regions(&self, planner: &Planner) -> Vec<Region> {
         let mut regions = Vec::new();
-        let (mut current, disk_size) = planner.offsets();
+        let (_, disk_size) = planner.offsets();
+        let mut current = planner.allocation_start();
 
-        // Sort existing partitions by start position
+        // Sort existing partitions, bad and reserved regions by start position
         let mut layout = planner.current_layout();
+        layout.extend(planner.bad_regions().iter().cloned());
+        layout.extend(planner.reserved_regions().iter().cloned());
         layout.sort_by_key(|r| r.start);
 
-        // Find gaps between partitions
+        // Find gaps between partitions, routing around bad regions
         for region in layout {
             if region.start > current {
                 regions.push(Region::new(current, region.start));
             }
-            current = region.end;
+            current = current.max(region.end);
         }
 
         // Add final region if there's space after last partition
@@ -120,7 +467,10 @@ impl Strategy {
         let mut desc = match &self.allocation {
             AllocationStrategy::InitializeWholeDisk => "Initialize new partition layout on entire disk".to_string(),
             AllocationStrategy::LargestFree => "Use largest free region".to_string(),
+            AllocationStrategy::SmallestFree => "Use smallest free region that fits".to_string(),
+            AllocationStrategy::BestFit => "Use best fitting free region".to_string(),
             AllocationStrategy::FirstFit => "Use first available region".to_string(),
+            AllocationStrategy::FillGapsFirst => "Fill gaps before the trailing free region".to_string(),
             AllocationStrategy::SpecificRegion(r) => format!("Use specific region: {}", r.describe(r.end - r.start)),
         };
 
@@ -129,11 +479,25 @@ impl Strategy {
             for (i, req) in self.requests.iter().enumerate() {
                 let size_desc = match &req.size {
                     SizeRequirement::Exact(size) => format!("exactly {}", format_size(*size)),
+                    SizeRequirement::Percent(percent) => format!("{percent}% of the disk"),
                     SizeRequirement::AtLeast(min) => format!("at least {}", format_size(*min)),
                     SizeRequirement::Range { min, max } => {
                         format!("between {} and {}", format_size(*min), format_size(*max))
                     }
+                    SizeRequirement::Bounded { min, recommended, max } => format!(
+                        "{} (between {} and {})",
+                        format_size(*recommended),
+                        format_size(*min),
+                        format_size(*max)
+                    ),
                     SizeRequirement::Remaining => "remaining space".to_string(),
+                    SizeRequirement::RemainingWeighted(weight) => format!("remaining space (weight {weight})"),
+                    SizeRequirement::RemainingMinus(reserve) => {
+                        format!("remaining space less {}", format_size(*reserve))
+                    }
+                    SizeRequirement::FractionOfRemaining { numerator, denominator } => {
+                        format!("{numerator}/{denominator} of remaining space")
+                    }
                 };
                 desc.push_str(&format!("  {}: {}\n", i + 1, size_desc));
             }
@@ -144,15 +508,91 @@ impl Strategy {
     /// Apply this strategy to a planner
     /// This will plan the necessary partition changes to fulfill the requirements
     /// Returns an error if the strategy cannot be applied due to insufficient space
-    /// or other constraints
-    pub fn apply(&self, planner: &mut Planner) -> Result<(), PlanError> {
+    /// or other constraints, in which case any changes made are undone
+    pub fn apply(&self, planner: &mut Planner) -> Result<(), StrategyError> {
+        let result = self.plan_requests(planner);
+        planner.clear_reserved_partition_ids();
+        if result.is_err() {
+            // Clean up any changes we made since we can't complete all requests,
+            // including initialization of the disk
+            while planner.has_changes() {
+                planner.undo();
+            }
+        }
+        result
+    }
+
+    /// Apply this strategy, leaving out low priority requests that can't be satisfied
+    ///
+    /// When a request can't be satisfied and another has a higher priority, it's
+    /// dropped and the remaining requests are planned again. Returns the indices of
+    /// the dropped requests, or the error that dropping requests couldn't resolve.
+    pub fn apply_by_priority(&self, planner: &mut Planner) -> Result<Vec<usize>, StrategyError> {
+        let mut strategy = self.clone();
+        let mut indices = (0..self.requests.len()).collect::<Vec<_>>();
+        let mut dropped = vec![];
+        loop {
+            let err = match strategy.apply(planner) {
+                Ok(()) => {
+                    dropped.sort_unstable();
+                    return Ok(dropped);
+                }
+                Err(err) => err,
+            };
+            let highest = strategy.requests.iter().map(|r| r.effective_priority()).max();
+            match err.request_index() {
+                Some(index) if Some(strategy.requests[index].effective_priority()) < highest => {
+                    warn!("Dropping request {}: {err}", indices[index]);
+                    strategy.remove_request(index);
+                    dropped.push(indices.remove(index));
+                }
+                _ => return Err(err.reindex(&indices)),
+            }
+        }
+    }
+
+    fn plan_requests(&self, planner: &mut Planner) -> Result<(), StrategyError> {
+        // Percentages must be sensible alone and together
+        let mut percent_total = 0u32;
+        for request in &self.requests {
+            if let SizeRequirement::Percent(percent) = request.size {
+                if !(1..=100).contains(&percent) {
+                    return Err(PlanError::InvalidPercentage {
+                        percent: u32::from(percent),
+                    }
+                    .into());
+                }
+                percent_total += u32::from(percent);
+            }
+        }
+        if percent_total > 100 {
+            return Err(PlanError::InvalidPercentage { percent: percent_total }.into());
+        }
+
+        // Clear existing partitions and start fresh. Kept partitions survive this,
+        // so allocate from the largest remaining free region.
+        if let AllocationStrategy::InitializeWholeDisk = self.allocation {
+            planner.plan_initialize_disk()?;
+        }
+
+        // Keep explicit partition numbers from being handed out to earlier requests
+        for number in self.requests.iter().filter_map(|r| r.number) {
+            planner.reserve_partition_id(number)?;
+        }
+
+        // The ESP always goes first, at the front of the disk
+        let mut reserves = vec![];
+        let esps = self.place_esp_requests(planner, &mut reserves)?;
+
         // Determine the target region for our partitions
-        let target = match &self.allocation {
+        let mut target = match &self.allocation {
             AllocationStrategy::InitializeWholeDisk => {
-                // Clear existing partitions and start fresh
-                planner.plan_initialize_disk()?;
-                let (start, end) = planner.offsets();
-                Region::new(start, end)
+                let free_regions = self.find_free_regions(planner);
+                free_regions
+                    .iter()
+                    .max_by_key(|r| r.size())
+                    .cloned()
+                    .ok_or(PlanError::NoFreeRegions)?
             }
             AllocationStrategy::LargestFree => {
                 let free_regions = self.find_free_regions(planner);
@@ -162,73 +602,224 @@ impl Strategy {
                     .cloned()
                     .ok_or(PlanError::NoFreeRegions)?
             }
+            AllocationStrategy::SmallestFree => {
+                let needed = self.minimum_footprint(planner);
+                let free_regions = self.find_free_regions(planner);
+                free_regions
+                    .iter()
+                    .filter(|r| r.size() >= needed)
+                    .min_by_key(|r| r.size())
+                    .cloned()
+                    .ok_or(PlanError::NoFreeRegions)?
+            }
+            AllocationStrategy::BestFit => {
+                let free_regions = self.find_free_regions(planner);
+                let best = self.preferred_footprint(planner).and_then(|preferred| {
+                    free_regions
+                        .iter()
+                        .filter(|r| r.size() >= preferred)
+                        .min_by_key(|r| r.size() - preferred)
+                });
+                best.or_else(|| free_regions.iter().max_by_key(|r| r.size()))
+                    .cloned()
+                    .ok_or(PlanError::NoFreeRegions)?
+            }
             AllocationStrategy::FirstFit => {
+                let needed = self.minimum_footprint(planner);
+                let free_regions = self.find_free_regions(planner);
+                free_regions
+                    .into_iter()
+                    .find(|r| r.size() >= needed)
+                    .ok_or(PlanError::NoFreeRegions)?
+            }
+            AllocationStrategy::FillGapsFirst => {
                 let free_regions = self.find_free_regions(planner);
-                free_regions.first().cloned().ok_or(PlanError::NoFreeRegions)?
+                let (_, disk_end) = planner.offsets();
+                let (interior, trailing): (Vec<_>, Vec<_>) = free_regions.iter().partition(|r| r.end < disk_end);
+                let sized = self.requests.iter().all(|r| {
+                    matches!(
+                        r.size,
+                        SizeRequirement::Exact(_)
+                            | SizeRequirement::Percent(_)
+                            | SizeRequirement::Range { .. }
+                            | SizeRequirement::Bounded { .. }
+                    )
+                });
+                let gap = if sized {
+                    let needed = self.minimum_footprint(planner);
+                    interior.into_iter().find(|r| r.size() >= needed)
+                } else {
+                    None
+                };
+                gap.or_else(|| trailing.into_iter().next())
+                    .or_else(|| free_regions.iter().max_by_key(|r| r.size()))
+                    .cloned()
+                    .ok_or(PlanError::NoFreeRegions)?
             }
             AllocationStrategy::SpecificRegion(region) => region.clone(),
         };
 
+        // Leave the space taken by the ESPs, including their growth reserves
+        for esp in &esps {
+            if esp.overlaps_with(&target) {
+                target.start = target.start.max(esp.end);
+            }
+        }
+        if target.start >= target.end {
+            return Err(PlanError::NoFreeRegions.into());
+        }
+
         let mut current = target.start;
         let mut remaining = target.end - target.start;
 
         let mut flexible_requests = Vec::new();
         let mut total_fixed = 0u64;
         let mut min_flexible = 0u64;
+        let mut reserved = 0u64;
 
         // First pass: Calculate space requirements
-        for (current_idx, request) in self.requests.iter().enumerate() {
+        for (current_idx, request) in self.requests.iter().enumerate().filter(|(_, r)| !r.is_esp()) {
+            // Sizes are considered in terms of footprint, including any growth reserve
+            let fp = |size: u64| request.footprint(size);
             match &request.size {
-                SizeRequirement::Exact(size) => total_fixed += size,
+                SizeRequirement::Exact(_) | SizeRequirement::Percent(_) => {
+                    total_fixed += request.fixed_footprint(planner).unwrap_or_default()
+                }
                 SizeRequirement::AtLeast(min) => {
-                    min_flexible += min;
-                    flexible_requests.push((current_idx, *min, None));
+                    min_flexible += fp(*min);
+                    flexible_requests.push((current_idx, fp(*min), None, None));
                 }
                 SizeRequirement::Range { min, max } => {
-                    min_flexible += min;
-                    flexible_requests.push((current_idx, *min, Some(*max)));
+                    min_flexible += fp(*min);
+                    flexible_requests.push((current_idx, fp(*min), Some(fp(*max)), None));
+                }
+                SizeRequirement::Bounded { min, recommended, max } => {
+                    min_flexible += fp(*min);
+                    let recommended = (*recommended).clamp(*min, (*max).max(*min));
+                    flexible_requests.push((current_idx, fp(*min), Some(fp(*max)), Some(fp(recommended))));
+                }
+                SizeRequirement::Remaining
+                | SizeRequirement::RemainingWeighted(_)
+                | SizeRequirement::FractionOfRemaining { .. } => {
+                    flexible_requests.push((current_idx, 0, None, None));
                 }
-                SizeRequirement::Remaining => {
-                    flexible_requests.push((current_idx, 0, None));
+                SizeRequirement::RemainingMinus(reserve) => {
+                    reserved += reserve;
+                    flexible_requests.push((current_idx, 0, None, None));
                 }
             }
         }
 
-        // Verify we have enough space for minimum requirements
-        if total_fixed + min_flexible > remaining {
+        // Gaps are never allocated, just like reserved space
+        reserved += self.gaps.iter().map(|(_, size)| size).sum::<u64>();
+
+        // Verify we have enough space for minimum requirements. Requests are
+        // satisfied highest priority first, fixed sizes before flexible ones, so
+        // the blame falls on the first to no longer fit.
+        if total_fixed + min_flexible + reserved > remaining {
+            let mut by_priority = (0..self.requests.len())
+                .filter(|&idx| !self.requests[idx].is_esp())
+                .collect::<Vec<_>>();
+            by_priority.sort_by_key(|&idx| Reverse(self.requests[idx].effective_priority()));
+
+            let mut available = remaining.saturating_sub(reserved);
+            for level in by_priority
+                .chunk_by(|a, b| self.requests[*a].effective_priority() == self.requests[*b].effective_priority())
+            {
+                let (fixed, flexible): (Vec<usize>, Vec<usize>) = level
+                    .iter()
+                    .partition(|&&idx| self.requests[idx].fixed_size(planner).is_some());
+                let total = fixed
+                    .iter()
+                    .map(|&idx| self.requests[idx].fixed_footprint(planner).unwrap_or_default())
+                    .sum::<u64>();
+                if total > available {
+                    return Err(match fixed[..] {
+                        [index] => StrategyError::InsufficientSpace {
+                            index,
+                            needed: total,
+                            available,
+                        },
+                        _ => StrategyError::ConflictingFixedSizes {
+                            indices: fixed,
+                            total,
+                            available,
+                        },
+                    });
+                }
+                available -= total;
+
+                for idx in flexible {
+                    let min = flexible_requests
+                        .iter()
+                        .find(|(index, ..)| *index == idx)
+                        .map_or(0, |(_, min, ..)| *min);
+                    if min > available {
+                        return Err(StrategyError::InsufficientSpace {
+                            index: idx,
+                            needed: min,
+                            available,
+                        });
+                    }
+                    available -= min;
+                }
+            }
+
+            // Only the reserved space and gaps are left to blame
             return Err(PlanError::RegionOutOfBounds {
                 start: current,
-                end: current + total_fixed + min_flexible,
-            });
+                end: current + total_fixed + min_flexible + reserved,
+            }
+            .into());
         }
 
-        // First pass: allocate exact size partitions
-        for request in &self.requests {
-            if let SizeRequirement::Exact(size) = request.size {
-                planner.plan_add_partition_with_attributes(current, current + size, request.attributes.clone())?;
-                current += size;
-                remaining -= size;
+        // Reserved space is left unallocated at the end of the target region
+        remaining -= reserved;
+
+        // Exact size partitions are sized first, followed by flexible partitions
+        let mut sizes = vec![0u64; self.requests.len()];
+        let mut order = Vec::with_capacity(self.requests.len());
+        for (idx, request) in self.requests.iter().enumerate().filter(|(_, r)| !r.is_esp()) {
+            if let Some(footprint) = request.fixed_footprint(planner) {
+                sizes[idx] = footprint;
+                order.push(idx);
+                remaining -= sizes[idx];
             }
         }
 
-        // Second pass: allocate flexible partitions
+        // Second pass: size flexible partitions, highest priority first
+        flexible_requests.sort_by_key(|(idx, ..)| Reverse(self.requests[*idx].effective_priority()));
         let mut remaining_flexible = flexible_requests.len();
-        for (idx, min, max_opt) in &flexible_requests {
+        let mut pending_min = min_flexible;
+        let mut pending_weight = flexible_requests
+            .iter()
+            .map(|(idx, ..)| self.requests[*idx].weight())
+            .sum::<u64>();
+        for (idx, min, max_opt, recommended) in &flexible_requests {
             remaining_flexible -= 1;
+            pending_min -= min;
+            let weight = self.requests[*idx].weight();
 
             // First verify we have enough space for minimum requirement
             if *min > remaining {
-                // Clean up any changes we made since we can't complete all requests
-                while planner.has_changes() {
-                    planner.undo();
-                }
-                return Err(PlanError::RegionOutOfBounds {
-                    start: current,
-                    end: current + min,
+                return Err(StrategyError::InsufficientSpace {
+                    index: *idx,
+                    needed: *min,
+                    available: remaining,
                 });
             }
 
-            let size = if remaining_flexible == 0 {
+            let size = if let SizeRequirement::FractionOfRemaining { numerator, denominator } = self.requests[*idx].size
+            {
+                // Take the fraction of what's left now, leaving room for later minimums
+                let denominator = denominator.max(1);
+                scale_size(remaining, numerator.min(denominator), denominator)
+                    .min(remaining.saturating_sub(pending_min))
+            } else if let Some(recommended) = recommended {
+                // Use the recommended size, shrinking toward the minimum when later
+                // requests need the space for their own minimums
+                (*recommended).min(remaining.saturating_sub(pending_min)).max(*min)
+            } else if remaining_flexible == 0 {
                 // Last flexible partition gets all remaining space
                 let size = remaining;
                 if let Some(max) = max_opt {
@@ -237,39 +828,95 @@ impl Strategy {
                     size.max(*min)
                 }
             } else {
-                // Other flexible partitions get fair share plus minimum
-                let share = remaining / (remaining_flexible + 1) as u64;
+                // Other flexible partitions get their weighted share plus minimum
+                let share = scale_size(remaining, weight, pending_weight);
                 let size = min + share;
                 if let Some(max) = max_opt { size.min(*max) } else { size }
             };
 
-            match planner.plan_add_partition_with_attributes(
-                current,
-                current + size,
-                self.requests.get(*idx).and_then(|r| r.attributes.clone()),
-            ) {
-                Ok(_) => {
-                    current += size;
-                    remaining -= size;
+            // Slices short of the last are aligned down, so none are lost between partitions
+            let alignment = planner.alignment();
+            let size = match size / alignment * alignment {
+                aligned if remaining_flexible > 0 && aligned >= *min => aligned,
+                _ => size,
+            };
+
+            sizes[*idx] = size;
+            order.push(*idx);
+            remaining -= size;
+            pending_weight -= weight;
+        }
+
+        // Finally, place partitions contiguously in the order they were requested,
+        // keeping grouped requests together
+        order.sort_unstable();
+        for idx in self.placement_order(&order) {
+            let request = &self.requests[idx];
+            let footprint = sizes[idx];
+            current += self.gap_before(idx);
+            // A custom alignment may push the start forward. Fixed sizes carry the slack
+            // for it in their footprint, flexible ones shrink to stay within theirs.
+            let alignment = request.alignment.unwrap_or(planner.alignment());
+            let start = current.div_ceil(alignment) * alignment;
+            let end = match request.fixed_size(planner) {
+                Some(size) => start + size,
+                None => (start + request.size_within(footprint)).min(current + footprint) / alignment * alignment,
+            };
+            if end <= start || end > current + footprint {
+                return Err(StrategyError::Alignment { index: idx, alignment });
+            }
+            let planned = match request.number {
+                Some(number) => {
+                    planner.plan_add_partition_numbered(start, end, request.attributes.clone(), alignment, number)
                 }
-                Err(e) => {
-                    // Clean up any changes we made since we can't complete all requests
-                    while planner.has_changes() {
-                        planner.undo();
+                None => planner.plan_add_partition_aligned(start, end, request.attributes.clone(), alignment),
+            };
+            match planned {
+                Ok(_) => {
+                    if let Some(Change::AddPartition {
+                        partition_id,
+                        end: planned_end,
+                        ..
+                    }) = planner.changes().back()
+                    {
+                        if request.grow_reserve_percent > 0 && current + footprint > *planned_end {
+                            reserves.push((*partition_id, *planned_end, current + footprint));
+                        }
                     }
-                    return Err(e);
+                    current += footprint;
                 }
+                Err(e) => return Err(rejection(idx, alignment, e)),
             }
         }
 
+        for (partition_id, start, end) in reserves {
+            planner.reserve_growth(partition_id, start, end)?;
+        }
+
         Ok(())
     }
 }
 
+/// Greatest common divisor, for alignments that aren't multiples of each other
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+/// Attribute a planner error to the request that caused it
+fn rejection(index: usize, alignment: u64, source: PlanError) -> StrategyError {
+    match source {
+        PlanError::InvalidAlignment { .. } | PlanError::NotSectorAligned { .. } => {
+            StrategyError::Alignment { index, alignment }
+        }
+        source => StrategyError::Rejected { index, source },
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::planner::Planner;
+    use crate::planner::{PARTITION_ALIGNMENT, Planner, SegmentKind};
+    use crate::{GptAttributes, TableAttributes};
     use disks::{BlockDevice, mock::MockDisk};
     use test_log::test;
 
@@ -286,61 +933,641 @@ mod tests {
 
     /// Creates a root partition request that uses remaining space with a minimum size
     fn root_partition() -> PartitionRequest {
-        PartitionRequest {
-            size: SizeRequirement::AtLeast(ROOT_MIN),
-            attributes: None,
-        }
+        PartitionRequest::new(SizeRequirement::AtLeast(ROOT_MIN))
     }
 
     /// Creates a root partition request capped at 100GB, suitable for layouts with home partition
     fn capped_root_partition() -> PartitionRequest {
-        PartitionRequest {
-            size: SizeRequirement::Range {
-                min: ROOT_MIN,
-                max: ROOT_MAX,
-            },
-            attributes: None,
-        }
+        PartitionRequest::new(SizeRequirement::Range {
+            min: ROOT_MIN,
+            max: ROOT_MAX,
+        })
     }
 
     /// Creates a standard EFI system partition request
     fn efi_partition() -> PartitionRequest {
-        PartitionRequest {
-            size: SizeRequirement::Exact(EFI_SIZE),
-            attributes: None,
-        }
+        PartitionRequest::new(SizeRequirement::Exact(EFI_SIZE))
     }
 
     /// Creates a /boot partition request
     fn boot_partition() -> PartitionRequest {
-        PartitionRequest {
-            size: SizeRequirement::Exact(BOOT_SIZE),
-            attributes: None,
-        }
+        PartitionRequest::new(SizeRequirement::Exact(BOOT_SIZE))
     }
 
     /// Creates a swap partition request that scales with system RAM
     fn swap_partition() -> PartitionRequest {
-        PartitionRequest {
-            size: SizeRequirement::Range {
-                min: SWAP_MIN,
-                max: SWAP_MAX,
-            },
-            attributes: None,
-        }
+        PartitionRequest::new(SizeRequirement::Range {
+            min: SWAP_MIN,
+            max: SWAP_MAX,
+        })
     }
 
     /// Creates a home partition request that uses all remaining space
     fn home_partition() -> PartitionRequest {
-        PartitionRequest {
-            size: SizeRequirement::Remaining,
-            attributes: None,
-        }
+        PartitionRequest::new(SizeRequirement::Remaining)
     }
     fn create_test_disk() -> MockDisk {
         MockDisk::new(500 * GB)
     }
 
+    #[test]
+    fn test_explicit_partition_number() {
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_test_disk()));
+        let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+        strategy.add_request(boot_partition());
+        strategy.add_request(PartitionRequest {
+            number: Some(1),
+            ..efi_partition()
+        });
+        strategy.add_request(root_partition());
+        assert!(strategy.apply(&mut planner).is_ok());
+
+        // The ESP keeps number 1 despite being placed after /boot
+        let layout = planner.current_layout();
+        let ids = layout.iter().map(|r| r.partition_id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![Some(2), Some(1), Some(3)]);
+        assert_eq!(layout[1].size(), EFI_SIZE);
+
+        // Duplicate numbers are rejected without leaving partial changes
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_test_disk()));
+        let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+        strategy.add_request(PartitionRequest {
+            number: Some(1),
+            ..efi_partition()
+        });
+        strategy.add_request(PartitionRequest {
+            number: Some(1),
+            ..root_partition()
+        });
+        assert!(matches!(
+            strategy.apply(&mut planner),
+            Err(StrategyError::Plan(PlanError::PartitionNumberTaken { number: 1 }))
+        ));
+        assert!(!planner.has_changes());
+        assert_eq!(planner.allocate_partition_id(), 1);
+    }
+
+    #[test]
+    fn test_request_alignment() {
+        const HUGEPAGE: u64 = 2 * MB;
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_test_disk())).with_start_offset(MB);
+        let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+        strategy.add_request(PartitionRequest::new(SizeRequirement::Exact(EFI_SIZE)));
+        strategy.add_request(PartitionRequest {
+            alignment: Some(HUGEPAGE),
+            ..swap_partition()
+        });
+        strategy.add_request(root_partition());
+        assert!(strategy.apply(&mut planner).is_ok());
+
+        let layout = planner.current_layout();
+        assert_eq!(layout.len(), 3);
+        assert_eq!(layout[0].start, MB);
+        assert_eq!(layout[0].end, EFI_SIZE + MB);
+        assert!(layout[0].end % HUGEPAGE != 0);
+
+        // Swap is pushed onto the next hugepage boundary, the rest use 1MiB
+        assert_eq!(layout[1].start % HUGEPAGE, 0);
+        assert_eq!(layout[1].end % HUGEPAGE, 0);
+        assert!(layout[1].start > layout[0].end);
+        assert_eq!(layout[2].start % MB, 0);
+        assert!(layout[2].start % HUGEPAGE != 0);
+
+        // An exact size keeps every byte when its start is pushed forward
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_test_disk())).with_start_offset(MB);
+        let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+        strategy.add_request(PartitionRequest {
+            size: SizeRequirement::Exact(EFI_SIZE),
+            ..root_partition()
+        });
+        strategy.add_request(PartitionRequest {
+            size: SizeRequirement::Exact(SWAP_MIN),
+            alignment: Some(HUGEPAGE),
+            ..swap_partition()
+        });
+        strategy.add_request(root_partition());
+        assert!(strategy.apply(&mut planner).is_ok());
+
+        let layout = planner.current_layout();
+        assert_eq!(layout[0].end, 513 * MB);
+        assert_eq!(layout[1].start, 514 * MB);
+        assert_eq!(layout[1].size(), SWAP_MIN);
+        assert!(layout[2].start >= layout[1].end);
+    }
+
+    #[test]
+    fn test_first_fit_stable() {
+        // Gaps of 10GB and 30GB, followed by 200GB at the end of the disk
+        let mut disk = create_test_disk();
+        disk.add_partition(0, 100 * GB);
+        disk.add_partition(110 * GB, 200 * GB);
+        disk.add_partition(230 * GB, 300 * GB);
+        let device = BlockDevice::mock_device(disk);
+
+        let layout = || {
+            let mut planner = Planner::new(&device);
+            let mut strategy = Strategy::new(AllocationStrategy::FirstFit);
+            strategy.add_request(PartitionRequest {
+                size: SizeRequirement::AtLeast(5 * GB),
+                ..root_partition()
+            });
+            strategy.add_request(PartitionRequest {
+                size: SizeRequirement::Exact(15 * GB),
+                ..root_partition()
+            });
+            strategy.add_request(home_partition());
+            strategy.apply(&mut planner).unwrap();
+            planner
+                .current_layout()
+                .into_iter()
+                .filter(|r| r.partition_id > Some(3))
+                .map(|r| (r.start, r.end, r.partition_id))
+                .collect::<Vec<_>>()
+        };
+
+        // The 10GB gap is too small, so everything lands in the 30GB gap in request order
+        let first = layout();
+        assert_eq!(first.len(), 3);
+        assert_eq!(first[0].0, 200 * GB);
+        assert_eq!(first[0].2, Some(4));
+        assert_eq!(first[1].1 - first[1].0, 15 * GB);
+        assert_eq!(first[2].1, 230 * GB);
+        assert_eq!(first, layout());
+    }
+
+    #[test]
+    fn test_fit_strategies() {
+        // Gaps of 10GB and 30GB, followed by 200GB at the end of the disk
+        let mut disk = create_test_disk();
+        disk.add_partition(0, 100 * GB);
+        disk.add_partition(110 * GB, 200 * GB);
+        disk.add_partition(230 * GB, 300 * GB);
+        let device = BlockDevice::mock_device(disk);
+
+        let placed_at = |allocation| {
+            let mut planner = Planner::new(&device);
+            let mut strategy = Strategy::new(allocation);
+            strategy.add_request(PartitionRequest {
+                size: SizeRequirement::Range {
+                    min: 5 * GB,
+                    max: 25 * GB,
+                },
+                ..root_partition()
+            });
+            strategy.apply(&mut planner).unwrap();
+            let original = planner.original_regions().len();
+            let added = planner
+                .current_layout()
+                .into_iter()
+                .filter(|r| r.partition_id > Some(original as u32));
+            added.map(|r| r.start).collect::<Vec<_>>()
+        };
+
+        assert_eq!(placed_at(AllocationStrategy::default()), vec![300 * GB]);
+        assert_eq!(placed_at(AllocationStrategy::SmallestFree), vec![100 * GB]);
+        assert_eq!(placed_at(AllocationStrategy::BestFit), vec![200 * GB]);
+    }
+
+    #[test]
+    fn test_fill_gaps_first() {
+        let mut disk = create_test_disk();
+        disk.add_partition(0, 100 * GB);
+        disk.add_partition(110 * GB, 200 * GB);
+        let device = BlockDevice::mock_device(disk);
+
+        let mut planner = Planner::new(&device);
+        let mut strategy = Strategy::new(AllocationStrategy::FillGapsFirst);
+        strategy.add_request(PartitionRequest::new(SizeRequirement::Exact(5 * GB)));
+        assert!(strategy.apply(&mut planner).is_ok());
+        let added = planner.current_layout().into_iter().find(|r| r.start == 100 * GB);
+        assert_eq!(added.map(|r| r.end), Some(105 * GB));
+
+        // Requests that don't fit any gap fall back to the trailing region
+        let mut planner = Planner::new(&device);
+        let mut strategy = Strategy::new(AllocationStrategy::FillGapsFirst);
+        strategy.add_request(PartitionRequest::new(SizeRequirement::Exact(20 * GB)));
+        assert!(strategy.apply(&mut planner).is_ok());
+        assert!(planner.current_layout().iter().any(|r| r.start == 200 * GB));
+
+        // Open-ended requests always use the trailing region
+        let mut planner = Planner::new(&device);
+        let mut strategy = Strategy::new(AllocationStrategy::FillGapsFirst);
+        strategy.add_request(PartitionRequest::new(SizeRequirement::AtLeast(GB)));
+        assert!(strategy.apply(&mut planner).is_ok());
+        assert!(planner.current_layout().iter().any(|r| r.start == 200 * GB));
+    }
+
+    #[test]
+    fn test_esp_placed_first() {
+        let esp = || PartitionRequest {
+            attributes: Some(PartitionAttributes {
+                table: TableAttributes::Gpt(GptAttributes::default()),
+                role: Some(PartitionRole::Boot),
+                filesystem: None,
+            }),
+            ..efi_partition()
+        };
+
+        let device = BlockDevice::mock_device(create_test_disk());
+        let mut planner = Planner::new(&device);
+        let mut strategy = Strategy::new(AllocationStrategy::LargestFree);
+        strategy.add_request(PartitionRequest {
+            size: SizeRequirement::Exact(200 * GB),
+            ..root_partition()
+        });
+        strategy.add_request(swap_partition());
+        strategy.add_request(esp());
+        assert!(strategy.apply(&mut planner).is_ok());
+
+        // Requested last, behind larger exact requests, yet the ESP comes first
+        let layout = planner.current_layout();
+        assert_eq!(layout.len(), 3);
+        assert_eq!(layout[0].start, planner.allocation_start());
+        assert_eq!(layout[0].size(), EFI_SIZE);
+        assert_eq!(layout[0].partition_id, Some(1));
+        assert_eq!(layout[1].start, layout[0].end);
+
+        // The ESP takes the front gap even though the trailing region is the largest
+        let mut disk = create_test_disk();
+        disk.add_partition(10 * GB, 20 * GB);
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+        let mut strategy = Strategy::new(AllocationStrategy::LargestFree);
+        strategy.add_request(root_partition());
+        strategy.add_request(esp());
+        assert!(strategy.apply(&mut planner).is_ok());
+        let layout = planner.current_layout();
+        assert_eq!(layout[0].start, planner.allocation_start());
+        assert_eq!(layout[0].size(), EFI_SIZE);
+        assert_eq!(layout[2].start, 20 * GB);
+    }
+
+    #[test]
+    fn test_priority() {
+        let with_role = |request: PartitionRequest, role| PartitionRequest {
+            attributes: Some(PartitionAttributes {
+                table: TableAttributes::Gpt(GptAttributes::default()),
+                role: Some(role),
+                filesystem: None,
+            }),
+            ..request
+        };
+        let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+        strategy.add_request(with_role(efi_partition(), PartitionRole::Boot));
+        strategy.add_request(with_role(boot_partition(), PartitionRole::ExtendedBoot));
+        strategy.add_request(with_role(swap_partition(), PartitionRole::Swap));
+        strategy.add_request(root_partition());
+
+        // Everything but root fits, so root takes the blame and nothing is planned
+        let device = BlockDevice::mock_device(MockDisk::new(12 * GB));
+        let mut planner = Planner::new(&device);
+        assert!(matches!(
+            strategy.apply(&mut planner),
+            Err(StrategyError::InsufficientSpace { index: 3, .. })
+        ));
+        assert!(!planner.has_changes());
+
+        // Leaving root out, the boot partitions and swap are placed
+        assert_eq!(strategy.apply_by_priority(&mut planner).unwrap(), vec![3]);
+        let layout = planner.current_layout();
+        assert_eq!(layout.len(), 3);
+        assert_eq!(layout[0].size(), EFI_SIZE);
+        assert_eq!(layout[1].size(), BOOT_SIZE);
+        assert_eq!(layout[2].size(), SWAP_MAX);
+
+        // An explicit priority outranks the role
+        let mut strategy = strategy.clone();
+        strategy.requests[3].priority = Some(3);
+        let device = BlockDevice::mock_device(MockDisk::new(21 * GB));
+        let mut planner = Planner::new(&device);
+        assert_eq!(strategy.apply_by_priority(&mut planner).unwrap(), vec![1, 2]);
+        let layout = planner.current_layout();
+        assert_eq!(layout.len(), 2);
+        assert!(layout[1].size() >= ROOT_MIN);
+
+        // Nothing can be dropped in favour of the highest priority request
+        let device = BlockDevice::mock_device(MockDisk::new(10 * GB));
+        let mut planner = Planner::new(&device);
+        assert!(matches!(
+            strategy.apply_by_priority(&mut planner),
+            Err(StrategyError::InsufficientSpace { index: 3, .. })
+        ));
+        assert!(!planner.has_changes());
+    }
+
+    #[test]
+    fn test_planner_alignment() {
+        const ALIGNMENT: u64 = 8 * MB;
+        let device = BlockDevice::mock_device(MockDisk::new(500 * GB + 3 * MB));
+        let mut planner = Planner::new(&device).with_alignment(ALIGNMENT).unwrap();
+        let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+        for _ in 0..3 {
+            strategy.add_request(PartitionRequest {
+                size: SizeRequirement::AtLeast(GB + MB),
+                ..root_partition()
+            });
+        }
+        strategy.add_request(PartitionRequest {
+            size: SizeRequirement::Percent(33),
+            ..root_partition()
+        });
+        strategy.add_request(home_partition());
+        strategy.apply(&mut planner).unwrap();
+
+        // Every slice is aligned, and none are lost to alignment between them
+        let layout = planner.current_layout();
+        assert_eq!(layout.len(), 5);
+        for region in &layout {
+            assert_eq!(region.start % ALIGNMENT, 0);
+            assert_eq!(region.end % ALIGNMENT, 0);
+        }
+        for pair in layout.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+        }
+    }
+
+    #[test]
+    fn test_percent_requests() {
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_test_disk()));
+        let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+        strategy.add_request(efi_partition());
+        strategy.add_request(PartitionRequest {
+            size: SizeRequirement::Percent(90),
+            ..root_partition()
+        });
+        strategy.add_request(home_partition());
+        assert!(strategy.apply(&mut planner).is_ok());
+
+        let layout = planner.current_layout();
+        assert_eq!(layout.len(), 3);
+        assert_eq!(layout[0].size(), EFI_SIZE);
+        let root = 500 * GB / 10 * 9 / MB * MB;
+        assert_eq!(layout[1].size(), root);
+        assert_eq!(layout[2].end, 500 * GB);
+        assert!(strategy.describe().contains("90% of the disk"));
+
+        // Percentages must be between 1 and 100, also in total
+        let invalid = |percents: &[u8]| {
+            let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+            for percent in percents {
+                strategy.add_request(PartitionRequest {
+                    size: SizeRequirement::Percent(*percent),
+                    ..root_partition()
+                });
+            }
+            let mut planner = Planner::new(&BlockDevice::mock_device(create_test_disk()));
+            matches!(
+                strategy.apply(&mut planner),
+                Err(StrategyError::Plan(PlanError::InvalidPercentage { .. }))
+            )
+        };
+        assert!(invalid(&[0]));
+        assert!(invalid(&[101]));
+        assert!(invalid(&[60, 50]));
+        assert!(!invalid(&[60, 40]));
+
+        // The whole disk leaves no room for anything exact
+        let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+        strategy.add_request(efi_partition());
+        strategy.add_request(PartitionRequest {
+            size: SizeRequirement::Percent(100),
+            ..root_partition()
+        });
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_test_disk()));
+        assert!(strategy.apply(&mut planner).is_err());
+    }
+
+    #[test]
+    fn test_fraction_of_remaining() {
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_test_disk()));
+        let mut strategy = Strategy::new(AllocationStrategy::SpecificRegion(Region::new(MB, 400 * GB + MB)));
+        let half = || PartitionRequest {
+            size: SizeRequirement::FractionOfRemaining {
+                numerator: 1,
+                denominator: 2,
+            },
+            ..home_partition()
+        };
+        strategy.add_request(half());
+        strategy.add_request(half());
+        assert!(strategy.apply(&mut planner).is_ok());
+
+        let layout = planner.current_layout();
+        assert_eq!(layout.len(), 2);
+        assert_eq!(layout[0].size(), 200 * GB);
+        assert_eq!(layout[1].size(), 100 * GB);
+        assert!(strategy.describe().contains("1/2 of remaining space"));
+    }
+
+    #[test]
+    fn test_remaining_split() {
+        // An odd sized region, so the split can't be exact
+        let region = Region::new(MB, 300 * GB + 7 * MB);
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_test_disk()));
+        let mut strategy = Strategy::new(AllocationStrategy::SpecificRegion(region.clone()));
+        strategy.add_request(home_partition());
+        strategy.add_request(home_partition());
+        assert!(strategy.apply(&mut planner).is_ok());
+
+        let layout = planner.current_layout();
+        assert_eq!(layout.len(), 2);
+        assert_eq!(layout[0].size(), 150 * GB + 3 * MB);
+        assert_eq!(layout[1].size(), 150 * GB + 3 * MB);
+        assert!(layout.iter().all(|r| r.start % PARTITION_ALIGNMENT == 0));
+        assert!(layout.iter().map(|r| r.size()).sum::<u64>() <= region.size());
+    }
+
+    #[test]
+    fn test_remaining_weighted() {
+        let region = Region::new(MB, 400 * GB + 6 * MB);
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_test_disk()));
+        let mut strategy = Strategy::new(AllocationStrategy::SpecificRegion(region.clone()));
+        strategy.add_request(PartitionRequest {
+            size: SizeRequirement::RemainingWeighted(3),
+            ..home_partition()
+        });
+        strategy.add_request(home_partition());
+        assert!(strategy.apply(&mut planner).is_ok());
+
+        // The slack left by aligning root goes to home
+        let layout = planner.current_layout();
+        assert_eq!(layout.len(), 2);
+        assert_eq!(layout[0].size(), 300 * GB + 3 * MB);
+        assert_eq!(layout[1].size(), 100 * GB + 2 * MB);
+        assert_eq!(layout[1].end, region.end);
+        assert!(layout.iter().map(|r| r.size()).sum::<u64>() <= region.size());
+        assert!(strategy.describe().contains("remaining space (weight 3)"));
+    }
+
+    #[test]
+    fn test_proportional_sizing_large_disk() {
+        const TIB: u64 = 1024 * GB;
+        let usable = 16 * TIB;
+        assert_eq!(scale_size(usable, 80, 100), usable / 5 * 4);
+
+        let request = PartitionRequest {
+            grow_reserve_percent: 80,
+            ..PartitionRequest::new(SizeRequirement::Exact(usable))
+        };
+        let footprint = request.footprint(usable);
+        assert_eq!(footprint, usable + usable / 5 * 4);
+        assert_eq!(request.size_within(18 * TIB), 10 * TIB);
+        assert_eq!(request.footprint(u64::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn test_grow_reserve() {
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_test_disk()));
+        let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+        strategy.add_request(PartitionRequest {
+            grow_reserve_percent: 10,
+            ..PartitionRequest::new(SizeRequirement::Exact(100 * GB))
+        });
+        strategy.add_request(home_partition());
+        assert!(strategy.apply(&mut planner).is_ok());
+
+        let layout = planner.current_layout();
+        assert_eq!(layout.len(), 2);
+        assert_eq!(layout[0].size(), 100 * GB);
+        assert_eq!(layout[1].start, 110 * GB);
+
+        // The reserve sits directly after its partition and can't be allocated
+        let reserved = planner.reserved_regions();
+        assert_eq!(reserved.len(), 1);
+        assert_eq!(reserved[0].start, 100 * GB);
+        assert_eq!(reserved[0].size(), 10 * GB);
+        assert!(planner.plan_add_partition(100 * GB, 110 * GB).is_err());
+
+        let map = planner.layout_map();
+        assert!(
+            map.iter()
+                .any(|s| s.kind == SegmentKind::Reserved && s.region.start == 100 * GB && s.region.end == 110 * GB)
+        );
+    }
+
+    #[test]
+    fn test_grow_reserve_reapply() {
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_test_disk()));
+        let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+        strategy.add_request(PartitionRequest {
+            grow_reserve_percent: 10,
+            ..PartitionRequest::new(SizeRequirement::Exact(100 * GB))
+        });
+        strategy.add_request(home_partition());
+        assert!(strategy.apply(&mut planner).is_ok());
+        let first = planner.current_layout();
+        assert_eq!(planner.reserved_regions().len(), 1);
+
+        // Resetting releases the reserve along with its partition
+        planner.reset();
+        assert!(planner.reserved_regions().is_empty());
+
+        assert!(strategy.apply(&mut planner).is_ok());
+        let second = planner.current_layout();
+        assert_eq!(first.len(), second.len());
+        assert!(first.iter().zip(&second).all(|(a, b)| a.same_extent(b)));
+        assert_eq!(planner.reserved_regions().len(), 1);
+        assert_eq!(planner.reserved_regions()[0].start, 100 * GB);
+
+        // Undoing the changes releases it as well
+        while planner.has_changes() {
+            planner.undo();
+        }
+        assert!(planner.reserved_regions().is_empty());
+    }
+
+    #[test]
+    fn test_remaining_minus_reserve() {
+        let disk = MockDisk::new(400 * GB);
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+        let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+        strategy.add_request(PartitionRequest::new(SizeRequirement::RemainingMinus(20 * GB)));
+        assert!(strategy.apply(&mut planner).is_ok());
+
+        let layout = planner.current_layout();
+        assert_eq!(layout.len(), 1);
+        assert_eq!(layout[0].size(), 380 * GB);
+        assert_eq!(planner.usable_size() - layout[0].end, 20 * GB);
+    }
+
+    #[test]
+    fn test_bounded_requests() {
+        let bounded_swap = || {
+            PartitionRequest::new(SizeRequirement::Bounded {
+                min: SWAP_MIN,
+                recommended: SWAP_MAX,
+                max: 16 * GB,
+            })
+        };
+
+        // Ample space uses the recommended size
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_test_disk()));
+        let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+        strategy.add_request(efi_partition());
+        strategy.add_request(bounded_swap());
+        strategy.add_request(root_partition());
+        assert!(strategy.apply(&mut planner).is_ok());
+
+        let layout = planner.current_layout();
+        assert_eq!(layout[1].size(), SWAP_MAX);
+        assert_eq!(layout[2].size(), 500 * GB - EFI_SIZE - SWAP_MAX);
+
+        // Tight space shrinks toward the minimum, leaving room for root
+        let disk = MockDisk::new(EFI_SIZE + SWAP_MIN + ROOT_MIN);
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+        assert!(strategy.apply(&mut planner).is_ok());
+
+        let layout = planner.current_layout();
+        assert_eq!(layout[1].size(), SWAP_MIN);
+        assert_eq!(layout[2].size(), ROOT_MIN);
+    }
+
+    #[test]
+    fn test_avoid_bad_regions() {
+        let mut planner = Planner::new(&BlockDevice::mock_device(create_test_disk()));
+        planner.mark_bad_region(10 * GB, 11 * GB).unwrap();
+
+        let mut strategy = Strategy::new(AllocationStrategy::LargestFree);
+        strategy.add_request(efi_partition());
+        strategy.add_request(root_partition());
+        assert!(strategy.apply(&mut planner).is_ok());
+
+        let layout = planner.current_layout();
+        assert_eq!(layout.len(), 2);
+        assert_eq!(layout[0].start, 11 * GB);
+        assert_eq!(layout[1].end, 500 * GB);
+    }
+
+    #[test]
+    fn test_grouped_requests() {
+        let disk = create_test_disk();
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+        let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+
+        strategy.add_request(efi_partition());
+        strategy.add_request(PartitionRequest {
+            group: Some("system".into()),
+            ..capped_root_partition()
+        });
+        strategy.add_request(swap_partition());
+        strategy.add_request(PartitionRequest {
+            group: Some("system".into()),
+            ..home_partition()
+        });
+
+        assert!(strategy.apply(&mut planner).is_ok());
+
+        let layout = planner.current_layout();
+        assert_eq!(layout.len(), 4);
+
+        // Root and home are adjacent, with swap placed after them
+        assert_eq!(layout[0].size(), EFI_SIZE);
+        assert_eq!(layout[1].end, layout[2].start);
+        assert!(layout[1].size() >= ROOT_MIN && layout[1].size() <= ROOT_MAX);
+        assert_eq!(layout[2].end, layout[3].start);
+        assert!(layout[3].size() >= SWAP_MIN && layout[3].size() <= SWAP_MAX);
+        assert_eq!(layout[3].end, 500 * GB);
+    }
+
     #[test]
     fn test_uefi_clean_install() {
         // Test case: Clean UEFI installation with separate /home
@@ -403,10 +1630,7 @@ mod tests {
 
         // Simple layout - just boot and root
         strategy.add_request(boot_partition());
-        strategy.add_request(PartitionRequest {
-            size: SizeRequirement::Remaining,
-            attributes: None,
-        });
+        strategy.add_request(PartitionRequest::new(SizeRequirement::Remaining));
 
         eprintln!("\nMinimal Server Strategy:\n{}", strategy.describe());
         assert!(strategy.apply(&mut planner).is_ok());
@@ -423,10 +1647,7 @@ mod tests {
         let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
 
         // Try to allocate more than available
-        strategy.add_request(PartitionRequest {
-            size: SizeRequirement::Exact(20 * GB),
-            attributes: None,
-        });
+        strategy.add_request(PartitionRequest::new(SizeRequirement::Exact(20 * GB)));
 
         assert!(strategy.apply(&mut planner).is_err());
     }
@@ -438,18 +1659,39 @@ mod tests {
         let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
 
         // Request more than available in flexible partitions
+        strategy.add_request(PartitionRequest::new(SizeRequirement::AtLeast(6 * GB)));
+        strategy.add_request(PartitionRequest::new(SizeRequirement::AtLeast(6 * GB)));
+
+        // Should fail because total minimum (12GB) exceeds disk size (10GB)
+        let result = strategy.apply(&mut planner);
+        assert!(matches!(
+            result,
+            Err(StrategyError::InsufficientSpace { index: 1, needed, .. }) if needed == 6 * GB
+        ));
+        assert!(!planner.has_changes());
+    }
+
+    #[test]
+    fn test_conflicting_fixed_sizes() {
+        let disk = MockDisk::new(10 * GB);
+        let mut planner = Planner::new(&BlockDevice::mock_device(disk));
+        let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
         strategy.add_request(PartitionRequest {
-            size: SizeRequirement::AtLeast(6 * GB),
-            attributes: None,
+            size: SizeRequirement::Exact(6 * GB),
+            ..root_partition()
         });
+        strategy.add_request(home_partition());
         strategy.add_request(PartitionRequest {
-            size: SizeRequirement::AtLeast(6 * GB),
-            attributes: None,
+            size: SizeRequirement::Exact(6 * GB),
+            ..root_partition()
         });
 
-        // Should fail because total minimum (12GB) exceeds disk size (10GB)
-        let result = strategy.apply(&mut planner);
-        assert!(matches!(result, Err(PlanError::RegionOutOfBounds { .. })));
+        let err = strategy.apply(&mut planner).unwrap_err();
+        assert!(matches!(
+            &err,
+            StrategyError::ConflictingFixedSizes { indices, total, .. } if indices == &[0, 2] && *total == 12 * GB
+        ));
+        assert_eq!(err.request_index(), None);
         assert!(!planner.has_changes());
     }
 
@@ -460,28 +1702,20 @@ mod tests {
         let mut strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
 
         // Request sequence where first two would fit but third won't
-        strategy.add_request(PartitionRequest {
-            size: SizeRequirement::Range { min: GB, max: 2 * GB },
-            attributes: None,
-        });
-        strategy.add_request(PartitionRequest {
-            size: SizeRequirement::Range {
-                min: 2 * GB,
-                max: 4 * GB,
-            },
-            attributes: None,
-        });
-        strategy.add_request(PartitionRequest {
-            size: SizeRequirement::Range {
-                min: 25 * GB,
-                max: 120 * GB,
-            },
-            attributes: None,
-        });
+        strategy.add_request(PartitionRequest::new(SizeRequirement::Range { min: GB, max: 2 * GB }));
+        strategy.add_request(PartitionRequest::new(SizeRequirement::Range {
+            min: 2 * GB,
+            max: 4 * GB,
+        }));
+        strategy.add_request(PartitionRequest::new(SizeRequirement::Range {
+            min: 25 * GB,
+            max: 120 * GB,
+        }));
 
         // Should fail and undo partial changes
         let result = strategy.apply(&mut planner);
-        assert!(matches!(result, Err(PlanError::RegionOutOfBounds { .. })));
+        assert!(matches!(result, Err(StrategyError::InsufficientSpace { index: 2, .. })));
+        assert_eq!(result.unwrap_err().request_index(), Some(2));
         assert!(!planner.has_changes(), "Partial changes should be undone");
     }
 }
@@ -126,6 +126,7 @@ impl<'a> DiskWriter<'a> {
                 Change::DeletePartition { partition_id, .. } => {
                     used_ids.remove(partition_id);
                 }
+                Change::ResizePartition { .. } | Change::SetAttributes { .. } | Change::InitializeDisk { .. } => {}
             }
         }
 
@@ -185,6 +186,40 @@ impl<'a> DiskWriter<'a> {
                         println!("Deleted partition {partition_id} (index {original_index}): {id:?}");
                     }
                 }
+                Change::SetAttributes {
+                    partition_id,
+                    attributes,
+                    ..
+                } => {
+                    let mut partitions = gpt_table.partitions().clone();
+                    if let (Some(partition), Some(gpt)) = (partitions.get_mut(partition_id), attributes.table.as_gpt())
+                    {
+                        partition.part_type_guid = gpt.type_guid.clone();
+                        partition.flags = gpt.flags;
+                        if let Some(name) = &gpt.name {
+                            partition.name = name.clone();
+                        }
+                        if let Some(uuid) = gpt.uuid {
+                            partition.part_guid = uuid;
+                        }
+                        gpt_table.update_partitions(partitions)?;
+                        println!("Updated attributes of partition {partition_id}");
+                    }
+                }
+                Change::ResizePartition {
+                    partition_id,
+                    start,
+                    end,
+                    ..
+                } => {
+                    let mut partitions = gpt_table.partitions().clone();
+                    if let Some(partition) = partitions.get_mut(partition_id) {
+                        partition.first_lba = *start / SECTOR_SIZE;
+                        partition.last_lba = *end / SECTOR_SIZE - 1;
+                        gpt_table.update_partitions(partitions)?;
+                        println!("Resized partition {partition_id}");
+                    }
+                }
                 Change::AddPartition {
                     start,
                     end,
@@ -195,11 +230,11 @@ impl<'a> DiskWriter<'a> {
                     let start_lba = *start / SECTOR_SIZE;
                     let size_bytes = *end - *start;
                     let size_lba = size_bytes / SECTOR_SIZE;
-                    let (part_type, part_name) = match attributes.as_ref().and_then(|a| a.table.as_gpt()) {
-                        Some(GptAttributes { type_guid, name, .. }) => {
-                            (type_guid.clone(), name.clone().unwrap_or_default())
-                        }
-                        None => (partition_types::BASIC, "".to_string()),
+                    let (part_type, part_name, flags) = match attributes.as_ref().and_then(|a| a.table.as_gpt()) {
+                        Some(GptAttributes {
+                            type_guid, name, flags, ..
+                        }) => (type_guid.clone(), name.clone().unwrap_or_default(), *flags),
+                        None => (partition_types::BASIC, "".to_string(), 0),
                     };
 
                     eprintln!(
@@ -210,13 +245,15 @@ impl<'a> DiskWriter<'a> {
                         start_lba + size_lba
                     );
                     let id =
-                        gpt_table.add_partition_at(&part_name, *partition_id, start_lba, size_lba, part_type, 0)?;
+                        gpt_table.add_partition_at(&part_name, *partition_id, start_lba, size_lba, part_type, flags)?;
                     println!("Added partition {partition_id}: {id:?}");
                     // Store start and size for zeroing
                     if writable {
                         zero_regions.push((*start, *end));
                     }
                 }
+                // The fresh table was already created above when wiping the disk
+                Change::InitializeDisk { .. } => {}
             }
         }
 
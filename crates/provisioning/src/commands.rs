@@ -6,15 +6,21 @@
 use crate::Context;
 
 mod create_partition;
+mod create_partition_from_image;
 mod create_partition_table;
 mod find_disk;
+mod keep_partition;
+mod reserve_gap;
 
 /// A command
 #[derive(Debug)]
 pub enum Command {
     CreatePartition(Box<create_partition::Command>),
+    CreatePartitionFromImage(Box<create_partition_from_image::Command>),
     CreatePartitionTable(Box<create_partition_table::Command>),
     FindDisk(Box<find_disk::Command>),
+    KeepPartition(Box<keep_partition::Command>),
+    ReserveGap(Box<reserve_gap::Command>),
 }
 
 /// Command execution function
@@ -24,7 +30,10 @@ type CommandExec = for<'a> fn(Context<'a>) -> Result<Command, crate::Error>;
 static COMMANDS: phf::Map<&'static str, CommandExec> = phf::phf_map! {
     "find-disk" => find_disk::parse,
     "create-partition" => create_partition::parse,
+    "create-partition-from-image" => create_partition_from_image::parse,
     "create-partition-table" => create_partition_table::parse,
+    "keep-partition" => keep_partition::parse,
+    "reserve-gap" => reserve_gap::parse,
 };
 
 /// Parse a command from a node if possible
@@ -7,7 +7,7 @@ use partitioning::{GptAttributes, PartitionAttributes, TableAttributes, gpt::par
 
 use crate::{
     Constraints, Context, Filesystem, FromKdlProperty, FromKdlType, PartitionRole, PartitionTypeGuid, PartitionTypeKDL,
-    get_kdl_entry, get_kdl_property, get_property_str,
+    get_kdl_entry, get_kdl_property, get_property_str, kdl_value_to_integer, kdl_value_to_string,
 };
 
 /// Command to create a partition
@@ -30,6 +30,15 @@ pub struct Command {
 
     /// The filesystem to format the partition with
     pub filesystem: Option<Filesystem>,
+
+    /// Placement group, keeping related partitions adjacent
+    pub group: Option<String>,
+
+    /// Options to mount the partition with
+    pub mount_options: Vec<String>,
+
+    /// Explicit partition number, for firmware that refers to partitions by number
+    pub number: Option<u32>,
 }
 
 impl Command {
@@ -42,6 +51,7 @@ impl Command {
                 },
                 name: self.partition_type.as_ref().map(|p| p.to_string()),
                 uuid: None,
+                flags: 0,
             }),
             role: self.role.clone(),
             filesystem: self.filesystem.clone(),
@@ -53,15 +63,22 @@ impl Command {
 pub(crate) fn parse(context: Context<'_>) -> Result<super::Command, crate::Error> {
     let disk = get_property_str(context.node, "disk")?;
     let id = get_property_str(context.node, "id")?;
+    let group = get_property_str(context.node, "group").ok();
     let role = if let Ok(role) = get_kdl_property(context.node, "role") {
         Some(PartitionRole::from_kdl_property(role)?)
     } else {
         None
     };
+    let number = if let Ok(number) = get_kdl_property(context.node, "number") {
+        Some(kdl_value_to_integer(number)? as u32)
+    } else {
+        None
+    };
 
     let mut constraints = Constraints::default();
     let mut partition_type = None;
     let mut filesystem = None;
+    let mut mount_options = vec![];
 
     for child in context.node.iter_children() {
         match child.name().value() {
@@ -72,6 +89,11 @@ pub(crate) fn parse(context: Context<'_>) -> Result<super::Command, crate::Error
                 }
             }
             "filesystem" => filesystem = Some(Filesystem::from_kdl_node(child)?),
+            "mount-options" => {
+                for entry in child.entries() {
+                    mount_options.push(kdl_value_to_string(entry)?);
+                }
+            }
             _ => {
                 return Err(crate::UnsupportedNode {
                     at: child.span(),
@@ -97,5 +119,8 @@ pub(crate) fn parse(context: Context<'_>) -> Result<super::Command, crate::Error
         constraints,
         partition_type,
         filesystem,
+        group,
+        mount_options,
+        number,
     })))
 }
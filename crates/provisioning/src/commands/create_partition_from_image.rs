@@ -0,0 +1,106 @@
+// SPDX-FileCopyrightText: Copyright © 2025 AerynOS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::path::PathBuf;
+
+use partitioning::{GptAttributes, PartitionAttributes, TableAttributes, gpt::partition_types};
+
+use crate::{
+    Context, FromKdlProperty, FromKdlType, PartitionRole, PartitionTypeGuid, PartitionTypeKDL, get_kdl_entry,
+    get_kdl_property, get_property_str, kdl_value_to_storage_size,
+};
+
+/// Command to create a partition whose contents are copied from an image
+#[derive(Debug)]
+pub struct Command {
+    /// The disk ID to create the partition on
+    pub disk: String,
+
+    /// The reference ID of the partition, also used as its GPT name
+    pub id: String,
+
+    /// The role, if any, of the partition
+    pub role: Option<PartitionRole>,
+
+    /// Size of the image in bytes, which the partition must hold
+    pub image_size: u64,
+
+    /// Path to the image to be written into the partition
+    pub source: PathBuf,
+
+    /// The GPT partition type, if any
+    pub partition_type: Option<PartitionTypeGuid>,
+}
+
+impl Command {
+    /// Get the partition attributes
+    pub fn attributes(&self) -> PartitionAttributes {
+        PartitionAttributes {
+            table: TableAttributes::Gpt(GptAttributes {
+                type_guid: match &self.partition_type {
+                    Some(p) => p.as_guid(),
+                    None => partition_types::BASIC,
+                },
+                name: Some(self.id.clone()),
+                uuid: None,
+                flags: 0,
+            }),
+            role: self.role.clone(),
+            filesystem: None,
+        }
+    }
+}
+
+/// Generate a command to create a partition from an image
+pub(crate) fn parse(context: Context<'_>) -> Result<super::Command, crate::Error> {
+    let disk = get_property_str(context.node, "disk")?;
+    let id = get_property_str(context.node, "id")?;
+    let source = get_property_str(context.node, "source")?.into();
+    let role = if let Ok(role) = get_kdl_property(context.node, "role") {
+        Some(PartitionRole::from_kdl_property(role)?)
+    } else {
+        None
+    };
+
+    let mut image_size = None;
+    let mut partition_type = None;
+
+    for child in context.node.iter_children() {
+        match child.name().value() {
+            "image-size" => image_size = Some(kdl_value_to_storage_size(get_kdl_entry(child, &0)?)?),
+            "type" => {
+                partition_type = match PartitionTypeKDL::from_kdl_type(get_kdl_entry(child, &0)?)? {
+                    PartitionTypeKDL::GUID => Some(PartitionTypeGuid::from_kdl_node(child)?),
+                }
+            }
+            _ => {
+                return Err(crate::UnsupportedNode {
+                    at: child.span(),
+                    name: child.name().value().into(),
+                }
+                .into());
+            }
+        }
+    }
+
+    let Some(image_size) = image_size else {
+        return Err(crate::InvalidArguments {
+            at: context.node.span(),
+            advice: Some(
+                "create-partition-from-image [disk=<disk>] [id=<id>] [source=<path>] { image-size <size> } - you must provide the image size"
+                    .into(),
+            ),
+        }
+        .into());
+    };
+
+    Ok(super::Command::CreatePartitionFromImage(Box::new(Command {
+        disk,
+        id,
+        role,
+        image_size,
+        source,
+        partition_type,
+    })))
+}
@@ -4,12 +4,17 @@
 
 use itertools::Itertools;
 
-use crate::{Constraints, Context};
+use crate::{Constraints, Context, get_property_str, kdl_value_to_bool};
 
 #[derive(Debug)]
 pub struct Command {
     pub name: String,
     pub constraints: Option<Constraints>,
+    /// Only match the disk with this World Wide Name
+    pub wwn: Option<String>,
+
+    /// Bind every matching disk as a pool, rather than branching per disk
+    pub all: bool,
 }
 
 /// Generate a command to find a disk
@@ -49,8 +54,16 @@ pub(crate) fn parse(context: Context<'_>) -> Result<super::Command, crate::Error
             None
         };
 
+    let wwn = get_property_str(context.node, "wwn").ok();
+    let all = match context.node.entry("all") {
+        Some(entry) => kdl_value_to_bool(entry)?,
+        None => false,
+    };
+
     Ok(super::Command::FindDisk(Box::new(Command {
         name: name.to_owned(),
         constraints,
+        wwn,
+        all,
     })))
 }
@@ -0,0 +1,85 @@
+// SPDX-FileCopyrightText: Copyright © 2025 AerynOS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use partitioning::planner::Region;
+
+use crate::{
+    Context, FromKdlProperty, FromKdlType, PartitionRole, PartitionTypeGuid, PartitionTypeKDL, get_kdl_entry,
+    get_property_str, kdl_value_to_integer,
+};
+
+/// How an existing partition is matched
+#[derive(Debug)]
+pub enum PartitionMatch {
+    /// Match by index into the existing partitions
+    Index(usize),
+
+    /// Match by the role implied by the partition type
+    Role(PartitionRole),
+
+    /// Match by the partition type GUID
+    TypeGuid(PartitionTypeGuid),
+}
+
+impl PartitionMatch {
+    /// Returns true if the existing region at `index` matches
+    pub fn matches(&self, index: usize, region: &Region) -> bool {
+        let attributes = region.attributes.as_ref();
+        match self {
+            PartitionMatch::Index(i) => *i == index,
+            PartitionMatch::Role(role) => attributes.and_then(|a| a.role.as_ref()) == Some(role),
+            PartitionMatch::TypeGuid(guid) => attributes
+                .and_then(|a| a.table.as_gpt())
+                .is_some_and(|gpt| gpt.type_guid == guid.as_guid()),
+        }
+    }
+}
+
+/// Command to keep an existing partition
+#[derive(Debug)]
+pub struct Command {
+    /// The disk ID holding the partition
+    pub disk: String,
+
+    /// How to find the partition(s) to keep
+    pub match_by: PartitionMatch,
+}
+
+/// Generate a command to keep an existing partition
+pub(crate) fn parse(context: Context<'_>) -> Result<super::Command, crate::Error> {
+    let disk = get_property_str(context.node, "disk")?;
+
+    let mut match_by = None;
+    for child in context.node.iter_children() {
+        let matcher = match child.name().value() {
+            "index" => PartitionMatch::Index(kdl_value_to_integer(get_kdl_entry(child, &0)?)? as usize),
+            "role" => PartitionMatch::Role(PartitionRole::from_kdl_property(get_kdl_entry(child, &0)?)?),
+            "type" => match PartitionTypeKDL::from_kdl_type(get_kdl_entry(child, &0)?)? {
+                PartitionTypeKDL::GUID => PartitionMatch::TypeGuid(PartitionTypeGuid::from_kdl_node(child)?),
+            },
+            _ => {
+                return Err(crate::UnsupportedNode {
+                    at: child.span(),
+                    name: child.name().value().into(),
+                }
+                .into());
+            }
+        };
+
+        if match_by.replace(matcher).is_some() {
+            return Err(crate::InvalidArguments {
+                at: child.span(),
+                advice: Some("keep-partition accepts only one of index, role or type".into()),
+            }
+            .into());
+        }
+    }
+
+    let match_by = match_by.ok_or_else(|| crate::InvalidArguments {
+        at: context.node.span(),
+        advice: Some("keep-partition disk=<disk> { index <n> | role <role> | type (GUID)<type> }".into()),
+    })?;
+
+    Ok(super::Command::KeepPartition(Box::new(Command { disk, match_by })))
+}
@@ -0,0 +1,23 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::{Context, get_kdl_property, get_property_str, kdl_value_to_storage_size};
+
+/// Command to leave unpartitioned space before the next partition
+#[derive(Debug)]
+pub struct Command {
+    /// The disk ID to leave the gap on
+    pub disk: String,
+
+    /// Size of the gap in bytes
+    pub size: u64,
+}
+
+/// Generate a command to reserve a gap between partitions
+pub(crate) fn parse(context: Context<'_>) -> Result<super::Command, crate::Error> {
+    let disk = get_property_str(context.node, "disk")?;
+    let size = kdl_value_to_storage_size(get_kdl_property(context.node, "size")?)?;
+
+    Ok(super::Command::ReserveGap(Box::new(Command { disk, size })))
+}
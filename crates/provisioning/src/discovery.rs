@@ -0,0 +1,78 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+/// Options controlling which devices are returned by [`crate::Provisioner::discover_devices_with`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiscoveryOptions {
+    /// Include removable devices such as USB sticks and card readers
+    pub include_removable: bool,
+
+    /// Include loopback devices
+    pub include_loop: bool,
+}
+
+impl DiscoveryOptions {
+    /// Whether a whole disk with the given kernel name should be considered
+    ///
+    /// RAM-backed devices are never useful install targets, so they are
+    /// always excluded.
+    pub fn includes(&self, name: &str, removable: bool) -> bool {
+        if name.starts_with("ram") || name.starts_with("zram") {
+            return false;
+        }
+        if name.starts_with("loop") && !self.include_loop {
+            return false;
+        }
+        self.include_removable || !removable
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn discover_devices(options: &DiscoveryOptions) -> std::io::Result<Vec<disks::BlockDevice>> {
+    use std::fs;
+
+    use disks::BlockDevice;
+
+    let mut names = fs::read_dir("/sys/block")?
+        .filter_map(Result::ok)
+        .filter_map(|e| Some(e.file_name().to_str()?.to_owned()))
+        .collect::<Vec<_>>();
+    names.sort();
+
+    let devices = names
+        .into_iter()
+        .filter(|name| {
+            let removable = fs::read_to_string(format!("/sys/block/{name}/removable")).is_ok_and(|r| r.trim() == "1");
+            options.includes(name, removable)
+        })
+        .filter_map(|name| BlockDevice::from_sysfs_path("/", &name).ok())
+        .collect();
+
+    Ok(devices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_includes() {
+        let options = DiscoveryOptions::default();
+        assert!(options.includes("sda", false));
+        assert!(options.includes("nvme0n1", false));
+        assert!(!options.includes("sdb", true));
+        assert!(!options.includes("loop0", false));
+        assert!(!options.includes("ram0", false));
+        assert!(!options.includes("zram0", false));
+
+        let options = DiscoveryOptions {
+            include_removable: true,
+            include_loop: true,
+        };
+        assert!(options.includes("sdb", true));
+        assert!(options.includes("loop0", false));
+        assert!(!options.includes("ram0", false));
+        assert!(!options.includes("zram0", false));
+    }
+}
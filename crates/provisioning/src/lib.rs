@@ -2,7 +2,7 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::{fs, path::Path, sync::Arc};
+use std::{collections::HashMap, fs, path::Path, sync::Arc};
 
 use itertools::{Either, Itertools};
 use kdl::{KdlDocument, KdlNode};
@@ -11,6 +11,12 @@ use miette::{Diagnostic, NamedSource, Severity};
 mod provisioner;
 pub use provisioner::*;
 
+mod discovery;
+pub use discovery::*;
+
+mod manifest;
+pub use manifest::*;
+
 mod commands;
 use commands::*;
 
@@ -34,6 +40,21 @@ pub struct StrategyDefinition {
     /// The strategy that this strategy inherits from
     pub inherits: Option<String>,
 
+    /// Default filesystems for partitions with a role but no explicit filesystem
+    pub filesystem_defaults: HashMap<PartitionRole, Filesystem>,
+
+    /// Tags used to categorise and filter strategies
+    pub tags: Vec<String>,
+
+    /// Mount options added to every mount in the plan
+    pub global_mount_options: Vec<String>,
+
+    /// Minimum number of data partitions a plan must end up with
+    pub min_data_partitions: Option<usize>,
+
+    /// Where to mount the ESP, [`DEFAULT_ESP_MOUNTPOINT`] unless set
+    pub esp_mountpoint: Option<String>,
+
     /// The commands to execute
     pub commands: Vec<Command>,
 }
@@ -130,13 +151,69 @@ impl Parser {
             None
         };
 
+        let mut filesystem_defaults = HashMap::new();
+        for child in node.iter_children().filter(|n| n.name().value() == "defaults") {
+            match Self::parse_defaults(child) {
+                Ok(defaults) => filesystem_defaults.extend(defaults),
+                Err(e) => errors.push(e),
+            }
+        }
+
+        let mut tags = vec![];
+        for child in node.iter_children().filter(|n| n.name().value() == "tags") {
+            for entry in child.entries() {
+                match kdl_value_to_string(entry) {
+                    Ok(tag) => tags.push(tag),
+                    Err(e) => errors.push(e),
+                }
+            }
+        }
+
+        let mut global_mount_options = vec![];
+        for child in node
+            .iter_children()
+            .filter(|n| n.name().value() == "global-mount-options")
+        {
+            for entry in child.entries() {
+                match kdl_value_to_string(entry) {
+                    Ok(option) => global_mount_options.push(option),
+                    Err(e) => errors.push(e),
+                }
+            }
+        }
+
+        let mut min_data_partitions = None;
+        for child in node
+            .iter_children()
+            .filter(|n| n.name().value() == "min-data-partitions")
+        {
+            match get_kdl_entry(child, &0).and_then(kdl_value_to_integer) {
+                Ok(count) => min_data_partitions = Some(count as usize),
+                Err(e) => errors.push(e),
+            }
+        }
+
+        let mut esp_mountpoint = None;
+        for child in node.iter_children().filter(|n| n.name().value() == "esp-mountpoint") {
+            match get_kdl_entry(child, &0).and_then(kdl_value_to_string) {
+                Ok(path) => esp_mountpoint = Some(path),
+                Err(e) => errors.push(e),
+            }
+        }
+
         // Collect all failures in this strategy
-        let (commands, child_errors): (Vec<_>, Vec<_>) =
-            node.iter_children()
-                .partition_map(|node| match parse_command(Context { node }) {
-                    Ok(cmd) => Either::Left(cmd),
-                    Err(e) => Either::Right(e),
-                });
+        let (commands, child_errors): (Vec<_>, Vec<_>) = node
+            .iter_children()
+            .filter(|n| {
+                !matches!(
+                    n.name().value(),
+                    "defaults" | "tags" | "global-mount-options" | "min-data-partitions" | "esp-mountpoint"
+                )
+            })
+            .partition_map(|node| match parse_command(Context { node }) {
+                Ok(cmd) => Either::Left(cmd),
+                Err(e) => Either::Right(e),
+            });
 
         errors.extend(child_errors);
 
@@ -155,11 +232,39 @@ impl Parser {
             name,
             summary,
             inherits,
+            filesystem_defaults,
+            tags,
+            global_mount_options,
+            min_data_partitions,
+            esp_mountpoint,
             commands,
         };
 
         Ok(strategy)
     }
+
+    // Parse a defaults node, mapping partition roles to their default filesystem
+    fn parse_defaults(node: &KdlNode) -> Result<HashMap<PartitionRole, Filesystem>, Error> {
+        let mut defaults = HashMap::new();
+
+        for child in node.iter_children() {
+            match child.name().value() {
+                "filesystem" => {
+                    let role = PartitionRole::from_kdl_property(get_kdl_property(child, "role")?)?;
+                    defaults.insert(role, Filesystem::from_kdl_node(child)?);
+                }
+                _ => {
+                    return Err(UnsupportedNode {
+                        at: child.span(),
+                        name: child.name().value().into(),
+                    }
+                    .into());
+                }
+            }
+        }
+
+        Ok(defaults)
+    }
 }
 
 #[cfg(test)]
@@ -173,4 +278,26 @@ mod tests {
         eprintln!("p: {_p:?}");
         Ok(())
     }
+
+    #[test]
+    fn test_invalid_label() {
+        let result = Parser::new(
+            "label.kdl",
+            r#"
+            strategy name="bad_label" summary="ESP with a lowercase label" {
+                find-disk "root_disk"
+                create-partition disk="root_disk" role="boot" id="esp" {
+                    constraints {
+                        exactly (MiB)512
+                    }
+                    filesystem {
+                        type "fat32"
+                        label "esp"
+                    }
+                }
+            }
+            "#,
+        );
+        assert!(result.is_err());
+    }
 }
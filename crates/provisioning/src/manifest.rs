@@ -0,0 +1,104 @@
+// SPDX-FileCopyrightText: Copyright © 2025 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Device manifests, describing a disk topology without the hardware
+//!
+//! A manifest is a JSON document listing devices by kernel name and size in
+//! bytes, optionally with their model, flags and existing partitions:
+//!
+//! ```json
+//! {
+//!     "devices": [
+//!         {
+//!             "name": "sda",
+//!             "size": 536870912000,
+//!             "model": "Samsung SSD 870",
+//!             "rotational": false,
+//!             "removable": false,
+//!             "partitions": [{ "start": 1048576, "end": 107374182400 }]
+//!         }
+//!     ]
+//! }
+//! ```
+
+use disks::{BlockDevice, mock::MockDisk};
+use serde::Deserialize;
+use thiserror::Error;
+
+/// Errors that can occur loading a device manifest
+#[derive(Debug, Error)]
+pub enum ManifestError {
+    #[error("invalid manifest: {0}")]
+    Parse(#[from] serde_json::Error),
+
+    #[error("partition {start}..{end} does not fit on device {device}")]
+    InvalidPartition { device: String, start: u64, end: u64 },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    devices: Vec<DeviceEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DeviceEntry {
+    name: String,
+    size: u64,
+    model: Option<String>,
+    #[serde(default)]
+    rotational: bool,
+    #[serde(default)]
+    removable: bool,
+    logical_block_size: Option<u64>,
+    wwn: Option<String>,
+    #[serde(default)]
+    partitions: Vec<PartitionEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PartitionEntry {
+    start: u64,
+    end: u64,
+    type_guid: Option<String>,
+}
+
+/// Build mock-backed devices from a JSON manifest
+pub(crate) fn load_devices(json: &str) -> Result<Vec<BlockDevice>, ManifestError> {
+    let manifest: Manifest = serde_json::from_str(json)?;
+
+    manifest
+        .devices
+        .into_iter()
+        .map(|entry| {
+            let mut disk = MockDisk::new_with_name(&entry.name, entry.size, false)
+                .with_rotational(entry.rotational)
+                .with_removable(entry.removable);
+            if let Some(model) = entry.model.as_deref() {
+                disk = disk.with_model(model);
+            }
+            if let Some(size) = entry.logical_block_size {
+                disk = disk.with_logical_block_size(size);
+            }
+            if let Some(wwn) = entry.wwn.as_deref() {
+                disk = disk.with_wwn(wwn);
+            }
+
+            for partition in entry.partitions {
+                if partition.start >= partition.end || partition.end > entry.size {
+                    return Err(ManifestError::InvalidPartition {
+                        device: entry.name,
+                        start: partition.start,
+                        end: partition.end,
+                    });
+                }
+                disk.add_partition(partition.start, partition.end).type_guid = partition.type_guid;
+            }
+
+            Ok(BlockDevice::mock_device(disk))
+        })
+        .collect()
+}
@@ -3,17 +3,81 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeSet, HashMap},
+    hash::{DefaultHasher, Hash, Hasher},
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use disks::BlockDevice;
+use disks::{BlockDevice, PartitionTableKind, format_size};
+use itertools::Itertools;
 use log::{debug, trace, warn};
 use partitioning::{
-    planner::{PARTITION_ALIGNMENT, Planner},
+    GptAttributes, PartitionAttributes,
+    gpt::partition_types,
+    planner::{Change, PARTITION_ALIGNMENT, Planner, Region},
     strategy::{AllocationStrategy, PartitionRequest, SizeRequirement, Strategy},
 };
-use types::{Filesystem, PartitionRole};
+use thiserror::Error;
+use types::{Filesystem, PartitionRole, StandardFilesystemType};
+
+use crate::{Constraints, DiscoveryOptions, ManifestError, StrategyDefinition, commands::Command};
+
+/// Where the ESP is mounted unless a strategy sets `esp-mountpoint`
+pub const DEFAULT_ESP_MOUNTPOINT: &str = "/boot/efi";
+
+/// A validator run against each plan once built, returning the reason for rejection
+pub type PlanValidator = Box<dyn Fn(&Plan<'_>) -> Result<(), String>>;
+
+/// A plan that was built but rejected, along with the reason why
+#[derive(Debug, Clone)]
+pub struct PlanRejection {
+    /// The name of the strategy the plan was built from
+    pub strategy: String,
+
+    /// Why the plan was rejected
+    pub reason: String,
+}
+
+/// The result of attempting a single strategy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StrategyOutcome {
+    /// The name of the strategy
+    pub strategy: String,
+
+    /// What came of planning it
+    pub status: StrategyStatus,
+}
+
+/// Whether a strategy produced any plans, and if not, why
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StrategyStatus {
+    /// The given number of plans were produced
+    Planned(usize),
+
+    /// No devices matched the strategy, so nothing was attempted
+    Skipped(String),
+
+    /// Every plan built for the strategy was rejected
+    Failed(String),
+}
+
+/// Indented record of the device matching performed while planning a strategy
+#[derive(Debug, Default)]
+struct SearchTree {
+    output: String,
+    depth: usize,
+}
 
-use crate::{Constraints, StrategyDefinition, commands::Command};
+impl SearchTree {
+    fn line(&mut self, text: impl AsRef<str>) {
+        self.output.push_str(&"  ".repeat(self.depth));
+        self.output.push_str(text.as_ref());
+        self.output.push('\n');
+    }
+}
 
 /// Provisioner
 pub struct Provisioner<'a> {
@@ -22,18 +86,733 @@ pub struct Provisioner<'a> {
 
     /// Strategy configurations
     configs: HashMap<String, &'a StrategyDefinition>,
+
+    /// Whether the disk backing the running system may be used
+    allow_system_disk: bool,
+
+    /// Whether plans must claim every usable device in the pool
+    require_full_coverage: bool,
+
+    /// Custom validators run against every plan
+    validators: Vec<PlanValidator>,
+
+    /// Results of the last full planning run, keyed by the fingerprint of its inputs
+    cache: RefCell<Option<PlanCache<'a>>>,
+
+    /// Number of full planning runs performed, for observing the cache
+    planning_runs: Cell<usize>,
+}
+
+/// Cached results of planning every strategy
+struct PlanCache<'a> {
+    fingerprint: u64,
+    plans: Vec<Plan<'a>>,
+    rejections: Vec<PlanRejection>,
 }
 
 /// Compiled plan
+#[derive(Clone)]
 pub struct Plan<'a> {
     pub strategy: &'a StrategyDefinition,
     pub device_assignments: HashMap<String, DevicePlan<'a>>,
 
-    // Global mount points
+    // Global mount points, the first partition of each role
     pub role_mounts: HashMap<PartitionRole, PathBuf>,
 
     // Filesystems to be formatted
     pub filesystems: HashMap<PathBuf, Filesystem>,
+
+    /// Mount table for the partitions with a mountable role, sorted by mountpoint
+    pub mounts: Vec<MountEntry>,
+
+    /// Non-fatal issues found while building the plan
+    pub warnings: Vec<PlanWarning>,
+
+    /// Partitions to be populated from images
+    images: Vec<ImageSource>,
+}
+
+/// An entry in the mount table of a plan
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MountEntry {
+    /// The partition device to mount
+    pub device: PathBuf,
+
+    /// Where the partition is mounted
+    pub mountpoint: PathBuf,
+
+    /// Options to mount the partition with
+    pub options: Vec<String>,
+}
+
+/// An owned copy of a [`Plan`], detached from the devices it was planned against
+///
+/// Unlike the plan itself this can be serialized, e.g. to hand the plan to a
+/// web frontend as JSON.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PlanSnapshot {
+    /// The name of the strategy the plan was built from
+    pub strategy: String,
+
+    /// The devices bound by the plan, in disk name order
+    pub devices: Vec<DeviceSnapshot>,
+
+    /// Mount table for the partitions with a mountable role, sorted by mountpoint
+    pub mounts: Vec<MountEntry>,
+}
+
+/// The planned layout of a single device within a [`PlanSnapshot`]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceSnapshot {
+    /// The disk name the strategy bound the device to
+    pub disk: String,
+
+    /// Path to the device in /dev
+    pub device: PathBuf,
+
+    /// Size of the device in bytes
+    pub size: u64,
+
+    /// Whether a fresh partition table is written, discarding existing data
+    pub wipe: bool,
+
+    /// The resulting partitions, ordered by start position
+    pub partitions: Vec<PartitionSnapshot>,
+}
+
+/// A partition in the resulting layout of a [`DeviceSnapshot`]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PartitionSnapshot {
+    /// The partition number
+    pub number: u32,
+
+    /// Path to the partition device in /dev
+    pub path: PathBuf,
+
+    /// The absolute start position in bytes
+    pub start: u64,
+
+    /// The absolute end position in bytes
+    pub end: u64,
+
+    /// Size in bytes
+    pub size: u64,
+
+    /// The role of the partition, if any
+    pub role: Option<PartitionRole>,
+
+    /// The filesystem to format the partition with, if any
+    pub filesystem: Option<Filesystem>,
+
+    /// Where the partition is mounted, if anywhere
+    pub mountpoint: Option<PathBuf>,
+}
+
+/// A partition whose contents are to be copied from an image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageSource {
+    /// The partition device to write the image to
+    pub partition: PathBuf,
+
+    /// The image to copy from
+    pub source: PathBuf,
+
+    /// Size of the image in bytes
+    pub size: u64,
+}
+
+/// Non-fatal issues with a plan that are worth surfacing to the user
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum PlanWarning {
+    /// The ESP is smaller than recommended, and may run out of space for kernels
+    #[error("ESP {path:?} is {size} bytes, at least {recommended} bytes are recommended")]
+    SmallEsp { path: PathBuf, size: u64, recommended: u64 },
+
+    /// The swap partition is smaller than recommended
+    #[error("swap {path:?} is {size} bytes, at least {recommended} bytes are recommended")]
+    SmallSwap { path: PathBuf, size: u64, recommended: u64 },
+
+    /// Most of the disk is left unallocated
+    #[error("{free} of {size} bytes on {disk} are left unused")]
+    UnusedSpace { disk: String, free: u64, size: u64 },
+
+    /// The disk will be wiped despite already holding a partition table
+    #[error("{disk} already has a {kind} partition table which will be wiped")]
+    ExistingTable { disk: String, kind: PartitionTableKind },
+}
+
+/// Smallest ESP that doesn't produce a warning
+const RECOMMENDED_ESP_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Smallest swap partition that doesn't produce a warning
+const RECOMMENDED_SWAP_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Errors that can occur when revalidating a plan against refreshed devices
+#[derive(Debug, Error)]
+pub enum RevalidationError {
+    /// A device bound by the plan is no longer present
+    #[error("device {disk} ({path:?}) is no longer present")]
+    MissingDevice { disk: String, path: PathBuf },
+
+    /// A device bound by the plan has changed size
+    #[error("device {disk} ({path:?}) changed size from {expected} to {actual} bytes")]
+    SizeMismatch {
+        disk: String,
+        path: PathBuf,
+        expected: u64,
+        actual: u64,
+    },
+
+    /// The planned layout no longer fits on the device
+    #[error("planned layout for {disk} ends at {end} bytes but the device is {size} bytes")]
+    LayoutExceedsDevice { disk: String, end: u64, size: u64 },
+}
+
+/// Errors that can occur exporting a plan as commands for an external tool
+#[derive(Debug, Error)]
+pub enum ExportError {
+    /// A partition would have to be recreated, but its original attributes aren't known
+    #[error("partition {partition_id} on {device:?} can't be recreated without its original type and GUID")]
+    UnknownAttributes { device: PathBuf, partition_id: u32 },
+
+    /// A partition name contains both kinds of quote, which the tool can't represent
+    #[error("partition {partition_id} name {name:?} can't be quoted")]
+    UnquotableName { partition_id: u32, name: String },
+}
+
+/// Differences between a planned layout and the partitions currently on a device
+#[derive(Debug, Clone, Default)]
+pub struct LayoutDiff {
+    /// Planned partitions that don't exist on the device yet
+    pub added: Vec<Region>,
+
+    /// Partitions on the device that the plan will remove or replace
+    pub removed: Vec<Region>,
+
+    /// Partitions on the device that survive the plan unchanged
+    pub unchanged: Vec<Region>,
+
+    /// Partitions that appeared on or vanished from the device since planning
+    pub drifted: Vec<Region>,
+}
+
+impl LayoutDiff {
+    /// Whether the device changed since the plan was made
+    pub fn has_drift(&self) -> bool {
+        !self.drifted.is_empty()
+    }
+}
+
+impl Plan<'_> {
+    /// Total size in bytes of the partitions that will be formatted with a filesystem
+    ///
+    /// Swap and partitions without a filesystem are excluded, as only a small
+    /// header is written to them. Intended for estimating formatting time.
+    pub fn bytes_to_format(&self) -> u64 {
+        self.device_assignments
+            .values()
+            .flat_map(|device_plan| device_plan.planner.current_layout())
+            .filter(|region| {
+                region
+                    .attributes
+                    .as_ref()
+                    .and_then(|a| a.filesystem.as_ref())
+                    .is_some_and(|fs| {
+                        !matches!(
+                            fs,
+                            Filesystem::Standard {
+                                filesystem_type: StandardFilesystemType::Swap,
+                                ..
+                            }
+                        )
+                    })
+            })
+            .map(|region| region.size())
+            .sum()
+    }
+
+    /// The partitions that must be populated from images once created
+    pub fn image_sources(&self) -> &[ImageSource] {
+        &self.images
+    }
+
+    /// Re-check the plan against a refreshed set of devices
+    ///
+    /// Hardware may change between planning and applying, so this ensures every
+    /// bound device still exists with the same size and that the planned layout
+    /// still fits within it.
+    pub fn revalidate(&self, devices: &[&BlockDevice]) -> Result<(), RevalidationError> {
+        for (disk, device_plan) in &self.device_assignments {
+            let path = device_plan.device.device();
+            let device =
+                devices
+                    .iter()
+                    .find(|d| d.device() == path)
+                    .ok_or_else(|| RevalidationError::MissingDevice {
+                        disk: disk.clone(),
+                        path: path.to_path_buf(),
+                    })?;
+
+            if device.size() != device_plan.device.size() {
+                return Err(RevalidationError::SizeMismatch {
+                    disk: disk.clone(),
+                    path: path.to_path_buf(),
+                    expected: device_plan.device.size(),
+                    actual: device.size(),
+                });
+            }
+
+            if let Some(end) = device_plan.planner.current_layout().iter().map(|r| r.end).max() {
+                if end > device.size() {
+                    return Err(RevalidationError::LayoutExceedsDevice {
+                        disk: disk.clone(),
+                        end,
+                        size: device.size(),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compare the planned layout with the partitions currently on the device
+    ///
+    /// The device is re-read rather than trusting the layout seen while planning,
+    /// so any drift since then is reported. Devices that aren't part of the plan
+    /// produce an empty diff.
+    pub fn diff_against_device(&self, device: &BlockDevice) -> LayoutDiff {
+        let Some(device_plan) = self
+            .device_assignments
+            .values()
+            .find(|device_plan| device_plan.device.device() == device.device())
+        else {
+            return LayoutDiff::default();
+        };
+
+        let planned = device_plan.planner.current_layout();
+        let actual = Planner::new(device).original_regions().to_vec();
+        let seen = Planner::new(device_plan.device).original_regions().to_vec();
+        let missing_from = |regions: &[Region], region: &Region| !regions.iter().any(|r| r.same_extent(region));
+
+        let (unchanged, removed) = actual
+            .iter()
+            .cloned()
+            .partition(|region| !missing_from(&planned, region));
+        LayoutDiff {
+            added: planned.iter().filter(|r| missing_from(&actual, r)).cloned().collect(),
+            removed,
+            unchanged,
+            drifted: actual
+                .iter()
+                .filter(|r| missing_from(&seen, r))
+                .chain(seen.iter().filter(|r| missing_from(&actual, r)))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// A one-line summary of the plan, suitable for listing plans in a CLI
+    ///
+    /// For example: `whole_disk on /dev/sda: 3 partitions (boot 1.0GiB, root 120.0GiB)`
+    pub fn summary_line(&self) -> String {
+        let mut disks = self.device_assignments.iter().collect::<Vec<_>>();
+        disks.sort_by_key(|(name, _)| *name);
+
+        let devices = disks
+            .iter()
+            .map(|(_, device_plan)| {
+                let layout = device_plan.planner.current_layout();
+                let roles = layout
+                    .iter()
+                    .filter_map(|region| {
+                        let role = region.attributes.as_ref()?.role.as_ref()?;
+                        Some(format!("{role} {}", format_size(region.size())))
+                    })
+                    .collect::<Vec<_>>();
+
+                let mut summary = format!(
+                    "{}: {} partition{}",
+                    device_plan.device_path().display(),
+                    layout.len(),
+                    if layout.len() == 1 { "" } else { "s" }
+                );
+                if !roles.is_empty() {
+                    summary.push_str(&format!(" ({})", roles.join(", ")));
+                }
+                summary
+            })
+            .collect::<Vec<_>>();
+
+        format!("{} on {}", self.strategy.name, devices.join("; "))
+    }
+
+    /// Take an owned copy of the plan, suitable for serialization
+    pub fn snapshot(&self) -> PlanSnapshot {
+        let mut disks = self.device_assignments.iter().collect::<Vec<_>>();
+        disks.sort_by_key(|(name, _)| *name);
+
+        let devices = disks
+            .into_iter()
+            .map(|(name, device_plan)| {
+                let partitions = device_plan
+                    .planner
+                    .current_layout()
+                    .into_iter()
+                    .map(|region| {
+                        let number = region.partition_id.unwrap_or_default();
+                        let path = device_plan.device.partition_path(number as usize);
+                        PartitionSnapshot {
+                            number,
+                            start: region.start,
+                            end: region.end,
+                            size: region.size(),
+                            role: region.attributes.as_ref().and_then(|a| a.role.clone()),
+                            filesystem: self.filesystems.get(&path).cloned(),
+                            mountpoint: self
+                                .mounts
+                                .iter()
+                                .find(|m| m.device == path)
+                                .map(|m| m.mountpoint.clone()),
+                            path,
+                        }
+                    })
+                    .collect();
+                DeviceSnapshot {
+                    disk: name.clone(),
+                    device: device_plan.device.device().to_path_buf(),
+                    size: device_plan.device.size(),
+                    wipe: device_plan.planner.wipe_disk(),
+                    partitions,
+                }
+            })
+            .collect();
+
+        PlanSnapshot {
+            strategy: self.strategy.name.clone(),
+            devices,
+            mounts: self.mounts.clone(),
+        }
+    }
+
+    /// Serialize a snapshot of the plan as a JSON document
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(&self.snapshot())
+    }
+
+    /// Describe the plan in full, e.g. for an installer confirmation screen
+    ///
+    /// Each device is listed in disk name order with its resulting partitions,
+    /// their roles, filesystems and mountpoints, followed by the pending changes
+    /// and a warning when existing data will be destroyed.
+    pub fn describe(&self) -> String {
+        let mut disks = self.device_assignments.iter().collect::<Vec<_>>();
+        disks.sort_by_key(|(name, _)| *name);
+
+        let mut description = format!("Strategy {}: {}\n", self.strategy.name, self.strategy.summary);
+        for (name, device_plan) in disks {
+            let planner = &device_plan.planner;
+            description.push_str(&format!(
+                "\n{name}: {} ({})\n",
+                device_plan.device_path().display(),
+                format_size(device_plan.device.size())
+            ));
+
+            for region in planner.current_layout() {
+                let path = region
+                    .partition_id
+                    .map(|id| device_plan.device.partition_path(id as usize));
+                let mut line = format!(
+                    "  {}: {}",
+                    path.as_ref().map_or("unnumbered".into(), |p| p.display().to_string()),
+                    region.describe(planner.usable_size())
+                );
+                if let Some(role) = region.attributes.as_ref().and_then(|a| a.role.as_ref()) {
+                    line.push_str(&format!(", {role}"));
+                }
+                if let Some(path) = path.as_ref() {
+                    if let Some(filesystem) = self.filesystems.get(path) {
+                        line.push_str(&format!(", {filesystem}"));
+                    }
+                    if let Some(mount) = self.mounts.iter().find(|m| &m.device == path) {
+                        line.push_str(&format!(", mounted at {}", mount.mountpoint.display()));
+                    }
+                }
+                description.push_str(&line);
+                description.push('\n');
+            }
+
+            for line in planner.describe_changes().lines() {
+                description.push_str(&format!("  {line}\n"));
+            }
+            if planner.has_destructive_changes() {
+                description.push_str(&format!(
+                    "  Existing data on {} will be destroyed\n",
+                    device_plan.device.device().display()
+                ));
+            }
+        }
+        description
+    }
+
+    /// Export the planned changes for `device` as a GNU parted script
+    ///
+    /// Each entry is a single parted command. Offsets are given in logical sectors
+    /// of the device, with the inclusive end sector parted expects, so boundaries
+    /// are kept exactly. New partitions are created with their GPT name. Devices
+    /// not bound by this plan yield no commands.
+    pub fn to_parted_script(&self, device: &Path) -> Result<Vec<String>, ExportError> {
+        let Some(device_plan) = self.device_assignments.values().find(|p| p.device.device() == device) else {
+            return Ok(vec![]);
+        };
+        let sector = device_plan.planner.sector_size();
+
+        let mut script = vec!["unit s".to_string()];
+        if device_plan.planner.wipe_disk() {
+            script.push("mklabel gpt".to_string());
+        }
+
+        for change in device_plan.planner.changes() {
+            match change {
+                Change::DeletePartition { partition_id, .. } => script.push(format!("rm {partition_id}")),
+                Change::ResizePartition { partition_id, end, .. } => {
+                    script.push(format!("resizepart {partition_id} {}s", end / sector - 1));
+                }
+                Change::SetAttributes {
+                    partition_id,
+                    attributes,
+                    ..
+                } => script.extend(parted_flags(*partition_id, attributes)),
+                Change::AddPartition {
+                    start,
+                    end,
+                    partition_id,
+                    attributes,
+                } => {
+                    // On GPT the first argument of mkpart is the partition name
+                    let name = attributes
+                        .as_ref()
+                        .and_then(|a| a.table.as_gpt())
+                        .and_then(|gpt| gpt.name.as_deref())
+                        .unwrap_or_default();
+                    let quoted = parted_quote(name).ok_or_else(|| ExportError::UnquotableName {
+                        partition_id: *partition_id,
+                        name: name.to_owned(),
+                    })?;
+                    script.push(format!("mkpart {quoted} {}s {}s", start / sector, end / sector - 1));
+                    if let Some(attributes) = attributes {
+                        script.extend(parted_flags(*partition_id, attributes));
+                    }
+                }
+                // The fresh table is written up front, as checked above
+                Change::InitializeDisk { .. } => {}
+            }
+        }
+
+        Ok(script)
+    }
+
+    /// Export the planned layout of a device as an `sfdisk` script
+    ///
+    /// `device_key` is the disk name the strategy bound the device to. The script
+    /// follows the `sfdisk --dump` format, in sectors, and describes the whole
+    /// resulting table, so a wiped disk with nothing planned yields a bare GPT
+    /// header. Returns `None` if no device is bound to `device_key`.
+    pub fn to_sfdisk_script(&self, device_key: &str) -> Option<String> {
+        let device_plan = self.device_assignments.get(device_key)?;
+        let planner = &device_plan.planner;
+        let device = device_plan.device;
+        let sector = planner.sector_size();
+        let (_, usable_end) = planner.offsets();
+
+        let mut script = format!(
+            "label: gpt\ndevice: {}\nunit: sectors\nfirst-lba: {}\nlast-lba: {}\nsector-size: {sector}\n",
+            device.device().display(),
+            planner.allocation_start().div_ceil(sector),
+            usable_end / sector - 1
+        );
+
+        let layout = planner.current_layout();
+        if !layout.is_empty() {
+            script.push('\n');
+        }
+        for region in layout {
+            let id = region.partition_id.unwrap_or_default();
+            let mut line = format!(
+                "{} : start={}, size={}",
+                device.partition_path(id as usize).display(),
+                region.start / sector,
+                region.size() / sector
+            );
+            if let Some(gpt) = region.attributes.as_ref().and_then(|a| a.table.as_gpt()) {
+                line.push_str(&format!(", type={}", type_code(gpt)));
+                if let Some(name) = gpt.name.as_ref() {
+                    line.push_str(&format!(", name=\"{name}\""));
+                }
+            }
+            script.push_str(&line);
+            script.push('\n');
+        }
+
+        Some(script)
+    }
+
+    /// Export the planned changes of every device as `sgdisk` command lines
+    ///
+    /// Each entry is a single invocation. Offsets are given in logical sectors of
+    /// the device, with the inclusive end sector `sgdisk` expects. A wiped disk is
+    /// zapped first. The commands are only generated, never run.
+    ///
+    /// Fails if a resized partition can't be recreated faithfully, as its original
+    /// type or unique GUID are unknown.
+    pub fn to_sgdisk_commands(&self) -> Result<HashMap<PathBuf, Vec<String>>, ExportError> {
+        self.device_assignments
+            .values()
+            .map(|device_plan| {
+                let planner = &device_plan.planner;
+                let device = device_plan.device.device();
+                let sector = planner.sector_size();
+                let new = |id: u32, start: u64, end: u64| format!("--new={id}:{}:{}", start / sector, end / sector - 1);
+
+                let mut commands = vec![];
+                if planner.wipe_disk() {
+                    commands.push(vec!["--zap-all".to_string()]);
+                }
+                for change in planner.changes() {
+                    match change {
+                        Change::DeletePartition { partition_id, .. } => {
+                            commands.push(vec![format!("--delete={partition_id}")]);
+                        }
+                        // sgdisk can't resize in place, so recreate the partition as it was,
+                        // keeping its unique GUID so PARTUUID references still resolve
+                        Change::ResizePartition {
+                            original_index,
+                            partition_id,
+                            start,
+                            end,
+                        } => {
+                            let Some((attributes, uuid)) = planner
+                                .original_regions()
+                                .get(*original_index)
+                                .and_then(|r| r.attributes.as_ref())
+                                .and_then(|a| Some((a, a.table.as_gpt()?.uuid?)))
+                            else {
+                                return Err(ExportError::UnknownAttributes {
+                                    device: device.to_path_buf(),
+                                    partition_id: *partition_id,
+                                });
+                            };
+                            let mut args = vec![
+                                format!("--delete={partition_id}"),
+                                new(*partition_id, *start, *end),
+                                format!(
+                                    "--partition-guid={partition_id}:{}",
+                                    uuid.hyphenated().to_string().to_uppercase()
+                                ),
+                            ];
+                            args.extend(sgdisk_attributes(*partition_id, attributes));
+                            commands.push(args);
+                        }
+                        Change::SetAttributes {
+                            partition_id,
+                            attributes,
+                            ..
+                        } => commands.push(sgdisk_attributes(*partition_id, attributes)),
+                        Change::AddPartition {
+                            start,
+                            end,
+                            partition_id,
+                            attributes,
+                        } => {
+                            let mut args = vec![new(*partition_id, *start, *end)];
+                            if let Some(attributes) = attributes {
+                                args.extend(sgdisk_attributes(*partition_id, attributes));
+                            }
+                            commands.push(args);
+                        }
+                        // The disk is zapped up front, as checked above
+                        Change::InitializeDisk { .. } => {}
+                    }
+                }
+
+                let commands = commands
+                    .into_iter()
+                    .filter(|args| !args.is_empty())
+                    .map(|args| format!("sgdisk {} {}", args.join(" "), shell_quote(&device.to_string_lossy())))
+                    .collect();
+                Ok((device.to_path_buf(), commands))
+            })
+            .collect()
+    }
+}
+
+/// The partition type GUID in the uppercase form partitioning tools print
+fn type_code(gpt: &GptAttributes) -> String {
+    gpt.type_guid.guid.hyphenated().to_string().to_uppercase()
+}
+
+/// Quote a word for a POSIX shell, leaving words made only of safe characters as they are
+fn shell_quote(word: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./:=,+@%".contains(c);
+    if !word.is_empty() && word.chars().all(safe) {
+        word.to_owned()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+/// The sgdisk arguments setting the type, name and flags of a partition
+fn sgdisk_attributes(partition_id: u32, attributes: &PartitionAttributes) -> Vec<String> {
+    let Some(gpt) = attributes.table.as_gpt() else {
+        return vec![];
+    };
+
+    let mut args = vec![format!("--typecode={partition_id}:{}", type_code(gpt))];
+    if let Some(name) = gpt.name.as_ref() {
+        args.push(shell_quote(&format!("--change-name={partition_id}:{name}")));
+    }
+    if gpt.flags & GptAttributes::LEGACY_BIOS_BOOTABLE != 0 {
+        args.push(format!("--attributes={partition_id}:set:2"));
+    }
+    args
+}
+
+/// Quote a word for parted, which understands either quote but no escapes
+fn parted_quote(word: &str) -> Option<String> {
+    if !word.contains('"') {
+        Some(format!("\"{word}\""))
+    } else if !word.contains('\'') {
+        Some(format!("'{word}'"))
+    } else {
+        None
+    }
+}
+
+/// The parted flags to set on a partition for its table attributes
+fn parted_flags(partition_id: u32, attributes: &PartitionAttributes) -> Vec<String> {
+    let Some(gpt) = attributes.table.as_gpt() else {
+        return vec![];
+    };
+
+    let mut flags = vec![];
+    if gpt.type_guid == partition_types::EFI {
+        flags.push("esp");
+    } else if gpt.type_guid == partition_types::FREEDESK_BOOT {
+        flags.push("bls_boot");
+    }
+    if gpt.flags & GptAttributes::LEGACY_BIOS_BOOTABLE != 0 {
+        flags.push("legacy_boot");
+    }
+
+    flags
+        .into_iter()
+        .map(|flag| format!("set {partition_id} {flag} on"))
+        .collect()
 }
 
 #[derive(Debug, Clone)]
@@ -43,6 +822,126 @@ pub struct DevicePlan<'a> {
     pub strategy: Strategy,
 }
 
+/// Whether a device assignment key belongs to the disk name used in a command,
+/// either directly or as a pool member (`name[N]`) bound by `find-disk all=#true`
+fn is_assigned_to(key: &str, disk: &str) -> bool {
+    key == disk
+        || key
+            .strip_prefix(disk)
+            .and_then(|rest| rest.strip_prefix('['))
+            .and_then(|rest| rest.strip_suffix(']'))
+            .is_some_and(|index| index.parse::<usize>().is_ok())
+}
+
+/// All device plans a command naming `disk` applies to
+fn assigned_plans_mut<'m, 'a>(
+    device_assignments: &'m mut HashMap<String, DevicePlan<'a>>,
+    disk: &str,
+) -> Vec<&'m mut DevicePlan<'a>> {
+    device_assignments
+        .iter_mut()
+        .filter(|(key, _)| is_assigned_to(key, disk))
+        .map(|(_, device_plan)| device_plan)
+        .collect()
+}
+
+/// Rough cost of a single partition table operation
+const TABLE_OPERATION_COST: Duration = Duration::from_millis(500);
+
+/// Rough cost of asking the kernel to reread the partition table
+const SETTLE_COST: Duration = Duration::from_secs(2);
+
+const GIB: u64 = 1024 * 1024 * 1024;
+
+/// Rough formatting throughput per GiB for a filesystem, plus its fixed setup cost
+fn format_cost(filesystem: &Filesystem, size: u64) -> Duration {
+    let (fixed, per_gib) = match filesystem {
+        Filesystem::Fat32 { .. } => (Duration::from_millis(500), Duration::from_millis(100)),
+        Filesystem::Standard { filesystem_type, .. } => match filesystem_type {
+            StandardFilesystemType::Ext4 => (Duration::from_secs(1), Duration::from_millis(40)),
+            StandardFilesystemType::F2fs => (Duration::from_secs(1), Duration::from_millis(20)),
+            StandardFilesystemType::Xfs => (Duration::from_secs(1), Duration::from_millis(10)),
+            StandardFilesystemType::Swap => (Duration::from_millis(200), Duration::from_millis(1)),
+        },
+    };
+    fixed + per_gib.mul_f64(size as f64 / GIB as f64)
+}
+
+impl<'a> DevicePlan<'a> {
+    /// Start planning against a device, leaving an aligned margin at either end
+    fn new(device: &'a BlockDevice) -> Self {
+        Self {
+            device,
+            planner: Planner::new(device)
+                .with_start_offset(PARTITION_ALIGNMENT)
+                .with_end_offset(device.size() - PARTITION_ALIGNMENT),
+            strategy: Strategy::new(AllocationStrategy::LargestFree),
+        }
+    }
+
+    /// The path to refer to the device by, preferring a stable `/dev/disk/by-id` path
+    pub fn device_path(&self) -> PathBuf {
+        self.device
+            .by_id_path()
+            .unwrap_or_else(|| self.device.device().to_path_buf())
+    }
+
+    /// Estimate the steps needed to apply this plan, with a rough duration for each.
+    ///
+    /// These are heuristics intended to drive a progress display, not guarantees.
+    pub fn estimated_steps(&self) -> Vec<(String, Duration)> {
+        let mut steps = vec![];
+
+        if self.planner.wipe_disk() {
+            let size = self.device.size();
+            steps.push((
+                "Discard device contents".to_string(),
+                Duration::from_secs(1) + Duration::from_millis(10).mul_f64(size as f64 / GIB as f64),
+            ));
+            steps.push(("Write new partition table".to_string(), TABLE_OPERATION_COST));
+        }
+
+        for change in self.planner.changes() {
+            match change {
+                Change::DeletePartition { partition_id, .. } => {
+                    steps.push((format!("Delete partition {partition_id}"), TABLE_OPERATION_COST));
+                }
+                Change::SetAttributes { partition_id, .. } => {
+                    steps.push((
+                        format!("Update partition {partition_id} attributes"),
+                        TABLE_OPERATION_COST,
+                    ));
+                }
+                Change::ResizePartition { partition_id, .. } => {
+                    steps.push((format!("Resize partition {partition_id}"), TABLE_OPERATION_COST));
+                }
+                Change::AddPartition {
+                    start,
+                    end,
+                    partition_id,
+                    attributes,
+                } => {
+                    steps.push((format!("Create partition {partition_id}"), TABLE_OPERATION_COST));
+                    if let Some(filesystem) = attributes.as_ref().and_then(|a| a.filesystem.as_ref()) {
+                        steps.push((
+                            format!("Format partition {partition_id} as {}", filesystem),
+                            format_cost(filesystem, end - start),
+                        ));
+                    }
+                }
+                // Accounted for by the wipe steps above
+                Change::InitializeDisk { .. } => {}
+            }
+        }
+
+        if !steps.is_empty() {
+            steps.push(("Wait for devices to settle".to_string(), SETTLE_COST));
+        }
+
+        steps
+    }
+}
+
 impl Default for Provisioner<'_> {
     fn default() -> Self {
         Self::new()
@@ -50,29 +949,111 @@ impl Default for Provisioner<'_> {
 }
 
 impl<'a> Provisioner<'a> {
+    /// Discover the whole disks present on the system, suitable for [`Provisioner::push_device`]
+    ///
+    /// Removable, loopback and RAM-backed devices are excluded.
+    #[cfg(target_os = "linux")]
+    pub fn discover_devices() -> std::io::Result<Vec<BlockDevice>> {
+        Self::discover_devices_with(&DiscoveryOptions::default())
+    }
+
+    /// Discover the whole disks present on the system, filtered by `options`
+    #[cfg(target_os = "linux")]
+    pub fn discover_devices_with(options: &DiscoveryOptions) -> std::io::Result<Vec<BlockDevice>> {
+        crate::discovery::discover_devices(options)
+    }
+
+    /// Build mock devices from a JSON manifest, suitable for [`Provisioner::push_device`]
+    ///
+    /// This reproduces a disk topology without the hardware, e.g. to plan in CI or
+    /// on a server. Devices are listed with their sizes, existing partitions,
+    /// rotational and removable flags, and model names.
+    pub fn load_devices_from_manifest(json: &str) -> Result<Vec<BlockDevice>, ManifestError> {
+        crate::manifest::load_devices(json)
+    }
+
     /// Create a new provisioner
     pub fn new() -> Self {
         debug!("Creating new provisioner");
         Self {
             devices: Vec::new(),
             configs: HashMap::new(),
+            allow_system_disk: false,
+            require_full_coverage: false,
+            validators: Vec::new(),
+            cache: RefCell::new(None),
+            planning_runs: Cell::new(0),
+        }
+    }
+
+    /// Discard any cached planning results, forcing the next plan to be recomputed
+    pub fn clear_cache(&self) {
+        self.cache.take();
+    }
+
+    /// Fingerprint the inputs to planning: the device pool and the strategy set
+    fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for device in &self.devices {
+            device.device().hash(&mut hasher);
+            device.size().hash(&mut hasher);
+            device.partition_count().hash(&mut hasher);
+        }
+        let mut strategies = self.configs.keys().collect::<Vec<_>>();
+        strategies.sort();
+        strategies.hash(&mut hasher);
+        self.allow_system_disk.hash(&mut hasher);
+        self.require_full_coverage.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Register a validator to run against every plan once built
+    ///
+    /// Plans failing any validator are dropped, with the reason recorded in the
+    /// rejections returned by [`Provisioner::plan_with_rejections`].
+    pub fn add_validator(&mut self, validator: impl Fn(&Plan<'_>) -> Result<(), String> + 'static) {
+        self.clear_cache();
+        self.validators.push(Box::new(validator));
+    }
+
+    /// Allow the disk backing the running system to be matched by strategies
+    ///
+    /// By default it is excluded to avoid wiping the system we're running from.
+    pub fn with_allow_system_disk(self, allow: bool) -> Self {
+        Self {
+            allow_system_disk: allow,
+            ..self
+        }
+    }
+
+    /// Require plans to claim every usable device in the pool
+    ///
+    /// Plans leaving a device unassigned are dropped, with the reason recorded in
+    /// the rejections returned by [`Provisioner::plan_with_rejections`]. The disk
+    /// backing the running system only counts when it is allowed to be used.
+    pub fn require_full_coverage(self, require: bool) -> Self {
+        Self {
+            require_full_coverage: require,
+            ..self
         }
     }
 
     /// Add a strategy configuration
     pub fn add_strategy(&mut self, config: &'a StrategyDefinition) {
         debug!("Adding strategy: {}", config.name);
+        self.clear_cache();
         self.configs.insert(config.name.clone(), config);
     }
 
     // Add a device to the provisioner pool
     pub fn push_device(&mut self, device: &'a BlockDevice) {
         debug!("Adding device to pool: {device:?}");
+        self.clear_cache();
         self.devices.push(device)
     }
 
     // Build an inheritance chain for a strategy
-    fn strategy_parents<'b>(&'b self, strategy: &'b StrategyDefinition) -> Vec<&'b StrategyDefinition> {
+    fn strategy_parents(&self, strategy: &'a StrategyDefinition) -> Vec<&'a StrategyDefinition> {
         trace!("Building inheritance chain for strategy: {}", strategy.name);
         let mut chain = vec![];
         if let Some(parent) = &strategy.inherits {
@@ -84,32 +1065,138 @@ impl<'a> Provisioner<'a> {
         chain
     }
 
+    /// Collect the devices that would be modified by any of the given plans
+    ///
+    /// Devices are de-duplicated across plans and returned in path order, making
+    /// this suitable as a final confirmation list before anything is written.
+    pub fn affected_devices(plans: &[Plan<'_>]) -> Vec<PathBuf> {
+        plans
+            .iter()
+            .flat_map(|plan| plan.device_assignments.values())
+            .filter(|device_plan| device_plan.planner.wipe_disk() || device_plan.planner.has_changes())
+            .map(|device_plan| device_plan.device.device().to_path_buf())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
     /// Attempt all strategies on the pool of devices
-    pub fn plan(&self) -> Vec<Plan<'_>> {
+    ///
+    /// Results are cached until the device pool or strategy set changes.
+    pub fn plan(&self) -> Vec<Plan<'a>> {
+        self.plan_with_rejections().0
+    }
+
+    /// Attempt all strategies, also returning any plans rejected by validators
+    pub fn plan_with_rejections(&self) -> (Vec<Plan<'a>>, Vec<PlanRejection>) {
         trace!("Planning device provisioning");
+        let fingerprint = self.fingerprint();
+        if let Some(cache) = self.cache.borrow().as_ref().filter(|c| c.fingerprint == fingerprint) {
+            debug!("Reusing cached plans");
+            return (cache.plans.clone(), cache.rejections.clone());
+        }
+
+        let (plans, rejections) = self.plan_strategies(self.configs.values().copied());
+        self.cache.replace(Some(PlanCache {
+            fingerprint,
+            plans: plans.clone(),
+            rejections: rejections.clone(),
+        }));
+        (plans, rejections)
+    }
+
+    /// Attempt every strategy, reporting the outcome of each one, sorted by name
+    ///
+    /// Unlike [`Provisioner::plan`], strategies that produce no plans are
+    /// reported rather than silently dropped.
+    pub fn evaluate(&self) -> Vec<StrategyOutcome> {
+        self.configs
+            .values()
+            .copied()
+            .sorted_by(|a, b| a.name.cmp(&b.name))
+            .map(|strategy| {
+                let (plans, rejections) = self.plan_strategies(std::iter::once(strategy));
+                let status = if !plans.is_empty() {
+                    StrategyStatus::Planned(plans.len())
+                } else if !rejections.is_empty() {
+                    StrategyStatus::Failed(rejections.iter().map(|r| r.reason.as_str()).unique().join("; "))
+                } else {
+                    StrategyStatus::Skipped("no matching devices".to_owned())
+                };
+                StrategyOutcome {
+                    strategy: strategy.name.clone(),
+                    status,
+                }
+            })
+            .collect()
+    }
+
+    /// Attempt only the strategies carrying any of the given tags
+    pub fn plan_with_tags(&self, tags: &[&str]) -> Vec<Plan<'a>> {
+        trace!("Planning device provisioning for tags {tags:?}");
+        self.plan_strategies(
+            self.configs
+                .values()
+                .copied()
+                .filter(|strategy| strategy.tags.iter().any(|tag| tags.contains(&tag.as_str()))),
+        )
+        .0
+    }
+
+    fn plan_strategies(
+        &self,
+        strategies: impl Iterator<Item = &'a StrategyDefinition>,
+    ) -> (Vec<Plan<'a>>, Vec<PlanRejection>) {
+        self.planning_runs.set(self.planning_runs.get() + 1);
         let mut plans = Vec::new();
-        for strategy in self.configs.values() {
+        let mut rejections = Vec::new();
+        for strategy in strategies {
             debug!("Attempting strategy: {}", strategy.name);
-            self.create_plans_for_strategy(strategy, &mut HashMap::new(), &mut plans);
+            self.create_plans_for_strategy(strategy, &mut HashMap::new(), &mut plans, &mut rejections, None);
         }
-        debug!("Generated {} plans", plans.len());
-        plans
+        debug!("Generated {} plans, rejected {}", plans.len(), rejections.len());
+        (plans, rejections)
+    }
+
+    /// Describe the device matching performed when planning the named strategy
+    ///
+    /// The result is an indented tree showing the devices matched by each
+    /// `find-disk`, and whether each branch produced a plan or was rejected.
+    /// This is useful for debugging why a strategy produced a given number of plans.
+    pub fn explain_plan(&self, strategy: &str) -> String {
+        let Some(strategy) = self.configs.get(strategy).copied() else {
+            return format!("unknown strategy {strategy}\n");
+        };
+
+        let mut tree = SearchTree::default();
+        tree.line(format!("strategy {}", strategy.name));
+        tree.depth += 1;
+        self.create_plans_for_strategy(strategy, &mut HashMap::new(), &mut vec![], &mut vec![], Some(&mut tree));
+        tree.output
     }
 
-    fn create_plans_for_strategy<'b>(
-        &'b self,
-        strategy: &'b StrategyDefinition,
-        device_assignments: &mut HashMap<String, DevicePlan<'b>>,
-        plans: &mut Vec<Plan<'b>>,
+    fn create_plans_for_strategy(
+        &self,
+        strategy: &'a StrategyDefinition,
+        device_assignments: &mut HashMap<String, DevicePlan<'a>>,
+        plans: &mut Vec<Plan<'a>>,
+        rejections: &mut Vec<PlanRejection>,
+        mut tree: Option<&mut SearchTree>,
     ) {
         trace!("Creating plans for strategy: {}", strategy.name);
         let chain = self.strategy_parents(strategy);
 
+        // Children override the filesystem defaults of their parents
+        let filesystem_defaults = chain
+            .iter()
+            .flat_map(|s| &s.filesystem_defaults)
+            .collect::<HashMap<_, _>>();
+
         for command in chain.iter().flat_map(|s| &s.commands) {
             match command {
                 Command::FindDisk(command) => {
                     // Skip if already assigned
-                    if device_assignments.contains_key(&command.name) {
+                    if device_assignments.keys().any(|key| is_assigned_to(key, &command.name)) {
                         trace!("Disk {} already assigned, skipping", command.name);
                         continue;
                     }
@@ -124,6 +1211,8 @@ impl<'a> Provisioner<'a> {
                             Some(Constraints::Range { min, max }) => d.size() >= *min && d.size() <= *max,
                             _ => true,
                         })
+                        .filter(|d| command.wwn.as_ref().is_none_or(|wwn| d.wwn() == Some(wwn.as_str())))
+                        .filter(|d| self.allow_system_disk || !d.is_system_disk())
                         .filter(|d| {
                             !device_assignments.values().any(|assigned| {
                                 std::ptr::eq(assigned.device as *const BlockDevice, **d as *const BlockDevice)
@@ -133,67 +1222,231 @@ impl<'a> Provisioner<'a> {
 
                     debug!("Found {} matching devices for {}", matching_devices.len(), command.name);
 
+                    if let Some(tree) = tree.as_deref_mut() {
+                        let names = matching_devices
+                            .iter()
+                            .map(|d| d.device().display().to_string())
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        match matching_devices.len() {
+                            0 => tree.line(format!("find-disk {}: no matching devices", command.name)),
+                            _ if command.all => tree.line(format!("find-disk {}: pooling {names}", command.name)),
+                            _ => tree.line(format!("find-disk {}: candidates {names}", command.name)),
+                        }
+                    }
+
+                    // Pool every matching device into this plan, rather than branching
+                    if command.all {
+                        if matching_devices.is_empty() {
+                            return;
+                        }
+                        for (index, device) in matching_devices.into_iter().enumerate() {
+                            device_assignments.insert(format!("{}[{index}]", command.name), DevicePlan::new(device));
+                        }
+                        continue;
+                    }
+
                     // Branch for each matching device
                     for device in matching_devices {
                         trace!("Creating plan branch for device: {device:?}");
                         let mut new_assignments = device_assignments.clone();
-                        new_assignments.insert(
-                            command.name.clone(),
-                            DevicePlan {
-                                device,
-                                planner: Planner::new(device)
-                                    .with_start_offset(PARTITION_ALIGNMENT)
-                                    .with_end_offset(device.size() - PARTITION_ALIGNMENT),
-                                strategy: Strategy::new(AllocationStrategy::LargestFree),
-                            },
+                        new_assignments.insert(command.name.clone(), DevicePlan::new(device));
+                        if let Some(tree) = tree.as_deref_mut() {
+                            tree.line(format!("{} = {}", command.name, device.device().display()));
+                            tree.depth += 1;
+                        }
+                        self.create_plans_for_strategy(
+                            strategy,
+                            &mut new_assignments,
+                            plans,
+                            rejections,
+                            tree.as_deref_mut(),
                         );
-                        self.create_plans_for_strategy(strategy, &mut new_assignments, plans);
+                        if let Some(tree) = tree.as_deref_mut() {
+                            tree.depth -= 1;
+                        }
                     }
 
                     return;
                 }
                 Command::CreatePartitionTable(command) => {
-                    if let Some(device_plan) = device_assignments.get_mut(&command.disk) {
-                        debug!("Creating partition table on disk {}", command.disk);
-                        device_plan.strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
-                    } else {
+                    let device_plans = assigned_plans_mut(device_assignments, &command.disk);
+                    if device_plans.is_empty() {
                         warn!("Could not find disk {} to create partition table", command.disk);
                     }
+                    for device_plan in device_plans {
+                        debug!("Creating partition table on disk {}", command.disk);
+                        device_plan.strategy = Strategy::new(AllocationStrategy::InitializeWholeDisk);
+                    }
                 }
-                Command::CreatePartition(command) => {
-                    if let Some(device_plan) = device_assignments.get_mut(&command.disk) {
-                        debug!("Adding partition request for disk {}", command.disk);
+                Command::KeepPartition(command) => {
+                    let device_plans = assigned_plans_mut(device_assignments, &command.disk);
+                    if device_plans.is_empty() {
+                        warn!("Could not find disk {} to keep partition", command.disk);
+                    }
+                    for device_plan in device_plans {
+                        let indices = device_plan
+                            .planner
+                            .original_regions()
+                            .iter()
+                            .enumerate()
+                            .filter(|(index, region)| command.match_by.matches(*index, region))
+                            .map(|(index, _)| index)
+                            .collect::<Vec<_>>();
+                        debug!("Keeping partitions {indices:?} on disk {}", command.disk);
+                        for index in indices {
+                            if let Err(e) = device_plan.planner.keep_partition(index) {
+                                warn!("Failed to keep partition {index} on disk {}: {e:?}", command.disk);
+                            }
+                        }
+                    }
+                }
+                Command::CreatePartitionFromImage(command) => {
+                    let device_plans = assigned_plans_mut(device_assignments, &command.disk);
+                    if device_plans.is_empty() {
+                        warn!("Could not find disk {} to create image partition", command.disk);
+                    }
+                    for device_plan in device_plans {
+                        debug!("Adding image partition request for disk {}", command.disk);
+                        // Round up so that alignment can't leave the partition short of the image
+                        let alignment = device_plan.planner.alignment();
+                        let size = command.image_size.div_ceil(alignment) * alignment;
                         device_plan.strategy.add_request(PartitionRequest {
-                            size: match &command.constraints {
-                                Constraints::AtLeast(n) => SizeRequirement::AtLeast(*n),
-                                Constraints::Exact(n) => SizeRequirement::Exact(*n),
-                                Constraints::Range { min, max } => SizeRequirement::Range { min: *min, max: *max },
-                                _ => SizeRequirement::Remaining,
-                            },
                             attributes: Some(command.attributes()),
+                            ..PartitionRequest::new(SizeRequirement::Exact(size))
                         });
-                    } else {
+                    }
+                }
+                Command::ReserveGap(command) => {
+                    let device_plans = assigned_plans_mut(device_assignments, &command.disk);
+                    if device_plans.is_empty() {
+                        warn!("Could not find disk {} to reserve gap", command.disk);
+                    }
+                    for device_plan in device_plans {
+                        debug!("Reserving {} gap on disk {}", format_size(command.size), command.disk);
+                        device_plan.strategy.add_gap(command.size);
+                    }
+                }
+                Command::CreatePartition(command) => {
+                    let device_plans = assigned_plans_mut(device_assignments, &command.disk);
+                    if device_plans.is_empty() {
                         warn!("Could not find disk {} to create partition", command.disk);
                     }
+                    for device_plan in device_plans {
+                        debug!("Adding partition request for disk {}", command.disk);
+                        let mut attributes = command.attributes();
+                        if attributes.filesystem.is_none() {
+                            attributes.filesystem = command
+                                .role
+                                .as_ref()
+                                .and_then(|role| filesystem_defaults.get(role))
+                                .map(|fs| (*fs).clone());
+                        }
+                        let size = match &command.constraints {
+                            Constraints::AtLeast(n) => SizeRequirement::AtLeast(*n),
+                            Constraints::Exact(n) => SizeRequirement::Exact(*n),
+                            Constraints::Range { min, max } => SizeRequirement::Range { min: *min, max: *max },
+                            _ => SizeRequirement::Remaining,
+                        };
+                        device_plan.strategy.add_request(PartitionRequest {
+                            attributes: Some(attributes),
+                            group: command.group.clone(),
+                            number: command.number,
+                            ..PartitionRequest::new(size)
+                        });
+                    }
+                }
+            }
+        }
+
+        match self.finish_plan(strategy, &chain, device_assignments) {
+            Ok(plan) => {
+                if let Some(tree) = tree {
+                    tree.line(format!("plan: {}", plan.summary_line()));
                 }
+                plans.push(plan);
+            }
+            Err(rejection) => {
+                if let Some(tree) = tree {
+                    tree.line(format!("rejected: {}", rejection.reason));
+                }
+                rejections.push(rejection);
             }
         }
+    }
 
+    /// Validate the fully assigned devices of a strategy, producing its plan
+    fn finish_plan(
+        &self,
+        strategy: &'a StrategyDefinition,
+        chain: &[&'a StrategyDefinition],
+        device_assignments: &mut HashMap<String, DevicePlan<'a>>,
+    ) -> Result<Plan<'a>, PlanRejection> {
         let mut role_mounts = HashMap::new();
         let mut filesystems = HashMap::new();
+        let mut duplicate_role = None;
+        let mut warnings = vec![];
 
         // OK lets now apply any mutations to the device assignments
         for (disk_name, device_plan) in device_assignments.iter_mut() {
             debug!("Applying device plan for disk {disk_name}");
             if let Err(e) = device_plan.strategy.apply(&mut device_plan.planner) {
-                warn!("Failed to apply strategy for disk {disk_name}: {e:?}");
+                warn!(
+                    "Rejecting plan for strategy {}: failed to apply to disk {disk_name}: {e}",
+                    strategy.name
+                );
+                return Err(PlanRejection {
+                    strategy: strategy.name.clone(),
+                    reason: format!("disk {disk_name}: {e}"),
+                });
             }
-            for region in device_plan.planner.current_layout().iter() {
+            if device_plan.planner.wipe_disk() {
+                if let Some(kind) = device_plan.device.detect_table() {
+                    warn!("Disk {disk_name} already has a {kind} partition table");
+                    warnings.push(PlanWarning::ExistingTable {
+                        disk: disk_name.clone(),
+                        kind,
+                    });
+                }
+            }
+            let layout = device_plan.planner.current_layout();
+            let size = device_plan.device.size();
+            let free = size.saturating_sub(layout.iter().map(|r| r.size()).sum());
+            if free > size / 2 {
+                warnings.push(PlanWarning::UnusedSpace {
+                    disk: disk_name.clone(),
+                    free,
+                    size,
+                });
+            }
+            for region in layout.iter() {
                 if let Some(id) = region.partition_id {
                     let device_path = device_plan.device.partition_path(id as usize);
                     if let Some(attributes) = region.attributes.as_ref() {
                         if let Some(role) = attributes.role.as_ref() {
-                            role_mounts.insert(role.clone(), device_path.clone());
+                            match role {
+                                PartitionRole::Boot if region.size() < RECOMMENDED_ESP_SIZE => {
+                                    warnings.push(PlanWarning::SmallEsp {
+                                        path: device_path.clone(),
+                                        size: region.size(),
+                                        recommended: RECOMMENDED_ESP_SIZE,
+                                    });
+                                }
+                                PartitionRole::Swap if region.size() < RECOMMENDED_SWAP_SIZE => {
+                                    warnings.push(PlanWarning::SmallSwap {
+                                        path: device_path.clone(),
+                                        size: region.size(),
+                                        recommended: RECOMMENDED_SWAP_SIZE,
+                                    });
+                                }
+                                _ => {}
+                            }
+                            if role.is_unique() && role_mounts.contains_key(role) {
+                                duplicate_role = Some(role.clone());
+                            }
+                            // Only the first partition of a role is mounted, so further
+                            // server data partitions are formatted but left unmounted
+                            role_mounts.entry(role.clone()).or_insert_with(|| device_path.clone());
                         }
                         if let Some(fs) = attributes.filesystem.as_ref() {
                             filesystems.insert(device_path, fs.clone());
@@ -203,14 +1456,171 @@ impl<'a> Provisioner<'a> {
             }
         }
 
+        // Locate the partitions created for each image by their GPT name
+        let mut images = vec![];
+        for command in chain.iter().flat_map(|s| &s.commands) {
+            let Command::CreatePartitionFromImage(command) = command else {
+                continue;
+            };
+            let device_plans = device_assignments
+                .iter()
+                .filter(|(key, _)| is_assigned_to(key, &command.disk))
+                .map(|(_, device_plan)| device_plan);
+            for device_plan in device_plans {
+                let region = device_plan.planner.current_layout().into_iter().find(|region| {
+                    region
+                        .attributes
+                        .as_ref()
+                        .and_then(|a| a.table.as_gpt())
+                        .is_some_and(|gpt| gpt.name.as_ref() == Some(&command.id))
+                });
+                match region {
+                    Some(region) if region.size() >= command.image_size => {
+                        let id = region.partition_id.unwrap_or_default();
+                        images.push(ImageSource {
+                            partition: device_plan.device.partition_path(id as usize),
+                            source: command.source.clone(),
+                            size: command.image_size,
+                        });
+                    }
+                    _ => {
+                        warn!(
+                            "Rejecting plan for strategy {}: no room for image {}",
+                            strategy.name, command.id
+                        );
+                        return Err(PlanRejection {
+                            strategy: strategy.name.clone(),
+                            reason: format!(
+                                "partition {} cannot hold its {} byte image",
+                                command.id, command.image_size
+                            ),
+                        });
+                    }
+                }
+            }
+        }
+
+        // Children override the minimum of their parents
+        if let Some(minimum) = chain.iter().rev().find_map(|s| s.min_data_partitions) {
+            let count = device_assignments
+                .values()
+                .flat_map(|device_plan| device_plan.planner.current_layout())
+                .filter(|region| {
+                    !matches!(
+                        region.attributes.as_ref().and_then(|a| a.role.as_ref()),
+                        Some(PartitionRole::Boot | PartitionRole::ExtendedBoot | PartitionRole::Swap)
+                    )
+                })
+                .count();
+            if count < minimum {
+                warn!(
+                    "Rejecting plan for strategy {}: only {count} data partitions",
+                    strategy.name
+                );
+                return Err(PlanRejection {
+                    strategy: strategy.name.clone(),
+                    reason: format!("{count} data partitions, at least {minimum} required"),
+                });
+            }
+        }
+
+        if self.require_full_coverage {
+            let unassigned = self
+                .devices
+                .iter()
+                .filter(|d| self.allow_system_disk || !d.is_system_disk())
+                .filter(|d| {
+                    !device_assignments
+                        .values()
+                        .any(|assigned| std::ptr::eq(assigned.device as *const BlockDevice, **d as *const BlockDevice))
+                })
+                .map(|d| d.device().display().to_string())
+                .collect::<Vec<_>>();
+            if !unassigned.is_empty() {
+                warn!(
+                    "Rejecting plan for strategy {}: unassigned devices {unassigned:?}",
+                    strategy.name
+                );
+                return Err(PlanRejection {
+                    strategy: strategy.name.clone(),
+                    reason: format!("devices left unassigned: {}", unassigned.join(", ")),
+                });
+            }
+        }
+
+        // A system can only have one root, ESP, swap and so on
+        if let Some(role) = duplicate_role {
+            warn!(
+                "Rejecting plan for strategy {}: multiple {role} partitions",
+                strategy.name
+            );
+            return Err(PlanRejection {
+                strategy: strategy.name.clone(),
+                reason: format!("multiple partitions with the {role} role"),
+            });
+        }
+
+        // Children override the ESP mountpoint of their parents
+        let esp_mountpoint = chain
+            .iter()
+            .rev()
+            .find_map(|s| s.esp_mountpoint.as_deref())
+            .unwrap_or(DEFAULT_ESP_MOUNTPOINT);
+
+        // Per-partition mount options come first, followed by any global options
+        let global_mount_options = chain.iter().flat_map(|s| &s.global_mount_options).collect::<Vec<_>>();
+        let mut mounts = role_mounts
+            .iter()
+            .filter(|(role, _)| role.as_path().starts_with('/'))
+            .map(|(role, device)| {
+                let mut options = chain
+                    .iter()
+                    .flat_map(|s| &s.commands)
+                    .find_map(|command| match command {
+                        Command::CreatePartition(command) if command.role.as_ref() == Some(role) => {
+                            Some(command.mount_options.clone())
+                        }
+                        _ => None,
+                    })
+                    .unwrap_or_default();
+                for option in &global_mount_options {
+                    if !options.contains(option) {
+                        options.push((*option).clone());
+                    }
+                }
+                MountEntry {
+                    device: device.clone(),
+                    mountpoint: match role {
+                        PartitionRole::Boot => PathBuf::from(esp_mountpoint),
+                        _ => PathBuf::from(role.as_path()),
+                    },
+                    options,
+                }
+            })
+            .collect::<Vec<_>>();
+        mounts.sort_by(|a, b| a.mountpoint.cmp(&b.mountpoint));
+
         // All commands processed successfully - create a plan
         debug!("Creating final plan for strategy {}", strategy.name);
-        plans.push(Plan {
+        let plan = Plan {
             strategy,
             role_mounts,
             filesystems,
+            mounts,
             device_assignments: device_assignments.clone(),
-        });
+            warnings,
+            images,
+        };
+
+        if let Some(reason) = self.validators.iter().find_map(|validator| validator(&plan).err()) {
+            warn!("Rejecting plan for strategy {}: {reason}", strategy.name);
+            return Err(PlanRejection {
+                strategy: strategy.name.clone(),
+                reason,
+            });
+        }
+
+        Ok(plan)
     }
 }
 
@@ -223,18 +1633,40 @@ mod tests {
 
     use super::*;
 
-    #[test]
-    fn test_use_whole_disk() {
-        let test_strategies = Parser::new_for_path("tests/use_whole_disk.kdl").unwrap();
-        let def = test_strategies.strategies;
-        let device = BlockDevice::mock_device(MockDisk::new(150 * 1024 * 1024 * 1024));
+    /// A 150GiB mock disk, large enough for every strategy in the tests
+    fn mock_device() -> BlockDevice {
+        BlockDevice::mock_device(MockDisk::new(150 * 1024 * 1024 * 1024))
+    }
+
+    /// A provisioner offering the given strategies on the given devices
+    fn provisioner_for<'a>(
+        strategies: impl IntoIterator<Item = &'a StrategyDefinition>,
+        devices: &[&'a BlockDevice],
+    ) -> Provisioner<'a> {
         let mut provisioner = Provisioner::new();
-        provisioner.push_device(&device);
-        for def in def.iter() {
+        for device in devices {
+            provisioner.push_device(device);
+        }
+        for def in strategies {
             provisioner.add_strategy(def);
         }
+        provisioner
+    }
 
-        let plans = provisioner.plan();
+    /// Plan the given strategies on the given devices
+    fn plan_on<'a>(
+        strategies: impl IntoIterator<Item = &'a StrategyDefinition>,
+        devices: &[&'a BlockDevice],
+    ) -> Vec<Plan<'a>> {
+        provisioner_for(strategies, devices).plan()
+    }
+
+    #[test]
+    fn test_use_whole_disk() {
+        let test_strategies = Parser::new_for_path("tests/use_whole_disk.kdl").unwrap();
+        let def = test_strategies.strategies;
+        let device = mock_device();
+        let plans = plan_on(&def, &[&device]);
         assert_eq!(plans.len(), 1);
 
         let plan = &plans[0];
@@ -248,4 +1680,1258 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_describe() {
+        let test_strategies = Parser::new_for_path("tests/use_whole_disk.kdl").unwrap();
+        let device = mock_device();
+        let plans = plan_on(&test_strategies.strategies, &[&device]);
+        let description = plans[0].describe();
+        assert!(description.starts_with("Strategy whole_disk: Wipe and use an entire disk\n"));
+        assert!(description.contains("root_disk: /dev/mock0 (150.0GiB)"));
+        assert!(
+            description
+                .lines()
+                .any(|line| line.starts_with("  /dev/mock03: ") && line.ends_with(", root, xfs, mounted at /"))
+        );
+        assert!(description.contains("Existing data on /dev/mock0 will be destroyed"));
+        assert_eq!(description, plans[0].describe());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_plan_json() {
+        let test_strategies = Parser::new_for_path("tests/use_whole_disk.kdl").unwrap();
+        let device = mock_device();
+        let plans = plan_on(&test_strategies.strategies, &[&device]);
+        let json = plans[0].to_json().unwrap();
+        assert!(json.contains(r#""strategy": "whole_disk""#));
+        assert!(json.contains(r#""mountpoint": "/""#));
+        assert!(json.contains(r#""mountpoint": "/boot/efi""#));
+
+        let snapshot: PlanSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(snapshot, plans[0].snapshot());
+        assert_eq!(snapshot.devices[0].device, PathBuf::from("/dev/mock0"));
+        assert_eq!(snapshot.devices[0].partitions.len(), 3);
+        assert_eq!(snapshot.devices[0].partitions[2].role, Some(PartitionRole::Root));
+    }
+
+    #[test]
+    fn test_revalidate() {
+        let test_strategies = Parser::new_for_path("tests/use_whole_disk.kdl").unwrap();
+        let device = mock_device();
+        let plans = plan_on(&test_strategies.strategies, &[&device]);
+        let plan = &plans[0];
+        assert!(plan.revalidate(&[&device]).is_ok());
+
+        // The bound disk has gone away
+        let other = BlockDevice::mock_device(MockDisk::new_with_name("sdb", 150 * 1024 * 1024 * 1024, false));
+        assert!(matches!(
+            plan.revalidate(&[&other]),
+            Err(RevalidationError::MissingDevice { .. })
+        ));
+
+        // The bound disk was swapped for a smaller one at the same path
+        let smaller = BlockDevice::mock_device(MockDisk::new(100 * 1024 * 1024 * 1024));
+        assert!(matches!(
+            plan.revalidate(&[&smaller]),
+            Err(RevalidationError::SizeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_diff_against_device() {
+        const GB: u64 = 1024 * 1024 * 1024;
+
+        let test_strategies = Parser::new_for_path("tests/use_whole_disk.kdl").unwrap();
+        let device = mock_device();
+        let plans = plan_on(&test_strategies.strategies, &[&device]);
+        let plan = &plans[0];
+
+        let diff = plan.diff_against_device(&device);
+        assert_eq!(diff.added.len(), 3);
+        assert!(diff.removed.is_empty() && diff.unchanged.is_empty());
+        assert!(!diff.has_drift());
+
+        // Someone partitioned the disk after planning
+        let mut disk = MockDisk::new(150 * GB);
+        disk.add_partition(0, 10 * GB);
+        let changed = BlockDevice::mock_device(disk);
+        let diff = plan.diff_against_device(&changed);
+        assert_eq!(diff.added.len(), 3);
+        assert_eq!(diff.removed.len(), 1);
+        assert!(diff.has_drift());
+        assert!(diff.drifted[0].same_extent(&diff.removed[0]));
+
+        // Unrelated devices have nothing planned
+        let other = BlockDevice::mock_device(MockDisk::new_with_name("sdb", 150 * GB, false));
+        assert!(plan.diff_against_device(&other).added.is_empty());
+    }
+
+    #[test]
+    fn test_keep_partition() {
+        const MB: u64 = 1024 * 1024;
+        const GB: u64 = 1024 * MB;
+
+        let test_strategies = Parser::new(
+            "keep.kdl",
+            r#"
+            strategy name="keep_esp" summary="Keep the ESP and reclaim the rest" {
+                find-disk "root_disk"
+                keep-partition disk="root_disk" {
+                    type (GUID)"efi-system-partition"
+                }
+                create-partition-table type="gpt" disk="root_disk"
+                create-partition disk="root_disk" role="root" id="root" {
+                    constraints {
+                        remaining
+                    }
+                }
+            }
+            "#,
+        )
+        .unwrap();
+
+        // Windows layout: ESP, MSR, C: and recovery
+        let mut disk = MockDisk::new(500 * GB);
+        disk.add_partition(0, 100 * MB).type_guid = Some(partitioning::gpt::partition_types::EFI.guid.to_string());
+        disk.add_partition(100 * MB, 116 * MB);
+        disk.add_partition(116 * MB, 200 * GB + 116 * MB);
+        disk.add_partition(200 * GB + 116 * MB, 200 * GB + 616 * MB);
+        let device = BlockDevice::mock_device(disk);
+
+        let plans = plan_on(&test_strategies.strategies, &[&device]);
+        assert_eq!(plans.len(), 1);
+
+        let device_plan = &plans[0].device_assignments["root_disk"];
+        assert!(!device_plan.planner.wipe_disk());
+
+        let layout = device_plan.planner.current_layout();
+        assert_eq!(layout.len(), 2);
+        assert_eq!(layout[0].partition_id, Some(1));
+        assert_eq!(layout[0].end, 100 * MB);
+        assert_eq!(layout[1].start, 100 * MB);
+    }
+
+    #[test]
+    fn test_estimated_steps() {
+        let test_strategies = Parser::new_for_path("tests/use_whole_disk.kdl").unwrap();
+        let def = test_strategies
+            .strategies
+            .iter()
+            .find(|s| s.name == "whole_disk")
+            .unwrap();
+
+        let device = mock_device();
+        let plans = plan_on([def], &[&device]);
+        let wipe_steps = plans[0].device_assignments["root_disk"].estimated_steps();
+
+        let mut planner = Planner::new(&device);
+        planner.plan_add_partition(0, 1024 * 1024 * 1024).unwrap();
+        let single = DevicePlan {
+            device: &device,
+            planner,
+            strategy: Strategy::new(AllocationStrategy::LargestFree),
+        };
+        let single_steps = single.estimated_steps();
+
+        assert!(wipe_steps.len() > single_steps.len());
+        assert!(wipe_steps.iter().any(|(label, _)| label.starts_with("Format")));
+        assert!(!single_steps.iter().any(|(label, _)| label.starts_with("Format")));
+
+        let total = |steps: &[(String, Duration)]| steps.iter().map(|(_, d)| *d).sum::<Duration>();
+        assert!(total(&wipe_steps) > total(&single_steps));
+    }
+
+    /// Build a plan that wipes each of the given devices
+    fn wipe_plan<'a>(strategy: &'a StrategyDefinition, devices: &[(&str, &'a BlockDevice)]) -> Plan<'a> {
+        let device_assignments = devices
+            .iter()
+            .map(|(name, device)| {
+                let mut planner = Planner::new(device);
+                planner.plan_initialize_disk().unwrap();
+                let device_plan = DevicePlan {
+                    device,
+                    planner,
+                    strategy: Strategy::new(AllocationStrategy::InitializeWholeDisk),
+                };
+                (name.to_string(), device_plan)
+            })
+            .collect();
+
+        Plan {
+            strategy,
+            device_assignments,
+            role_mounts: HashMap::new(),
+            filesystems: HashMap::new(),
+            mounts: vec![],
+            warnings: vec![],
+            images: vec![],
+        }
+    }
+
+    #[test]
+    fn test_affected_devices() {
+        let test_strategies = Parser::new_for_path("tests/use_whole_disk.kdl").unwrap();
+        let def = test_strategies.strategies.first().unwrap();
+
+        let sda = BlockDevice::mock_device(MockDisk::new_with_name("sda", 150 * 1024 * 1024 * 1024, false));
+        let sdb = BlockDevice::mock_device(MockDisk::new_with_name("sdb", 150 * 1024 * 1024 * 1024, false));
+        let nvme = BlockDevice::mock_device(MockDisk::new_with_name("nvme0n1", 150 * 1024 * 1024 * 1024, true));
+
+        // Devices without changes aren't affected
+        let mut untouched = wipe_plan(def, &[("data_disk", &nvme)]);
+        untouched.device_assignments.get_mut("data_disk").unwrap().planner = Planner::new(&nvme);
+
+        let plans = vec![
+            wipe_plan(def, &[("root_disk", &sda), ("home_disk", &sdb)]),
+            wipe_plan(def, &[("root_disk", &sdb)]),
+            untouched,
+        ];
+        assert_eq!(
+            Provisioner::affected_devices(&plans),
+            vec![PathBuf::from("/dev/sda"), PathBuf::from("/dev/sdb")]
+        );
+
+        let plans = vec![
+            wipe_plan(def, &[("root_disk", &nvme)]),
+            wipe_plan(def, &[("root_disk", &sda)]),
+        ];
+        assert_eq!(
+            Provisioner::affected_devices(&plans),
+            vec![PathBuf::from("/dev/nvme0n1"), PathBuf::from("/dev/sda")]
+        );
+    }
+
+    #[test]
+    fn test_validators() {
+        let test_strategies = Parser::new(
+            "swap.kdl",
+            r#"
+            strategy name="no_swap" summary="Root only" {
+                find-disk "root_disk"
+                create-partition-table type="gpt" disk="root_disk"
+                create-partition disk="root_disk" role="root" id="root" {
+                    constraints {
+                        remaining
+                    }
+                }
+            }
+            strategy name="with_swap" summary="Root and swap" {
+                find-disk "root_disk"
+                create-partition-table type="gpt" disk="root_disk"
+                create-partition disk="root_disk" role="swap" id="swap" {
+                    constraints {
+                        exactly (GiB)4
+                    }
+                }
+                create-partition disk="root_disk" role="root" id="root" {
+                    constraints {
+                        remaining
+                    }
+                }
+            }
+            "#,
+        )
+        .unwrap();
+
+        let device = mock_device();
+        let mut provisioner = provisioner_for(&test_strategies.strategies, &[&device]);
+        provisioner.add_validator(|plan| {
+            if plan.role_mounts.contains_key(&PartitionRole::Swap) {
+                Ok(())
+            } else {
+                Err("no swap partition".to_string())
+            }
+        });
+
+        let (plans, rejections) = provisioner.plan_with_rejections();
+        assert_eq!(plans.len(), 1);
+        assert_eq!(plans[0].strategy.name, "with_swap");
+        assert_eq!(rejections.len(), 1);
+        assert_eq!(rejections[0].strategy, "no_swap");
+        assert_eq!(rejections[0].reason, "no swap partition");
+
+        assert_eq!(provisioner.plan().len(), 1);
+    }
+
+    #[test]
+    fn test_system_disk_excluded() {
+        let test_strategies = Parser::new_for_path("tests/use_whole_disk.kdl").unwrap();
+        let def = test_strategies.strategies.first().unwrap();
+
+        let system = BlockDevice::mock_device(
+            MockDisk::new_with_name("sda", 150 * 1024 * 1024 * 1024, false).with_system_disk(true),
+        );
+        let spare = BlockDevice::mock_device(MockDisk::new_with_name("sdb", 150 * 1024 * 1024 * 1024, false));
+        assert!(system.is_system_disk());
+        assert!(!spare.is_system_disk());
+
+        let plans = plan_on([def], &[&system, &spare]);
+        assert_eq!(plans.len(), 1);
+        assert_eq!(
+            plans[0].device_assignments["root_disk"].device.device(),
+            PathBuf::from("/dev/sdb")
+        );
+
+        let mut provisioner = Provisioner::new().with_allow_system_disk(true);
+        provisioner.push_device(&system);
+        provisioner.push_device(&spare);
+        provisioner.add_strategy(def);
+        assert_eq!(provisioner.plan().len(), 2);
+    }
+
+    #[test]
+    fn test_plan_with_tags() {
+        let test_strategies = Parser::new(
+            "tags.kdl",
+            r#"
+            strategy name="server" summary="Headless server" {
+                tags "server" "uefi"
+                find-disk "root_disk"
+                create-partition-table type="gpt" disk="root_disk"
+            }
+            strategy name="desktop" summary="Desktop" {
+                tags "desktop" "uefi"
+                find-disk "root_disk"
+                create-partition-table type="gpt" disk="root_disk"
+            }
+            strategy name="rescue" summary="Rescue system" {
+                tags "server" "rescue"
+                find-disk "root_disk"
+                create-partition-table type="gpt" disk="root_disk"
+            }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(test_strategies.strategies[0].tags, vec!["server", "uefi"]);
+
+        let device = mock_device();
+        let provisioner = provisioner_for(&test_strategies.strategies, &[&device]);
+
+        assert_eq!(provisioner.plan().len(), 3);
+
+        let mut names = provisioner
+            .plan_with_tags(&["server"])
+            .iter()
+            .map(|plan| plan.strategy.name.clone())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["rescue", "server"]);
+
+        assert!(provisioner.plan_with_tags(&["testing"]).is_empty());
+    }
+
+    #[test]
+    fn test_summary_line() {
+        let test_strategies = Parser::new_for_path("tests/use_whole_disk.kdl").unwrap();
+        let def = test_strategies.strategies.first().unwrap();
+
+        let device = mock_device();
+        let plans = plan_on([def], &[&device]);
+        assert_eq!(
+            plans[0].summary_line(),
+            "whole_disk on /dev/mock0: 3 partitions (boot 2.0GiB, extended-boot 4.0GiB, root 120.0GiB)"
+        );
+    }
+
+    #[test]
+    fn test_min_data_partitions() {
+        let test_strategies = Parser::new(
+            "data.kdl",
+            r#"
+            strategy name="single" summary="A single data partition" {
+                min-data-partitions 2
+                find-disk "root_disk"
+                create-partition-table type="gpt" disk="root_disk"
+                create-partition disk="root_disk" role="boot" id="esp" {
+                    constraints {
+                        exactly (GiB)1
+                    }
+                }
+                create-partition disk="root_disk" role="root" id="root" {
+                    constraints {
+                        remaining
+                    }
+                }
+            }
+
+            strategy name="split" summary="Separate root and home" inherits="single" {
+                create-partition disk="root_disk" role="home" id="home" {
+                    constraints {
+                        exactly (GiB)20
+                    }
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let device = mock_device();
+        let provisioner = provisioner_for(&test_strategies.strategies, &[&device]);
+
+        let (plans, rejections) = provisioner.plan_with_rejections();
+        assert_eq!(plans.len(), 1);
+        assert_eq!(plans[0].strategy.name, "split");
+        assert_eq!(rejections.len(), 1);
+        assert_eq!(rejections[0].strategy, "single");
+        assert_eq!(rejections[0].reason, "1 data partitions, at least 2 required");
+    }
+
+    #[test]
+    fn test_require_full_coverage() {
+        let test_strategies = Parser::new_for_path("tests/use_whole_disk.kdl").unwrap();
+        let def = test_strategies.strategies.first().unwrap();
+
+        let sda = BlockDevice::mock_device(MockDisk::new_with_name("sda", 150 * 1024 * 1024 * 1024, false));
+        let sdb = BlockDevice::mock_device(MockDisk::new_with_name("sdb", 150 * 1024 * 1024 * 1024, false));
+        let provisioner = provisioner_for([def], &[&sda, &sdb]);
+
+        // One plan per disk, each leaving the other unused
+        assert_eq!(provisioner.plan().len(), 2);
+
+        let provisioner = provisioner.require_full_coverage(true);
+        let (plans, rejections) = provisioner.plan_with_rejections();
+        assert!(plans.is_empty());
+        assert_eq!(rejections.len(), 2);
+        assert_eq!(rejections[0].reason, "devices left unassigned: /dev/sdb");
+        assert_eq!(rejections[1].reason, "devices left unassigned: /dev/sda");
+    }
+
+    #[test]
+    fn test_oversubscribed_strategy() {
+        let test_strategies = Parser::new(
+            "oversubscribed.kdl",
+            r#"
+            strategy name="oversubscribed" summary="More than the disk can hold" {
+                find-disk "root_disk"
+                create-partition-table type="gpt" disk="root_disk"
+                create-partition disk="root_disk" role="root" id="root" {
+                    constraints {
+                        exactly (GiB)100
+                    }
+                }
+                create-partition disk="root_disk" role="home" id="home" {
+                    constraints {
+                        exactly (GiB)100
+                    }
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let device = mock_device();
+        let provisioner = provisioner_for(&test_strategies.strategies, &[&device]);
+
+        let (plans, rejections) = provisioner.plan_with_rejections();
+        assert!(plans.is_empty());
+        assert_eq!(rejections.len(), 1);
+        assert!(
+            rejections[0]
+                .reason
+                .starts_with("disk root_disk: Fixed size requests [0, 1]")
+        );
+    }
+
+    #[test]
+    fn test_load_devices_from_manifest() {
+        const GB: u64 = 1024 * 1024 * 1024;
+
+        let devices = Provisioner::load_devices_from_manifest(
+            r#"{
+                "devices": [
+                    {
+                        "name": "sda",
+                        "size": 536870912000,
+                        "model": "Samsung SSD 870",
+                        "partitions": [{ "start": 1048576, "end": 107374182400 }]
+                    },
+                    {
+                        "name": "sdb",
+                        "size": 20401094656,
+                        "rotational": true,
+                        "removable": true
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].size(), 500 * GB);
+        assert_eq!(devices[0].model(), Some("Samsung SSD 870"));
+        assert_eq!(devices[0].partition_count(), 1);
+        assert!(!devices[0].is_rotational() && !devices[0].is_removable());
+        assert!(devices[1].is_rotational() && devices[1].is_removable());
+
+        // Only the first disk meets the minimum size of the strategy
+        let test_strategies = Parser::new_for_path("tests/use_whole_disk.kdl").unwrap();
+        let plans = plan_on(&test_strategies.strategies, &devices.iter().collect::<Vec<_>>());
+        assert_eq!(plans.len(), 1);
+        assert_eq!(Provisioner::affected_devices(&plans), vec![PathBuf::from("/dev/sda")]);
+
+        assert!(matches!(
+            Provisioner::load_devices_from_manifest(r#"{ "devices": [{ "name": "sda" }] }"#),
+            Err(ManifestError::Parse(_))
+        ));
+        assert!(matches!(
+            Provisioner::load_devices_from_manifest(
+                r#"{ "devices": [{ "name": "sda", "size": 1048576, "partitions": [{ "start": 0, "end": 2097152 }] }] }"#
+            ),
+            Err(ManifestError::InvalidPartition { .. })
+        ));
+    }
+
+    #[test]
+    fn test_plan_cache() {
+        let test_strategies = Parser::new_for_path("tests/use_whole_disk.kdl").unwrap();
+        let def = test_strategies.strategies.first().unwrap();
+
+        let sda = BlockDevice::mock_device(MockDisk::new_with_name("sda", 150 * 1024 * 1024 * 1024, false));
+        let sdb = BlockDevice::mock_device(MockDisk::new_with_name("sdb", 150 * 1024 * 1024 * 1024, false));
+        let mut provisioner = provisioner_for([def], &[&sda]);
+
+        // Consecutive plans without changes reuse the cached result
+        assert_eq!(provisioner.plan().len(), 1);
+        assert_eq!(provisioner.plan().len(), 1);
+        assert_eq!(provisioner.planning_runs.get(), 1);
+
+        // Changing the device pool invalidates the cache
+        provisioner.push_device(&sdb);
+        assert_eq!(provisioner.plan().len(), 2);
+        assert_eq!(provisioner.planning_runs.get(), 2);
+
+        provisioner.clear_cache();
+        assert_eq!(provisioner.plan().len(), 2);
+        assert_eq!(provisioner.planning_runs.get(), 3);
+    }
+
+    #[test]
+    fn test_global_mount_options() {
+        let test_strategies = Parser::new(
+            "mounts.kdl",
+            r#"
+            strategy name="noatime" summary="Mount everything noatime" {
+                global-mount-options "noatime"
+                find-disk "root_disk"
+                create-partition-table type="gpt" disk="root_disk"
+                create-partition disk="root_disk" role="root" id="root" {
+                    constraints {
+                        exactly (GiB)20
+                    }
+                    mount-options "noatime" "discard"
+                }
+                create-partition disk="root_disk" role="home" id="home" {
+                    constraints {
+                        exactly (GiB)20
+                    }
+                }
+                create-partition disk="root_disk" role="swap" id="swap" {
+                    constraints {
+                        exactly (GiB)4
+                    }
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let def = test_strategies.strategies.first().unwrap();
+        assert_eq!(def.global_mount_options, vec!["noatime"]);
+
+        let device = mock_device();
+        let plans = plan_on([def], &[&device]);
+        assert_eq!(plans.len(), 1);
+        assert_eq!(
+            plans[0].mounts,
+            vec![
+                MountEntry {
+                    device: PathBuf::from("/dev/mock01"),
+                    mountpoint: PathBuf::from("/"),
+                    options: vec!["noatime".to_string(), "discard".to_string()],
+                },
+                MountEntry {
+                    device: PathBuf::from("/dev/mock02"),
+                    mountpoint: PathBuf::from("/home"),
+                    options: vec!["noatime".to_string()],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_esp_mountpoint() {
+        let test_strategies = Parser::new(
+            "esp.kdl",
+            r#"
+            strategy name="classic" summary="ESP at the traditional location" {
+                find-disk "root_disk"
+                create-partition-table type="gpt" disk="root_disk"
+                create-partition disk="root_disk" role="boot" id="esp" {
+                    constraints {
+                        exactly (MiB)512
+                    }
+                }
+                create-partition disk="root_disk" role="root" id="root" {
+                    constraints {
+                        remaining
+                    }
+                }
+            }
+
+            strategy name="systemd-boot" summary="ESP at /efi" inherits="classic" {
+                esp-mountpoint "/efi"
+            }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(test_strategies.strategies[1].esp_mountpoint.as_deref(), Some("/efi"));
+
+        let device = mock_device();
+        let plans = plan_on(&test_strategies.strategies, &[&device]);
+        assert_eq!(plans.len(), 2);
+        for plan in plans {
+            let expected = match plan.strategy.name.as_str() {
+                "classic" => DEFAULT_ESP_MOUNTPOINT,
+                _ => "/efi",
+            };
+            let mountpoints = plan.mounts.iter().map(|m| m.mountpoint.clone()).collect::<Vec<_>>();
+            assert_eq!(mountpoints, vec![PathBuf::from("/"), PathBuf::from(expected)]);
+        }
+    }
+
+    #[test]
+    fn test_image_partition() {
+        let test_strategies = Parser::new(
+            "image.kdl",
+            r#"
+            strategy name="oem" summary="Root with an OEM recovery partition" {
+                find-disk "root_disk"
+                create-partition-table type="gpt" disk="root_disk"
+                create-partition disk="root_disk" role="root" id="root" {
+                    constraints {
+                        exactly (GiB)20
+                    }
+                    type (GUID)"linux-fs"
+                }
+                create-partition-from-image disk="root_disk" id="recovery" source="/usr/share/oem/recovery.img" {
+                    image-size (B)4294967396
+                    type (GUID)"linux-fs"
+                }
+            }
+
+            strategy name="too_big" summary="Image larger than the disk" {
+                find-disk "root_disk"
+                create-partition-table type="gpt" disk="root_disk"
+                create-partition-from-image disk="root_disk" id="recovery" source="/usr/share/oem/recovery.img" {
+                    image-size (GiB)200
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let device = mock_device();
+        let provisioner = provisioner_for(&test_strategies.strategies, &[&device]);
+
+        let (plans, rejections) = provisioner.plan_with_rejections();
+        assert_eq!(plans.len(), 1);
+        assert_eq!(rejections.len(), 1);
+        assert_eq!(rejections[0].strategy, "too_big");
+
+        let plan = &plans[0];
+        assert_eq!(
+            plan.image_sources(),
+            &[ImageSource {
+                partition: PathBuf::from("/dev/mock02"),
+                source: PathBuf::from("/usr/share/oem/recovery.img"),
+                size: 4294967396,
+            }]
+        );
+
+        // The partition is rounded up to hold the unaligned image
+        let layout = plan.device_assignments["root_disk"].planner.current_layout();
+        let recovery = layout.iter().find(|r| r.partition_id == Some(2)).unwrap();
+        assert_eq!(recovery.size(), 4097 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_small_esp_warning() {
+        let test_strategies = Parser::new(
+            "small_esp.kdl",
+            r#"
+            strategy name="small_esp" summary="Tiny ESP" {
+                find-disk "root_disk"
+                create-partition-table type="gpt" disk="root_disk"
+                create-partition disk="root_disk" role="boot" id="esp" {
+                    constraints {
+                        exactly (MiB)128
+                    }
+                    type (GUID)"efi-system-partition"
+                }
+                create-partition disk="root_disk" role="root" id="root" {
+                    constraints {
+                        remaining
+                    }
+                    type (GUID)"linux-fs"
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let def = test_strategies.strategies.first().unwrap();
+
+        let device = mock_device();
+        let plans = plan_on([def], &[&device]);
+        assert_eq!(plans.len(), 1);
+        assert_eq!(
+            plans[0].warnings,
+            vec![PlanWarning::SmallEsp {
+                path: PathBuf::from("/dev/mock01"),
+                size: 128 * 1024 * 1024,
+                recommended: RECOMMENDED_ESP_SIZE,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_existing_table_warning() {
+        let test_strategies = Parser::new_for_path("tests/use_whole_disk.kdl").unwrap();
+        let def = test_strategies.strategies.first().unwrap();
+
+        let mut header = vec![0u8; 1024];
+        header[510..512].copy_from_slice(&[0x55, 0xAA]);
+        header[512..520].copy_from_slice(b"EFI PART");
+        let device = BlockDevice::mock_device(MockDisk::new(150 * 1024 * 1024 * 1024).with_header(header));
+        let plans = plan_on([def], &[&device]);
+        assert_eq!(plans.len(), 1);
+        assert_eq!(
+            plans[0].warnings,
+            vec![PlanWarning::ExistingTable {
+                disk: "root_disk".into(),
+                kind: PartitionTableKind::Gpt,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_duplicate_unique_role() {
+        let test_strategies = Parser::new(
+            "two_roots.kdl",
+            r#"
+            strategy name="two_roots" summary="Duplicate root partitions" {
+                find-disk "root_disk"
+                create-partition-table type="gpt" disk="root_disk"
+                create-partition disk="root_disk" role="root" id="root" {
+                    constraints {
+                        exactly (GiB)20
+                    }
+                }
+                create-partition disk="root_disk" role="root" id="root2" {
+                    constraints {
+                        exactly (GiB)20
+                    }
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let device = mock_device();
+        let provisioner = provisioner_for(&test_strategies.strategies, &[&device]);
+
+        let (plans, rejections) = provisioner.plan_with_rejections();
+        assert!(plans.is_empty());
+        assert_eq!(rejections.len(), 1);
+        assert_eq!(rejections[0].strategy, "two_roots");
+        assert_eq!(rejections[0].reason, "multiple partitions with the root role");
+    }
+
+    #[test]
+    fn test_srv_role() {
+        let test_strategies = Parser::new(
+            "srv.kdl",
+            r#"
+            strategy name="server" summary="Root with server data" {
+                find-disk "root_disk"
+                create-partition-table type="gpt" disk="root_disk"
+                create-partition disk="root_disk" role="root" id="root" {
+                    constraints {
+                        exactly (GiB)20
+                    }
+                    type (GUID)"linux-fs"
+                }
+                create-partition disk="root_disk" role="srv" id="srv" {
+                    constraints {
+                        exactly (GiB)20
+                    }
+                    type (GUID)"linux-srv"
+                }
+                create-partition disk="root_disk" role="srv" id="srv2" {
+                    constraints {
+                        exactly (GiB)20
+                    }
+                    type (GUID)"linux-srv"
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let device = mock_device();
+        let plans = plan_on(&test_strategies.strategies, &[&device]);
+        assert_eq!(plans.len(), 1);
+        assert_eq!(PartitionRole::Srv.as_path(), "/srv");
+
+        // Several server data partitions may coexist, only the first is mounted at /srv
+        let plan = &plans[0];
+        assert_eq!(plan.role_mounts[&PartitionRole::Srv], Path::new("/dev/mock02"));
+        let srv_mounts = plan
+            .mounts
+            .iter()
+            .filter(|m| m.mountpoint.starts_with("/srv"))
+            .collect::<Vec<_>>();
+        assert_eq!(srv_mounts.len(), 1);
+        assert_eq!(srv_mounts[0].device, Path::new("/dev/mock02"));
+        let layout = plan.device_assignments["root_disk"].planner.current_layout();
+        let srv = layout
+            .iter()
+            .filter(|r| r.attributes.as_ref().and_then(|a| a.role.as_ref()) == Some(&PartitionRole::Srv))
+            .collect::<Vec<_>>();
+        assert_eq!(srv.len(), 2);
+        let gpt = srv[0].attributes.as_ref().and_then(|a| a.table.as_gpt()).unwrap();
+        assert_eq!(gpt.type_guid.guid.to_string(), "3b8f8425-20e0-4f3b-907f-1a25a76f98e8");
+    }
+
+    #[test]
+    fn test_find_disk_by_wwn() {
+        let test_strategies = Parser::new(
+            "wwn.kdl",
+            r#"
+            strategy name="wwn" summary="Use a specific disk" {
+                find-disk "root_disk" wwn="0x5000c500a1b2c3d4"
+                create-partition-table type="gpt" disk="root_disk"
+            }
+            "#,
+        )
+        .unwrap();
+        let def = test_strategies.strategies.first().unwrap();
+
+        let other = BlockDevice::mock_device(MockDisk::new(150 * 1024 * 1024 * 1024).with_wwn("0x5000c500deadbeef"));
+        let target = BlockDevice::mock_device(
+            MockDisk::new_with_name("mock1", 150 * 1024 * 1024 * 1024, false).with_wwn("0x5000c500a1b2c3d4"),
+        );
+        let plans = plan_on([def], &[&other, &target]);
+        assert_eq!(plans.len(), 1);
+        let device_plan = &plans[0].device_assignments["root_disk"];
+        assert_eq!(device_plan.device.device(), Path::new("/dev/mock1"));
+        assert_eq!(
+            device_plan.device_path(),
+            Path::new("/dev/disk/by-id/wwn-0x5000c500a1b2c3d4")
+        );
+        assert_eq!(
+            plans[0].summary_line(),
+            "wwn on /dev/disk/by-id/wwn-0x5000c500a1b2c3d4: 0 partitions"
+        );
+    }
+
+    #[test]
+    fn test_bytes_to_format() {
+        let test_strategies = Parser::new(
+            "format.kdl",
+            r#"
+            strategy name="format" summary="Typical desktop layout" {
+                find-disk "root_disk"
+                create-partition-table type="gpt" disk="root_disk"
+                create-partition disk="root_disk" role="boot" id="esp" {
+                    constraints {
+                        exactly (GiB) 1
+                    }
+                    filesystem {
+                        type "fat32"
+                    }
+                }
+                create-partition disk="root_disk" role="swap" id="swap" {
+                    constraints {
+                        exactly (GiB) 4
+                    }
+                    filesystem {
+                        type "swap"
+                    }
+                }
+                create-partition disk="root_disk" id="raw" {
+                    constraints {
+                        exactly (GiB) 2
+                    }
+                }
+                create-partition disk="root_disk" role="root" id="root" {
+                    constraints {
+                        exactly (GiB) 40
+                    }
+                    filesystem {
+                        type "xfs"
+                    }
+                }
+                create-partition disk="root_disk" role="home" id="home" {
+                    constraints {
+                        min (GiB) 10
+                    }
+                    filesystem {
+                        type "ext4"
+                    }
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let def = test_strategies.strategies.first().unwrap();
+
+        let disk = mock_device();
+        let plans = plan_on([def], &[&disk]);
+        assert_eq!(plans.len(), 1);
+        let size_of = |role: PartitionRole| {
+            plans[0].device_assignments["root_disk"]
+                .planner
+                .current_layout()
+                .iter()
+                .find(|r| r.attributes.as_ref().and_then(|a| a.role.as_ref()) == Some(&role))
+                .map(|r| r.size())
+                .unwrap()
+        };
+        let expected = size_of(PartitionRole::Boot) + size_of(PartitionRole::Root) + size_of(PartitionRole::Home);
+        assert_eq!(plans[0].bytes_to_format(), expected);
+        assert_eq!(size_of(PartitionRole::Root), 40 * 1024 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_reserve_gap() {
+        let test_strategies = Parser::new(
+            "gap.kdl",
+            r#"
+            strategy name="buffered" summary="Keep data apart from the system" {
+                find-disk "root_disk"
+                create-partition-table type="gpt" disk="root_disk"
+                create-partition disk="root_disk" role="root" id="root" {
+                    constraints {
+                        exactly (GiB)20
+                    }
+                }
+                reserve-gap disk="root_disk" size=(GiB)1
+                create-partition disk="root_disk" role="home" id="home" {
+                    constraints {
+                        exactly (GiB)50
+                    }
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let device = mock_device();
+        let plans = plan_on(test_strategies.strategies.first(), &[&device]);
+        assert_eq!(plans.len(), 1);
+        let layout = plans[0].device_assignments["root_disk"].planner.current_layout();
+        assert_eq!(layout.len(), 2);
+        assert_eq!(layout[0].gap_to(&layout[1]), Some(1024 * 1024 * 1024));
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let test_strategies = Parser::new(
+            "evaluate.kdl",
+            r#"
+            strategy name="single" summary="A single root partition" {
+                find-disk "root_disk"
+                create-partition-table type="gpt" disk="root_disk"
+                create-partition disk="root_disk" role="root" id="root" {
+                    constraints {
+                        remaining
+                    }
+                }
+            }
+
+            strategy name="split" summary="Needs more data partitions than it makes" inherits="single" {
+                min-data-partitions 2
+            }
+
+            strategy name="huge" summary="Needs a far larger disk" {
+                find-disk "root_disk" {
+                    constraints {
+                        min (TB) 10
+                    }
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let device = mock_device();
+        let provisioner = provisioner_for(&test_strategies.strategies, &[&device]);
+
+        let outcomes = provisioner.evaluate();
+        let status = |name: &str| outcomes.iter().find(|o| o.strategy == name).map(|o| o.status.clone());
+        assert_eq!(outcomes.len(), 3);
+        assert_eq!(status("single"), Some(StrategyStatus::Planned(1)));
+        assert_eq!(
+            status("split"),
+            Some(StrategyStatus::Failed(
+                "1 data partitions, at least 2 required".to_owned()
+            ))
+        );
+        assert!(matches!(status("huge"), Some(StrategyStatus::Skipped(_))));
+        assert_eq!(provisioner.plan().len(), 1);
+    }
+
+    #[test]
+    fn test_explain_plan() {
+        let test_strategies = Parser::new_for_path("tests/use_whole_disk.kdl").unwrap();
+        let def = test_strategies.strategies.first().unwrap();
+
+        let small = BlockDevice::mock_device(MockDisk::new_with_name("sda", 40 * 1024 * 1024 * 1024, false));
+        let large = BlockDevice::mock_device(MockDisk::new_with_name("sdb", 150 * 1024 * 1024 * 1024, false));
+        let provisioner = provisioner_for([def], &[&small, &large]);
+
+        let tree = provisioner.explain_plan(&def.name);
+        assert!(tree.starts_with(&format!("strategy {}\n", def.name)));
+        assert!(tree.contains("candidates /dev/sda, /dev/sdb"));
+        assert!(tree.contains("root_disk = /dev/sda\n"));
+        assert!(tree.contains("root_disk = /dev/sdb\n"));
+        assert_eq!(tree.matches("    plan: ").count(), provisioner.plan().len());
+        assert!(provisioner.explain_plan("missing").starts_with("unknown strategy"));
+    }
+
+    #[test]
+    fn test_find_disk_all() {
+        let test_strategies = Parser::new(
+            "pool.kdl",
+            r#"
+            strategy name="pool" summary="Spread data across every disk" {
+                find-disk "data" all=#true
+                create-partition-table type="gpt" disk="data"
+                create-partition disk="data" id="data" {
+                    constraints {
+                        min (GiB) 50
+                    }
+                    type (GUID) "linux-fs"
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let def = test_strategies.strategies.first().unwrap();
+
+        let disks = ["sda", "sdb", "sdc"]
+            .map(|name| BlockDevice::mock_device(MockDisk::new_with_name(name, 150 * 1024 * 1024 * 1024, false)));
+        let plans = plan_on([def], &disks.iter().collect::<Vec<_>>());
+        assert_eq!(plans.len(), 1);
+        let plan = &plans[0];
+        assert_eq!(plan.device_assignments.len(), 3);
+        for (index, name) in ["sda", "sdb", "sdc"].iter().enumerate() {
+            let device_plan = &plan.device_assignments[&format!("data[{index}]")];
+            assert_eq!(device_plan.device.name(), *name);
+            assert_eq!(device_plan.planner.current_layout().len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_parted_script() {
+        let test_strategies = Parser::new_for_path("tests/use_whole_disk.kdl").unwrap();
+        let def = test_strategies.strategies.first().unwrap();
+
+        let device = mock_device();
+        let plans = plan_on([def], &[&device]);
+        assert_eq!(
+            plans[0].to_parted_script(Path::new("/dev/mock0")).unwrap(),
+            vec![
+                "unit s",
+                "mklabel gpt",
+                r#"mkpart "EFI System Partition" 2048s 4196351s"#,
+                "set 1 esp on",
+                r#"mkpart "Linux Extended Boot" 4196352s 12584959s"#,
+                "set 2 bls_boot on",
+                r#"mkpart "Linux Filesystem" 12584960s 264243199s"#,
+            ]
+        );
+        assert!(plans[0].to_parted_script(Path::new("/dev/mock1")).unwrap().is_empty());
+
+        // Boundaries off the MiB grid are kept exactly
+        let mut plans = plans;
+        let device_plan = plans[0].device_assignments.values_mut().next().unwrap();
+        device_plan.planner = Planner::new(&device).with_alignment(4096).unwrap();
+        device_plan
+            .planner
+            .plan_add_partition(1024 * 1024, 1024 * 1024 + 8192)
+            .unwrap();
+        assert_eq!(
+            plans[0].to_parted_script(Path::new("/dev/mock0")).unwrap(),
+            vec!["unit s", r#"mkpart "" 2048s 2063s"#]
+        );
+
+        assert_eq!(parted_quote(r#"say "hi""#).as_deref(), Some(r#"'say "hi"'"#));
+        assert_eq!(parted_quote(r#"it's "x""#), None);
+    }
+
+    #[test]
+    fn test_sgdisk_commands() {
+        let test_strategies = Parser::new_for_path("tests/use_whole_disk.kdl").unwrap();
+        let def = test_strategies.strategies.first().unwrap();
+
+        let device = mock_device();
+        let plans = plan_on([def], &[&device]);
+        let commands = plans[0].to_sgdisk_commands().unwrap();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(
+            commands[Path::new("/dev/mock0")],
+            vec![
+                "sgdisk --zap-all /dev/mock0",
+                "sgdisk --new=1:2048:4196351 --typecode=1:C12A7328-F81F-11D2-BA4B-00A0C93EC93B '--change-name=1:EFI System Partition' /dev/mock0",
+                "sgdisk --new=2:4196352:12584959 --typecode=2:BC13C2FF-59E6-4262-A352-B275FD6F7172 '--change-name=2:Linux Extended Boot' /dev/mock0",
+                "sgdisk --new=3:12584960:264243199 --typecode=3:0FC63DAF-8483-4772-8E79-3D69D8477DE4 '--change-name=3:Linux Filesystem' /dev/mock0",
+            ]
+        );
+
+        assert_eq!(shell_quote(r#"it's "$HOME""#), r#"'it'\''s "$HOME"'"#);
+    }
+
+    #[test]
+    fn test_sgdisk_resize() {
+        const GB: u64 = 1024 * 1024 * 1024;
+        let test_strategies = Parser::new_for_path("tests/use_whole_disk.kdl").unwrap();
+        let def = test_strategies.strategies.first().unwrap();
+
+        let mut disk = MockDisk::new(150 * GB);
+        let partition = disk.add_partition(1024 * 1024, 10 * GB);
+        partition.type_guid = Some("0fc63daf-8483-4772-8e79-3d69d8477de4".into());
+        partition.uuid = Some("3f1b8a3c-5c47-4d8e-9b1a-2c4e6f8a0b1d".into());
+        partition.label = Some("data".into());
+        let device = BlockDevice::mock_device(disk);
+        // Swap in a planner that grows the existing partition rather than wiping
+        let mut plans = plan_on([def], &[&device]);
+        let device_plan = plans[0].device_assignments.values_mut().next().unwrap();
+        device_plan.planner = Planner::new(&device);
+        device_plan.planner.plan_resize_partition(0, 20 * GB).unwrap();
+
+        let commands = plans[0].to_sgdisk_commands().unwrap();
+        assert_eq!(
+            commands[Path::new("/dev/mock0")],
+            vec![concat!(
+                "sgdisk --delete=1 --new=1:2048:41943039 ",
+                "--partition-guid=1:3F1B8A3C-5C47-4D8E-9B1A-2C4E6F8A0B1D ",
+                "--typecode=1:0FC63DAF-8483-4772-8E79-3D69D8477DE4 --change-name=1:data /dev/mock0"
+            )]
+        );
+
+        // Without the original GUID the partition can't be recreated faithfully
+        let mut disk = MockDisk::new(150 * GB);
+        disk.add_partition(1024 * 1024, 10 * GB);
+        let device = BlockDevice::mock_device(disk);
+        let device_plan = plans[0].device_assignments.values_mut().next().unwrap();
+        device_plan.device = &device;
+        device_plan.planner = Planner::new(&device);
+        device_plan.planner.plan_resize_partition(0, 20 * GB).unwrap();
+        assert!(matches!(
+            plans[0].to_sgdisk_commands(),
+            Err(ExportError::UnknownAttributes { partition_id: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_sfdisk_script() {
+        let test_strategies = Parser::new_for_path("tests/use_whole_disk.kdl").unwrap();
+        let def = test_strategies.strategies.first().unwrap();
+
+        let device = mock_device();
+        let plans = plan_on([def], &[&device]);
+        let script = plans[0].to_sfdisk_script("root_disk").unwrap();
+        assert!(script.starts_with("label: gpt\ndevice: /dev/mock0\nunit: sectors\nfirst-lba: 2048\n"));
+
+        // Parse the partition lines back into their fields
+        let partitions = script
+            .lines()
+            .filter_map(|line| line.split_once(" : "))
+            .map(|(node, fields)| {
+                let fields = fields
+                    .split(", ")
+                    .filter_map(|field| field.split_once('='))
+                    .collect::<HashMap<_, _>>();
+                (node, fields)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(partitions.len(), 3);
+        let (node, root) = &partitions[2];
+        assert_eq!(*node, "/dev/mock03");
+        assert_eq!(root["type"], "0FC63DAF-8483-4772-8E79-3D69D8477DE4");
+        assert_eq!(root["name"], r#""Linux Filesystem""#);
+        assert_eq!(root["size"].parse::<u64>().unwrap() * 512, 120 * 1024 * 1024 * 1024);
+
+        assert!(plans[0].to_sfdisk_script("other_disk").is_none());
+
+        // Wiping the disk without planning partitions leaves just the header
+        let test_strategies = Parser::new(
+            "wipe.kdl",
+            r#"
+            strategy name="wipe" summary="Fresh partition table" {
+                find-disk "root_disk"
+                create-partition-table type="gpt" disk="root_disk"
+            }
+            "#,
+        )
+        .unwrap();
+        let plans = plan_on(&test_strategies.strategies[..1], &[&device]);
+        let script = plans[0].to_sfdisk_script("root_disk").unwrap();
+        assert_eq!(script.lines().count(), 6);
+        assert!(!script.contains(" : "));
+    }
+
+    #[test]
+    fn test_filesystem_defaults() {
+        let test_strategies = Parser::new(
+            "defaults.kdl",
+            r#"
+            strategy name="defaults" summary="Role filesystem defaults" {
+                defaults {
+                    filesystem role="root" {
+                        type "ext4"
+                    }
+                    filesystem role="home" {
+                        type "xfs"
+                    }
+                }
+                find-disk "root_disk"
+                create-partition-table type="gpt" disk="root_disk"
+                create-partition disk="root_disk" role="root" id="root" {
+                    constraints {
+                        min (GiB)20
+                        max (GiB)50
+                    }
+                }
+                create-partition disk="root_disk" role="home" id="home" {
+                    constraints {
+                        remaining
+                    }
+                    filesystem {
+                        type "f2fs"
+                    }
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let device = mock_device();
+        let plans = plan_on(&test_strategies.strategies, &[&device]);
+        assert_eq!(plans.len(), 1);
+        let plan = &plans[0];
+
+        // Root picks up the strategy default
+        let root = &plan.role_mounts[&PartitionRole::Root];
+        assert!(matches!(
+            plan.filesystems[root],
+            Filesystem::Standard {
+                filesystem_type: types::StandardFilesystemType::Ext4,
+                ..
+            }
+        ));
+
+        // Home overrides the strategy default
+        let home = &plan.role_mounts[&PartitionRole::Home];
+        assert!(matches!(
+            plan.filesystems[home],
+            Filesystem::Standard {
+                filesystem_type: types::StandardFilesystemType::F2fs,
+                ..
+            }
+        ));
+    }
 }
@@ -2,6 +2,8 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
+use disks::format_size;
+
 #[cfg(feature = "kdl")]
 use crate::{get_kdl_entry, kdl_value_to_storage_size};
 
@@ -23,6 +25,19 @@ pub enum Constraints {
     Invalid,
 }
 
+impl Constraints {
+    /// A human readable description of the constraints, e.g. "at least 100.0GiB"
+    pub fn describe(&self) -> String {
+        match self {
+            Constraints::Exact(size) => format!("exactly {}", format_size(*size)),
+            Constraints::AtLeast(min) => format!("at least {}", format_size(*min)),
+            Constraints::Range { min, max } => format!("between {} and {}", format_size(*min), format_size(*max)),
+            Constraints::Remaining => "all remaining space".to_string(),
+            Constraints::Invalid => "invalid constraints".to_string(),
+        }
+    }
+}
+
 #[cfg(feature = "kdl")]
 impl Constraints {
     pub fn from_kdl_node(node: &kdl::KdlNode) -> Result<Self, crate::Error> {
@@ -32,19 +47,16 @@ impl Constraints {
             .zip(node.iter_children().find(|n| n.name().value() == "max"));
 
         if let Some((min, max)) = range {
-            let min = kdl_value_to_storage_size(get_kdl_entry(min, &0)?)? as u64;
-            let max = kdl_value_to_storage_size(get_kdl_entry(max, &0)?)? as u64;
+            let min = kdl_value_to_storage_size(get_kdl_entry(min, &0)?)?;
+            let max = kdl_value_to_storage_size(get_kdl_entry(max, &0)?)?;
 
-            Ok(Self::Range {
-                min: min as u64,
-                max: max as u64,
-            })
+            Ok(Self::Range { min, max })
         } else if let Some(min) = node.iter_children().find(|n| n.name().value() == "min") {
-            let min = kdl_value_to_storage_size(get_kdl_entry(min, &0)?)? as u64;
-            Ok(Self::AtLeast(min as u64))
+            let min = kdl_value_to_storage_size(get_kdl_entry(min, &0)?)?;
+            Ok(Self::AtLeast(min))
         } else if let Some(exact) = node.iter_children().find(|n| n.name().value() == "exactly") {
-            let exact = kdl_value_to_storage_size(get_kdl_entry(exact, &0)?)? as u64;
-            Ok(Self::Exact(exact as u64))
+            let exact = kdl_value_to_storage_size(get_kdl_entry(exact, &0)?)?;
+            Ok(Self::Exact(exact))
         } else if node.iter_children().any(|n| n.name().value() == "remaining") {
             Ok(Self::Remaining)
         } else {
@@ -56,3 +68,26 @@ impl Constraints {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIB: u64 = 1024 * 1024 * 1024;
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(Constraints::Exact(GIB).describe(), "exactly 1.0GiB");
+        assert_eq!(Constraints::AtLeast(100 * GIB).describe(), "at least 100.0GiB");
+        assert_eq!(
+            Constraints::Range {
+                min: 100 * GIB,
+                max: 500 * GIB
+            }
+            .describe(),
+            "between 100.0GiB and 500.0GiB"
+        );
+        assert_eq!(Constraints::Remaining.describe(), "all remaining space");
+        assert_eq!(Constraints::Invalid.describe(), "invalid constraints");
+    }
+}
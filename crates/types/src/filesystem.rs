@@ -5,6 +5,8 @@
 
 use std::{fmt, str::FromStr};
 
+use thiserror::Error;
+
 #[cfg(feature = "kdl")]
 use crate::{get_kdl_entry, kdl_value_to_integer, kdl_value_to_string};
 
@@ -14,6 +16,7 @@ use super::FromKdlProperty;
 /// The filesystem information for a partition
 /// This is used to format the partition with a filesystem
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Filesystem {
     Fat32 {
         label: Option<String>,
@@ -27,6 +30,7 @@ pub enum Filesystem {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum StandardFilesystemType {
     F2fs,
     Ext4,
@@ -71,18 +75,121 @@ impl FromKdlProperty<'_> for StandardFilesystemType {
     }
 }
 
+/// The rules a filesystem places on its label
+#[derive(Debug, Clone, Copy)]
+pub struct LabelConstraints {
+    /// Maximum length of the label in bytes
+    pub max_len: usize,
+
+    /// Whether lowercase letters are rejected
+    pub uppercase_only: bool,
+
+    /// Whether a character may appear in the label
+    pub allowed_chars: fn(char) -> bool,
+}
+
+/// Why a label was rejected by [`Filesystem::validate_label`]
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum LabelError {
+    #[error("label is {len} bytes, but {filesystem} labels are at most {max_len} bytes")]
+    TooLong {
+        filesystem: String,
+        len: usize,
+        max_len: usize,
+    },
+
+    #[error("{filesystem} labels must be uppercase")]
+    NotUppercase { filesystem: String },
+
+    #[error("{filesystem} labels cannot contain {character:?}")]
+    InvalidCharacter { filesystem: String, character: char },
+}
+
+impl Filesystem {
+    /// The label to format the filesystem with, if any
+    pub fn label(&self) -> Option<&str> {
+        match self {
+            Self::Fat32 { label, .. } | Self::Standard { label, .. } => label.as_deref(),
+        }
+    }
+
+    /// Whether the filesystem can be given a label at all
+    pub fn supports_label(&self) -> bool {
+        self.label_constraints().max_len > 0
+    }
+
+    /// The rules the filesystem places on its label
+    pub fn label_constraints(&self) -> LabelConstraints {
+        match self {
+            // Short names exclude the characters reserved by DOS
+            Self::Fat32 { .. } => LabelConstraints {
+                max_len: 11,
+                uppercase_only: true,
+                allowed_chars: |c| c.is_ascii() && !c.is_ascii_control() && !"\"*+,./:;<=>?[\\]|".contains(c),
+            },
+            Self::Standard { filesystem_type, .. } => LabelConstraints {
+                max_len: match filesystem_type {
+                    StandardFilesystemType::Ext4 | StandardFilesystemType::Swap => 16,
+                    StandardFilesystemType::Xfs => 12,
+                    StandardFilesystemType::F2fs => 512,
+                },
+                uppercase_only: false,
+                allowed_chars: |c| !c.is_control(),
+            },
+        }
+    }
+
+    /// Check a label against the rules of the filesystem
+    pub fn validate_label(&self, label: &str) -> Result<(), LabelError> {
+        let constraints = self.label_constraints();
+        if label.len() > constraints.max_len {
+            return Err(LabelError::TooLong {
+                filesystem: self.to_string(),
+                len: label.len(),
+                max_len: constraints.max_len,
+            });
+        }
+        if let Some(character) = label.chars().find(|c| !(constraints.allowed_chars)(*c)) {
+            return Err(LabelError::InvalidCharacter {
+                filesystem: self.to_string(),
+                character,
+            });
+        }
+        if constraints.uppercase_only && label.chars().any(char::is_lowercase) {
+            return Err(LabelError::NotUppercase {
+                filesystem: self.to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Filesystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fat32 { .. } => f.write_str("fat32"),
+            Self::Standard { filesystem_type, .. } => filesystem_type.fmt(f),
+        }
+    }
+}
+
 #[cfg(feature = "kdl")]
 impl Filesystem {
     pub fn from_kdl_node(node: &kdl::KdlNode) -> Result<Self, crate::Error> {
         let mut fs_type = None;
         let mut label = None;
+        let mut label_span = node.span();
         let mut uuid = None;
         let mut volume_id = None;
 
         for entry in node.iter_children() {
             match entry.name().value() {
                 "type" => fs_type = Some(kdl_value_to_string(get_kdl_entry(entry, &0)?)?),
-                "label" => label = Some(kdl_value_to_string(get_kdl_entry(entry, &0)?)?),
+                "label" => {
+                    let value = get_kdl_entry(entry, &0)?;
+                    label_span = value.span();
+                    label = Some(kdl_value_to_string(value)?);
+                }
                 "uuid" => uuid = Some(kdl_value_to_string(get_kdl_entry(entry, &0)?)?),
                 "volume_id" => volume_id = Some(kdl_value_to_integer(get_kdl_entry(entry, &0)?)? as u32),
                 _ => {
@@ -100,7 +207,7 @@ impl Filesystem {
             name: "type".into(),
         })?;
 
-        match fs_type.as_str() {
+        let filesystem = match fs_type.as_str() {
             "fat32" => {
                 if uuid.is_some() {
                     return Err(crate::InvalidArguments {
@@ -109,7 +216,7 @@ impl Filesystem {
                     }
                     .into());
                 }
-                Ok(Filesystem::Fat32 { label, volume_id })
+                Filesystem::Fat32 { label, volume_id }
             }
             fs_type => {
                 if volume_id.is_some() {
@@ -119,12 +226,84 @@ impl Filesystem {
                     }
                     .into());
                 }
-                Ok(Filesystem::Standard {
+                Filesystem::Standard {
                     filesystem_type: fs_type.parse()?,
                     label,
                     uuid,
-                })
+                }
             }
+        };
+
+        if let Some(label) = filesystem.label() {
+            filesystem.validate_label(label).map_err(|e| crate::InvalidArguments {
+                at: label_span,
+                advice: Some(e.to_string()),
+            })?;
         }
+
+        Ok(filesystem)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn standard(filesystem_type: StandardFilesystemType) -> Filesystem {
+        Filesystem::Standard {
+            filesystem_type,
+            label: None,
+            uuid: None,
+        }
+    }
+
+    #[test]
+    fn test_validate_label() {
+        let fat = Filesystem::Fat32 {
+            label: None,
+            volume_id: None,
+        };
+        assert!(fat.supports_label());
+        assert!(fat.validate_label("ESP").is_ok());
+        assert_eq!(
+            fat.validate_label("esp"),
+            Err(LabelError::NotUppercase {
+                filesystem: "fat32".into()
+            })
+        );
+        assert_eq!(
+            fat.validate_label("EFI*SYSTEM"),
+            Err(LabelError::InvalidCharacter {
+                filesystem: "fat32".into(),
+                character: '*'
+            })
+        );
+        assert!(matches!(
+            fat.validate_label("SYSTEMPARTITION"),
+            Err(LabelError::TooLong {
+                len: 15,
+                max_len: 11,
+                ..
+            })
+        ));
+
+        let ext4 = standard(StandardFilesystemType::Ext4);
+        assert!(ext4.validate_label("root-filesystem").is_ok());
+        assert!(matches!(
+            ext4.validate_label("a-very-long-root-label"),
+            Err(LabelError::TooLong { max_len: 16, .. })
+        ));
+
+        let xfs = standard(StandardFilesystemType::Xfs);
+        assert!(xfs.validate_label("ROOT").is_ok());
+        assert!(xfs.validate_label("root-filesystem").is_err());
+
+        let f2fs = standard(StandardFilesystemType::F2fs);
+        assert!(f2fs.validate_label("data").is_ok());
+        assert!(f2fs.validate_label("tab\there").is_err());
+
+        let swap = standard(StandardFilesystemType::Swap);
+        assert!(swap.validate_label("swap").is_ok());
+        assert!(swap.validate_label(&"s".repeat(17)).is_err());
     }
 }
@@ -5,6 +5,9 @@
 
 use std::{fmt, str::FromStr};
 
+use gpt::partition_types;
+use uuid::Uuid;
+
 #[cfg(feature = "kdl")]
 use crate::kdl_value_to_string;
 
@@ -13,6 +16,7 @@ use super::FromKdlProperty;
 
 /// The role assigned to a partition
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PartitionRole {
     /// Boot partition (usually ESP)
     Boot,
@@ -28,9 +32,34 @@ pub enum PartitionRole {
 
     /// Swap partition
     Swap,
+
+    /// Server data mounted at /srv
+    ///
+    /// A plan may hold several of these, but only the first is mounted; the
+    /// others are formatted and left unmounted for the administrator to place.
+    Srv,
 }
 
 impl PartitionRole {
+    /// Infer the role of a partition from its GPT type GUID, where unambiguous
+    pub fn from_type_guid(guid: &Uuid) -> Option<Self> {
+        match *guid {
+            g if g == partition_types::EFI.guid => Some(Self::Boot),
+            g if g == partition_types::FREEDESK_BOOT.guid => Some(Self::ExtendedBoot),
+            g if g == partition_types::LINUX_HOME.guid => Some(Self::Home),
+            g if g == partition_types::LINUX_SWAP.guid => Some(Self::Swap),
+            g if g == partition_types::LINUX_SRV.guid => Some(Self::Srv),
+            g if g == partition_types::LINUX_ROOT_X64.guid
+                || g == partition_types::LINUX_ROOT_X86.guid
+                || g == partition_types::LINUX_ROOT_ARM_64.guid
+                || g == partition_types::LINUX_ROOT_ARM_32.guid =>
+            {
+                Some(Self::Root)
+            }
+            _ => None,
+        }
+    }
+
     pub fn as_path(&self) -> &'static str {
         match self {
             Self::Boot => "/efi",
@@ -38,8 +67,16 @@ impl PartitionRole {
             Self::Root => "/",
             Self::Home => "/home",
             Self::Swap => "swap",
+            Self::Srv => "/srv",
         }
     }
+
+    /// Whether at most one partition may take this role within a plan
+    ///
+    /// Server data may be spread over several partitions, so it is not unique.
+    pub fn is_unique(&self) -> bool {
+        !matches!(self, Self::Srv)
+    }
 }
 
 impl fmt::Display for PartitionRole {
@@ -50,6 +87,7 @@ impl fmt::Display for PartitionRole {
             Self::Root => f.write_str("root"),
             Self::Home => f.write_str("home"),
             Self::Swap => f.write_str("swap"),
+            Self::Srv => f.write_str("srv"),
         }
     }
 }
@@ -65,6 +103,7 @@ impl FromStr for PartitionRole {
             "root" => Ok(Self::Root),
             "home" => Ok(Self::Home),
             "swap" => Ok(Self::Swap),
+            "srv" => Ok(Self::Srv),
             _ => Err(crate::Error::UnknownVariant),
         }
     }
@@ -76,7 +115,7 @@ impl FromKdlProperty<'_> for PartitionRole {
         let value = kdl_value_to_string(entry)?;
         let v = value.parse().map_err(|_| crate::UnsupportedValue {
             at: entry.span(),
-            advice: Some("'boot', 'extended-boot', 'root', 'home' and 'swap' are supported".into()),
+            advice: Some("'boot', 'extended-boot', 'root', 'home', 'swap' and 'srv' are supported".into()),
         })?;
         Ok(v)
     }
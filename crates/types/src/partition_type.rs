@@ -64,6 +64,11 @@ pub enum PartitionTypeGuid {
     ExtendedBootLoader,
     LinuxSwap,
     LinuxFilesystem,
+    LinuxServerData,
+    LinuxRootX86_64,
+    LinuxRootX86,
+    LinuxRootArm64,
+    LinuxRootArm,
 }
 
 impl fmt::Display for PartitionTypeGuid {
@@ -73,6 +78,11 @@ impl fmt::Display for PartitionTypeGuid {
             Self::ExtendedBootLoader => f.write_str("Linux Extended Boot"),
             Self::LinuxFilesystem => f.write_str("Linux Filesystem"),
             Self::LinuxSwap => f.write_str("Linux Swap"),
+            Self::LinuxServerData => f.write_str("Linux Server Data"),
+            Self::LinuxRootX86_64 => f.write_str("Linux Root (x86-64)"),
+            Self::LinuxRootX86 => f.write_str("Linux Root (x86)"),
+            Self::LinuxRootArm64 => f.write_str("Linux Root (ARM64)"),
+            Self::LinuxRootArm => f.write_str("Linux Root (ARM)"),
         }
     }
 }
@@ -86,12 +96,72 @@ impl FromStr for PartitionTypeGuid {
             "linux-extended-boot" => Ok(Self::ExtendedBootLoader),
             "linux-swap" => Ok(Self::LinuxSwap),
             "linux-fs" => Ok(Self::LinuxFilesystem),
+            "linux-srv" => Ok(Self::LinuxServerData),
+            "linux-root-x86-64" => Ok(Self::LinuxRootX86_64),
+            "linux-root-x86" => Ok(Self::LinuxRootX86),
+            "linux-root-arm64" => Ok(Self::LinuxRootArm64),
+            "linux-root-arm" => Ok(Self::LinuxRootArm),
             _ => Err(crate::Error::UnknownVariant),
         }
     }
 }
 
+/// Every partition type GUID known to this crate, with its display name
+static KNOWN_TYPES: &[(PartitionTypeGuid, Uuid, &str)] = &[
+    (
+        PartitionTypeGuid::EfiSystemPartition,
+        gpt::partition_types::EFI.guid,
+        "EFI System Partition",
+    ),
+    (
+        PartitionTypeGuid::ExtendedBootLoader,
+        gpt::partition_types::FREEDESK_BOOT.guid,
+        "Linux Extended Boot",
+    ),
+    (
+        PartitionTypeGuid::LinuxSwap,
+        gpt::partition_types::LINUX_SWAP.guid,
+        "Linux Swap",
+    ),
+    (
+        PartitionTypeGuid::LinuxFilesystem,
+        gpt::partition_types::LINUX_FS.guid,
+        "Linux Filesystem",
+    ),
+    (
+        PartitionTypeGuid::LinuxServerData,
+        gpt::partition_types::LINUX_SRV.guid,
+        "Linux Server Data",
+    ),
+    (
+        PartitionTypeGuid::LinuxRootX86_64,
+        gpt::partition_types::LINUX_ROOT_X64.guid,
+        "Linux Root (x86-64)",
+    ),
+    (
+        PartitionTypeGuid::LinuxRootX86,
+        gpt::partition_types::LINUX_ROOT_X86.guid,
+        "Linux Root (x86)",
+    ),
+    (
+        PartitionTypeGuid::LinuxRootArm64,
+        gpt::partition_types::LINUX_ROOT_ARM_64.guid,
+        "Linux Root (ARM64)",
+    ),
+    (
+        PartitionTypeGuid::LinuxRootArm,
+        gpt::partition_types::LINUX_ROOT_ARM_32.guid,
+        "Linux Root (ARM)",
+    ),
+];
+
 impl PartitionTypeGuid {
+    /// Returns every supported partition type along with its GUID and display name,
+    /// suitable for populating a type selector in a frontend.
+    pub fn known_types() -> &'static [(PartitionTypeGuid, Uuid, &'static str)] {
+        KNOWN_TYPES
+    }
+
     /// Returns the GUID value for this partition type
     pub fn as_guid(&self) -> GptPartitionType {
         match self {
@@ -99,6 +169,11 @@ impl PartitionTypeGuid {
             Self::ExtendedBootLoader => gpt::partition_types::FREEDESK_BOOT,
             Self::LinuxSwap => gpt::partition_types::LINUX_SWAP,
             Self::LinuxFilesystem => gpt::partition_types::LINUX_FS,
+            Self::LinuxServerData => gpt::partition_types::LINUX_SRV,
+            Self::LinuxRootX86_64 => gpt::partition_types::LINUX_ROOT_X64,
+            Self::LinuxRootX86 => gpt::partition_types::LINUX_ROOT_X86,
+            Self::LinuxRootArm64 => gpt::partition_types::LINUX_ROOT_ARM_64,
+            Self::LinuxRootArm => gpt::partition_types::LINUX_ROOT_ARM_32,
         }
     }
 
@@ -108,9 +183,58 @@ impl PartitionTypeGuid {
         let v = value.parse().map_err(|_| crate::UnsupportedValue {
             at: node.span(),
             advice: Some(
-                "'efi-system-partition', 'linux-swap' 'linux-extended-boot' and 'linux-fs' are supported".into(),
+                "'efi-system-partition', 'linux-swap' 'linux-extended-boot', 'linux-fs', 'linux-srv', \
+                 'linux-root-x86-64', 'linux-root-x86', 'linux-root-arm64' and 'linux-root-arm' are supported"
+                    .into(),
             ),
         })?;
         Ok(v)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_known_types() {
+        let known = PartitionTypeGuid::known_types();
+
+        for ty in [
+            PartitionTypeGuid::EfiSystemPartition,
+            PartitionTypeGuid::LinuxFilesystem,
+            PartitionTypeGuid::LinuxSwap,
+            PartitionTypeGuid::LinuxRootX86_64,
+            PartitionTypeGuid::LinuxRootArm64,
+        ] {
+            let (_, guid, name) = known.iter().find(|(t, _, _)| *t == ty).unwrap();
+            assert_eq!(*guid, ty.as_guid().guid);
+            assert_eq!(*name, ty.to_string());
+        }
+
+        // Every root type maps back to the root role
+        let roots = known
+            .iter()
+            .filter(|(_, guid, _)| crate::PartitionRole::from_type_guid(guid) == Some(crate::PartitionRole::Root))
+            .map(|(ty, _, _)| ty)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            roots,
+            [
+                &PartitionTypeGuid::LinuxRootX86_64,
+                &PartitionTypeGuid::LinuxRootX86,
+                &PartitionTypeGuid::LinuxRootArm64,
+                &PartitionTypeGuid::LinuxRootArm,
+            ]
+        );
+        assert_eq!(
+            "linux-root-arm64".parse::<PartitionTypeGuid>().unwrap(),
+            PartitionTypeGuid::LinuxRootArm64
+        );
+
+        let unique = known.iter().map(|(_, guid, _)| guid).collect::<HashSet<_>>();
+        assert_eq!(unique.len(), known.len());
+    }
+}